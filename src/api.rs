@@ -1,14 +1,15 @@
-use std::{net::SocketAddr, sync::Arc};
+use std::{net::SocketAddr, sync::Arc, convert::Infallible};
 
-use axum::{Router, extract::{Path, State, WebSocketUpgrade}, response::IntoResponse, Json, routing::{get, post}};
+use axum::{Router, extract::{Path, State, WebSocketUpgrade, Query}, response::{IntoResponse, Response, sse::{Sse, Event, KeepAlive}}, Json, routing::{get, post}, middleware::{self, Next}, http::{Request, HeaderMap, header}, body::{self, HttpBody}};
+use futures::{Stream, StreamExt};
 use reqwest::StatusCode;
-use serde::{Deserialize};
+use serde::{Deserialize, Serialize};
 use tokio::{sync::{RwLock, broadcast::Sender}};
 use tower::ServiceBuilder;
 use tower_http::compression::CompressionLayer;
 use tracing::log;
 
-use crate::{SafeApiSeasonService, api_game_details::{ApiGameDetailsService, ApiGameDetails}, api_season_service::ApiSeasonService, api_teams_service::{ApiTeamsService, ApiTeam}, standing_service::StandingService, models::{League, Season}, vote_service::{Vote, SafeVoteService}, api_ws::{ApiWs, WsMsg}, user_service::{UserService}, models2::legacy::{game_details::LegacyGameDetails, player_stats::LegacyPlayerStats, season_games::LegacyGame}, api_player_stats_service::{ApiPlayerStatsService, TeamSeasonKey}, playoff_service::PlayoffService};
+use crate::{SafeApiSeasonService, api_game_details::{ApiGameDetailsService, ApiGameDetails}, api_season_service::ApiSeasonService, api_teams_service::{ApiTeamsService, ApiTeam}, standing_service::StandingService, models::{League, Season}, vote_service::{Vote, SafeVoteService}, api_ws::{ApiWs, WsMsg}, user_service::{UserService}, models2::legacy::{game_details::LegacyGameDetails, player_stats::LegacyPlayerStats, season_games::LegacyGame}, api_player_stats_service::{ApiPlayerStatsService, TeamSeasonKey}, playoff_service::PlayoffService, player_service::{PlayerService, StatKey}, schedule_service::ScheduleService, event_service::EventService, stats_service::StatsService, game_service::GameService, fieldset::Fieldset, rest_client, json_format, team_service::TeamService, publish_service::PublishService, event_proto, sse_gzip, metrics::Metrics, service_config::ServiceConfig, db::Db, CONFIG};
 
 #[derive(Clone)]
 pub struct ApiState {
@@ -16,18 +17,22 @@ pub struct ApiState {
     pub season_service: SafeApiSeasonService,
     pub vote_service: SafeVoteService,
     pub broadcast_sender: Sender<WsMsg>,
+    pub service_config: ServiceConfig,
+    pub publish_service: PublishService,
 
     pub nr_ws: Arc<RwLock<i16>>,
 }
 
 pub struct Api;
 impl Api {
-    pub async fn serve(port: u16, season_service: SafeApiSeasonService, vote_service: SafeVoteService, broadcast_sender: Sender<WsMsg>) {
+    pub async fn serve(port: u16, season_service: SafeApiSeasonService, vote_service: SafeVoteService, broadcast_sender: Sender<WsMsg>, service_config: ServiceConfig, publish_service: PublishService) {
         let state = ApiState {
-            game_details_service: ApiGameDetailsService::new(season_service.clone()),
+            game_details_service: ApiGameDetailsService::new(season_service.clone(), service_config.clone()),
             season_service,
             vote_service,
             broadcast_sender,
+            service_config,
+            publish_service,
             nr_ws: Arc::new(RwLock::new(0)),
         };
         let app = Router::new()
@@ -43,11 +48,33 @@ impl Api {
 
             .route("/v2/games/:season", get(Api::get_games))
             .route("/v2/game/:game_uuid", get(Api::get_game_details))
+            .route("/v2/game/:game_uuid/score", get(Api::get_game_score))
+            .route("/v2/game/:game_uuid/export", get(Api::get_game_export))
+            .route("/v2/game/:game_uuid/events", get(Api::get_game_events))
+            .route("/v2/game/:game_uuid/events/unacked", get(Api::get_unacked_events))
+            .route("/v2/game/:game_uuid/win-probability", get(Api::get_win_probability))
+            .route("/v2/game/:game_uuid/projections", get(Api::get_projections))
+            .route("/v2/game/:game_uuid/shots", get(Api::get_shots))
+            .route("/v2/game/:game_uuid/strength", get(Api::get_strength))
+            .route("/v2/game/:game_uuid/counts", get(Api::get_event_counts))
+            .route("/v2/game/:game_uuid/goal-gaps", get(Api::get_goal_gaps))
+            .route("/v2/game/:game_uuid/player/:player_id/events", get(Api::get_player_events))
+            .route("/v2/game/:game_uuid/period/:period/events", get(Api::get_period_events))
+            .route("/v2/game/:game_uuid/stats/:league", get(Api::get_game_stat_captions))
             .route("/v2/teams", get(Api::get_teams))
             .route("/v2/standings/:season", get(Api::get_leagues))
             .route("/v2/playoffs/:season", get(Api::get_playoffs))
             .route("/v2/player/:player_id", get(Api::get_player))
+            .route("/v2/player/:player_id/games/:season", get(Api::get_player_game_log))
             .route("/v2/players/:season/:team", get(Api::get_players))
+            .route("/v2/leaderboard/:league/:season/:stat", get(Api::get_leaderboard))
+            .route("/v2/team/:team_code", get(Api::get_team_meta))
+            .route("/v2/team/:league/:season/:team_code/stats", get(Api::get_team_season_stats))
+            .route("/v2/schedule/:season/:league/:team", get(Api::get_schedule))
+            .route("/v2/schedule/:date", get(Api::get_games_on))
+            .route("/v2/scoreboard", get(Api::get_scoreboard))
+            .route("/v2/live/goals", get(Api::get_live_goals))
+            .route("/v2/live/score", get(Api::get_live_score))
     
             .route("/v2/live-activity/start", post(Api::start_live_activity))
             .route("/v2/live-activity/end", post(Api::end_live_activity))
@@ -55,6 +82,15 @@ impl Api {
 
             .route("/vote", post(Api::vote))
 
+            .nest("/admin", Router::new()
+                .route("/rest-error", get(Api::get_rest_error))
+                .route("/slow-calls", get(Api::get_slow_calls))
+                .route("/metrics", get(Api::get_metrics))
+                .route("/db/:name/key/:safe_key", get(Api::get_original_key))
+                .route("/game/:game_uuid/publish", post(Api::set_publish_enabled))
+                .route("/game/:game_uuid/events/dry-run", get(Api::get_events_dry_run))
+                .route_layer(middleware::from_fn(Api::require_admin))
+            )
 
             .route("/ws", get(Api::ws_handler))
     
@@ -62,6 +98,7 @@ impl Api {
             .with_state(state)
             .layer(ServiceBuilder::new()
                 .layer(CompressionLayer::new()) // adds 50ms
+                .layer(middleware::from_fn(Api::pretty_json))
             );
         let addr = SocketAddr::from(([0, 0, 0, 0], port));
         log::info!("[API] Listening on {}", addr);
@@ -70,6 +107,32 @@ impl Api {
             .await;
     }
 
+    // ?pretty re-formats json responses for reading them by hand
+    async fn pretty_json<B>(req: Request<B>, next: Next<B>) -> Response {
+        let pretty = req.uri().query().is_some_and(|e| e.split('&').any(|e| e == "pretty" || e == "pretty=true"));
+        let rsp = next.run(req).await;
+        let is_json = rsp.headers().get(header::CONTENT_TYPE).is_some_and(|e| e.as_bytes().starts_with(b"application/json"));
+        if !pretty || !is_json {
+            return rsp;
+        }
+        let (mut parts, mut body) = rsp.into_parts();
+        let mut data = vec![];
+        while let Some(Ok(chunk)) = body.data().await {
+            data.extend_from_slice(&chunk);
+        }
+        parts.headers.remove(header::CONTENT_LENGTH);
+        let data = json_format::prettify(&data).map(|e| e.into_bytes()).unwrap_or(data);
+        Response::from_parts(parts, body::boxed(body::Full::from(data)))
+    }
+
+    // Admin routes need the configured admin_token as a bearer token, and are off without one
+    async fn require_admin<B>(req: Request<B>, next: Next<B>) -> Response {
+        if !is_admin(req.headers(), CONFIG.admin_token.as_deref()) {
+            return StatusCode::UNAUTHORIZED.into_response();
+        }
+        next.run(req).await
+    }
+
     async fn root() -> &'static str {
         "Puck puck puck"
     }
@@ -99,11 +162,60 @@ impl Api {
         Json(state.game_details_service.read(&game_uuid).await)
     }
 
-    
+    async fn get_game_export(Path(game_uuid): Path<String>, State(state): State<ApiState>) -> impl IntoResponse {
+        match state.season_service.read().await.read_game(&game_uuid) {
+            Some(game) => (StatusCode::OK, Json(GameService::export(&game)).into_response()),
+            None => (StatusCode::NOT_FOUND, "404".to_string().into_response()),
+        }
+    }
+
+    async fn get_rest_error(Query(req): Query<RestErrorQuery>) -> impl IntoResponse {
+        Json(rest_client::last_error(&req.url).map(|e| e.to_string()))
+    }
+
+    async fn get_slow_calls() -> impl IntoResponse {
+        Json(rest_client::slow_calls())
+    }
+
+    async fn get_metrics() -> impl IntoResponse {
+        Json(Metrics::latencies())
+    }
+
+    // The key a hashed file name in the db folder was written with
+    async fn get_original_key(Path((name, safe_key)): Path<(String, String)>) -> impl IntoResponse {
+        if name.contains("..") || safe_key.contains("..") {
+            return (StatusCode::BAD_REQUEST, "400".to_string()).into_response();
+        }
+        match Db::<String, serde_json::Value>::new(&name).original_key(&safe_key) {
+            Some(key) => (StatusCode::OK, Json(key)).into_response(),
+            None => (StatusCode::NOT_FOUND, "404".to_string()).into_response(),
+        }
+    }
+
+    // e.g. a test game, or one with bad data. Ingestion continues, nothing is published or notified
+    async fn set_publish_enabled(Path(game_uuid): Path<String>, Query(req): Query<PublishQuery>) -> impl IntoResponse {
+        PublishService::set_publish_enabled(&game_uuid, req.enabled);
+        StatusCode::OK
+    }
+
+    async fn get_events_dry_run(Path(game_uuid): Path<String>) -> impl IntoResponse {
+        match EventService::update_dry_run(&game_uuid).await {
+            Ok(diff) => Json(diff).into_response(),
+            Err(e) => (StatusCode::BAD_GATEWAY, e.to_string()).into_response(),
+        }
+    }
+
     async fn get_teams() -> impl IntoResponse {
         ApiTeamsService::read_raw()
     }
 
+    async fn get_team_meta(Path(team_code): Path<String>) -> impl IntoResponse {
+        match TeamService::get(&team_code) {
+            Some(meta) => (StatusCode::OK, Json(meta).into_response()),
+            None => (StatusCode::NOT_FOUND, "404".to_string().into_response()),
+        }
+    }
+
     async fn get_legacy_teams() -> impl IntoResponse {
         Json(ApiTeamsService::read().into_iter().filter(|e| e.league == Some(League::SHL)).collect::<Vec<ApiTeam>>())
     }
@@ -142,20 +254,143 @@ impl Api {
         Json(data)
     }
 
-    async fn get_players(Path((season, team)): Path<(String, String)>) -> impl IntoResponse {
+    async fn get_players(Path((season, team)): Path<(String, String)>, Query(req): Query<FieldsQuery>) -> impl IntoResponse {
         if let Ok(e) = season.parse() {
             let db = ApiPlayerStatsService::get_team_player_db();
-            (StatusCode::OK, db.read_raw(&TeamSeasonKey(e, team)))
+            match req.fieldset() {
+                Some(fields) => (StatusCode::OK, Json(fields.select(&db.read(&TeamSeasonKey(e, team)).unwrap_or_default())).into_response()),
+                None => (StatusCode::OK, db.read_raw(&TeamSeasonKey(e, team)).into_response()),
+            }
         } else {
-            (StatusCode::NOT_FOUND, "404".to_string())
+            (StatusCode::NOT_FOUND, "404".to_string().into_response())
         }
     } 
 
+    async fn get_leaderboard(Path((league, season, stat)): Path<(League, String, StatKey)>, Query(req): Query<LeaderboardQuery>) -> impl IntoResponse {
+        if let Ok(season) = season.parse() {
+            (StatusCode::OK, Json(PlayerService::leaderboard(&league, &season, stat, req.limit.unwrap_or(10))).into_response())
+        } else {
+            (StatusCode::NOT_FOUND, "404".to_string().into_response())
+        }
+    }
+
+    async fn get_team_season_stats(Path((league, season, team_code)): Path<(League, String, String)>) -> impl IntoResponse {
+        if let Ok(season) = season.parse() {
+            (StatusCode::OK, Json(StatsService::team_season(&league, &season, &team_code)).into_response())
+        } else {
+            (StatusCode::NOT_FOUND, "404".to_string().into_response())
+        }
+    }
+
     async fn get_player(Path(player_id): Path<i32>) -> impl IntoResponse {
         let db = ApiPlayerStatsService::get_player_career_db();
         db.read_raw(&player_id)
     } 
 
+    async fn get_game_score(Path(game_uuid): Path<String>) -> impl IntoResponse {
+        match GameService::score(&game_uuid) {
+            Some(score) => (StatusCode::OK, Json(score).into_response()),
+            None => (StatusCode::NOT_FOUND, "404".to_string().into_response()),
+        }
+    }
+
+    async fn get_game_events(Path(game_uuid): Path<String>, Query(req): Query<GameEventsQuery>, headers: HeaderMap, State(state): State<ApiState>) -> impl IntoResponse {
+        let mut events = EventService::update_swr(&game_uuid, state.service_config.events_throttle(), &state.publish_service);
+        if let Some(min_importance) = req.min_importance {
+            events.retain(|e| e.importance() >= min_importance);
+        }
+        // Json unless protobuf is asked for. Protobuf always has all fields
+        let protobuf = headers.get(header::ACCEPT)
+            .and_then(|e| e.to_str().ok())
+            .is_some_and(|e| e.contains(event_proto::PROTOBUF_CONTENT_TYPE));
+        if protobuf {
+            return ([(header::CONTENT_TYPE, event_proto::PROTOBUF_CONTENT_TYPE)], event_proto::encode_events(&events)).into_response();
+        }
+        match req.fieldset() {
+            Some(fields) => Json(fields.select(&events)).into_response(),
+            None => Json(events).into_response(),
+        }
+    }
+
+    async fn get_unacked_events(Path(game_uuid): Path<String>, Query(req): Query<UnackedQuery>) -> impl IntoResponse {
+        Json(EventService::unacked(&game_uuid, req.last_acked.unwrap_or_default()))
+    }
+
+    async fn get_win_probability(Path(game_uuid): Path<String>) -> impl IntoResponse {
+        Json(GameService::win_probability(&game_uuid))
+    }
+
+    async fn get_event_counts(Path(game_uuid): Path<String>) -> impl IntoResponse {
+        Json(EventService::event_counts(&game_uuid))
+    }
+
+    async fn get_goal_gaps(Path(game_uuid): Path<String>, Query(req): Query<GoalGapsQuery>) -> impl IntoResponse {
+        Json(EventService::goal_gaps(&game_uuid, req.same_team.unwrap_or_default()))
+    }
+
+    async fn get_player_events(Path((game_uuid, player_id)): Path<(String, i32)>) -> impl IntoResponse {
+        Json(EventService::player_events(&game_uuid, player_id))
+    }
+
+    // period is the feed number, 4 and up for overtimes and 99 for the shootout
+    async fn get_period_events(Path((game_uuid, period)): Path<(String, i16)>) -> impl IntoResponse {
+        Json(EventService::period_events(&game_uuid, period.into()))
+    }
+
+    async fn get_strength(Path(game_uuid): Path<String>) -> impl IntoResponse {
+        match GameService::current_strength(&game_uuid) {
+            Some(strength) => (StatusCode::OK, Json(strength).into_response()),
+            None => (StatusCode::NOT_FOUND, "404".to_string().into_response()),
+        }
+    }
+
+    async fn get_scoreboard(Query(req): Query<ScoreboardQuery>, State(state): State<ApiState>) -> impl IntoResponse {
+        let game_uuids: Vec<String> = req.games.split(',').map(|e| e.to_string()).collect();
+        Json(GameService::scoreboard_many(&game_uuids, &state.service_config))
+    }
+
+    async fn get_shots(Path(game_uuid): Path<String>) -> impl IntoResponse {
+        match GameService::shots(&game_uuid) {
+            Some(shots) => (StatusCode::OK, Json(shots).into_response()),
+            None => (StatusCode::NOT_FOUND, "404".to_string().into_response()),
+        }
+    }
+
+    async fn get_projections(Path(game_uuid): Path<String>) -> impl IntoResponse {
+        match GameService::projections(&game_uuid) {
+            Some(projections) => (StatusCode::OK, Json(projections).into_response()),
+            None => (StatusCode::NOT_FOUND, "404".to_string().into_response()),
+        }
+    }
+
+    async fn get_game_stat_captions(Path((game_uuid, league)): Path<(String, League)>) -> impl IntoResponse {
+        Json(StatsService::all_captions(&league, &game_uuid))
+    }
+
+    async fn get_player_game_log(Path((player_id, season)): Path<(i32, String)>) -> impl IntoResponse {
+        if let Ok(season) = season.parse() {
+            (StatusCode::OK, Json(PlayerService::game_log(player_id, &season)).into_response())
+        } else {
+            (StatusCode::NOT_FOUND, "404".to_string().into_response())
+        }
+    }
+
+    async fn get_schedule(Path((season, league, team)): Path<(String, League, String)>, State(state): State<ApiState>) -> impl IntoResponse {
+        if let Ok(season) = season.parse() {
+            (StatusCode::OK, Json(ScheduleService::team_games(&league, &season, &team, &state.service_config).await).into_response())
+        } else {
+            (StatusCode::NOT_FOUND, "404".to_string().into_response())
+        }
+    }
+
+    async fn get_games_on(Path(date): Path<String>, State(state): State<ApiState>) -> impl IntoResponse {
+        if let Ok(date) = date.parse() {
+            (StatusCode::OK, Json(ScheduleService::games_on(&date, &state.service_config).await).into_response())
+        } else {
+            (StatusCode::NOT_FOUND, "404".to_string().into_response())
+        }
+    }
+
     async fn get_playoffs(Path(season): Path<String>) -> impl IntoResponse {
         if let Ok(e) = season.parse() {
             (StatusCode::OK, PlayoffService::get_db().read_raw(&e).into_response())
@@ -201,14 +436,102 @@ impl Api {
         (StatusCode::OK, "success".to_string())
     }
 
+    async fn get_live_goals(Query(req): Query<LiveGoalsQuery>, State(state): State<ApiState>, headers: HeaderMap) -> Response {
+        let game_uuids = req.games.split(',').map(|e| e.to_string()).collect();
+        Api::sse(EventService::live_goals(state.broadcast_sender.subscribe(), game_uuids), &headers)
+    }
+
+    async fn get_live_score(Query(req): Query<LiveGoalsQuery>, State(state): State<ApiState>, headers: HeaderMap) -> Response {
+        let game_uuids = req.games.split(',').map(|e| e.to_string()).collect();
+        Api::sse(EventService::score_changes(state.broadcast_sender.subscribe(), game_uuids), &headers)
+    }
+
+    // Gzipped when the client accepts it
+    fn sse<T: Serialize + Send + 'static>(stream: impl Stream<Item = T> + Send + 'static, headers: &HeaderMap) -> Response {
+        if sse_gzip::accepts_gzip(headers) {
+            return sse_gzip::gzip_response(stream);
+        }
+        let stream = stream.map(|e| Ok::<Event, Infallible>(Event::default().json_data(e).unwrap_or_default()));
+        Sse::new(stream).keep_alive(KeepAlive::default()).into_response()
+    }
+
     async fn ws_handler(
         ws: WebSocketUpgrade,
+        Query(req): Query<WsQuery>,
         State(state): State<ApiState>) -> impl IntoResponse {
-        ws.on_upgrade(|socket| ApiWs::handle(socket, state))
+        ws.on_upgrade(move |socket| ApiWs::handle(socket, state, req.deltas))
     } 
 }
 
 
+#[derive(Deserialize)]
+struct WsQuery {
+    // revisions of events already sent on the connection come as event_delta
+    #[serde(default)]
+    deltas: bool,
+}
+
+#[derive(Deserialize)]
+struct LiveGoalsQuery {
+    games: String,
+}
+
+// Comma separated game uuids
+#[derive(Deserialize)]
+struct ScoreboardQuery {
+    games: String,
+}
+
+#[derive(Deserialize)]
+struct LeaderboardQuery {
+    limit: Option<usize>,
+}
+
+// ?fields=g,a to only get some fields, see Fieldset
+#[derive(Deserialize)]
+struct FieldsQuery {
+    fields: Option<String>,
+}
+
+impl FieldsQuery {
+    fn fieldset(&self) -> Option<Fieldset> {
+        self.fields.as_deref().and_then(Fieldset::parse)
+    }
+}
+
+#[derive(Deserialize)]
+struct GameEventsQuery {
+    fields: Option<String>,
+    // see ApiGameEvent::importance
+    min_importance: Option<u8>,
+}
+
+impl GameEventsQuery {
+    fn fieldset(&self) -> Option<Fieldset> {
+        self.fields.as_deref().and_then(Fieldset::parse)
+    }
+}
+
+#[derive(Deserialize)]
+struct PublishQuery {
+    enabled: bool,
+}
+
+#[derive(Deserialize)]
+struct RestErrorQuery {
+    url: String,
+}
+
+#[derive(Deserialize)]
+struct GoalGapsQuery {
+    same_team: Option<bool>,
+}
+
+#[derive(Deserialize)]
+struct UnackedQuery {
+    last_acked: Option<i32>,
+}
+
 #[derive(Deserialize)]
 struct VoteBody {
     game_uuid: String,
@@ -237,4 +560,36 @@ pub struct StartLiveActivity {
 pub struct EndLiveActivity {
     pub user_id: String,
     pub game_uuid: String,
-}
\ No newline at end of file
+}
+
+fn is_admin(headers: &HeaderMap, admin_token: Option<&str>) -> bool {
+    let Some(admin_token) = admin_token.filter(|e| !e.is_empty()) else {
+        return false;
+    };
+    let token = headers.get(header::AUTHORIZATION)
+        .and_then(|e| e.to_str().ok())
+        .and_then(|e| e.strip_prefix("Bearer "))
+        .unwrap_or_default();
+    // same time for every mismatch, so the token can't be guessed a byte at a time
+    token.len() == admin_token.len() && token.bytes().zip(admin_token.bytes()).fold(0, |acc, (a, b)| acc | (a ^ b)) == 0
+}
+
+#[cfg(test)]
+mod tests {
+    use axum::http::HeaderMap;
+
+    use super::is_admin;
+
+    #[test]
+    fn admin_needs_configured_token() {
+        let mut headers = HeaderMap::new();
+        assert!(!is_admin(&headers, Some("secret")));
+        headers.insert("authorization", "Bearer secret".parse().unwrap());
+        assert!(is_admin(&headers, Some("secret")));
+        assert!(!is_admin(&headers, Some("secret2")));
+        assert!(!is_admin(&headers, None));
+        assert!(!is_admin(&headers, Some("")));
+        headers.insert("authorization", "secret".parse().unwrap());
+        assert!(!is_admin(&headers, Some("secret")));
+    }
+}
@@ -1,10 +1,11 @@
 use std::{time::{Instant}, sync::Arc};
 
+use chrono::{DateTime, Utc};
 use serde::{Deserialize, Serialize};
 use tokio::sync::RwLock;
 use tracing::{log};
 
-use crate::{event_service::{EventService, ApiGameEvent}, api_season_service::{ApiGame, ApiSeasonService}, stats_service::{StatsService, ApiGameStats}, player_service::{PlayerService, ApiAthlete}, game_report_service::GameStatus};
+use crate::{event_service::{EventService, ApiGameEvent}, api_season_service::{ApiGame, ApiSeasonService}, stats_service::{StatsService, ApiGameStats}, player_service::{PlayerService, ApiAthlete}, game_report_service::GameStatus, service_config::ServiceConfig};
 
 #[derive(Serialize, Deserialize, Clone)]
 pub struct ApiGameDetails {
@@ -12,36 +13,50 @@ pub struct ApiGameDetails {
     pub stats: Option<ApiGameStats>,
     pub game: ApiGame,
     pub players: Vec<ApiAthlete>,
+    // when the oldest of events, stats and players was fetched
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub as_of: Option<DateTime<Utc>>,
 }
 
 #[derive(Clone)]
 pub struct ApiGameDetailsService {
-    api_season_service: Arc<RwLock<ApiSeasonService>>
+    api_season_service: Arc<RwLock<ApiSeasonService>>,
+    service_config: ServiceConfig,
 }
 
 impl ApiGameDetailsService {
-    pub fn new(api_season_service: Arc<RwLock<ApiSeasonService>>) -> ApiGameDetailsService {
-        ApiGameDetailsService { api_season_service }
+    pub fn new(api_season_service: Arc<RwLock<ApiSeasonService>>, service_config: ServiceConfig) -> ApiGameDetailsService {
+        ApiGameDetailsService { api_season_service, service_config }
     }
     pub async fn read(&self, game_uuid: &str) -> Option<ApiGameDetails> {
         let before = Instant::now();
         let game = self.api_season_service.read().await.read_game(game_uuid);
-        if let Some(GameStatus::Coming) = game.as_ref().map(|e| e.status.clone()) {
-            return Some(ApiGameDetails { game: game.unwrap(), events: vec!(), stats: None, players: vec![] });
+        if game.as_ref().is_some_and(|e| e.status == GameStatus::Coming || e.status.is_called_off()) {
+            return Some(ApiGameDetails { game: game.unwrap(), events: vec!(), stats: None, players: vec![], as_of: None });
         }
 
         let game = game.as_ref()?;
         let (events, stats, players) = futures::join!(
-            EventService::update(game_uuid, None),
-            StatsService::update(&game.league, game_uuid, None),
-            PlayerService::update(&game.league, game_uuid, None),
+            EventService::update(game_uuid, self.service_config.details_events_throttle()),
+            StatsService::update(&game.league, game_uuid, self.service_config.stats_throttle()),
+            PlayerService::update(&game.league, &game.season, game_uuid, self.service_config.players_throttle()),
         );
 
+        let mut events = events.unwrap_or_default();
+        let stats = stats.ok();
+        let players = players.unwrap_or_default();
+        EventService::resolve_assists(game_uuid, &mut events, &players);
+
         let res = Some(ApiGameDetails {
             game: game.clone(),
-            events: events.unwrap_or_default().into_iter().rev().collect(),
+            events: events.into_iter().rev().collect(),
             stats,
             players,
+            as_of: [
+                EventService::last_updated(game_uuid),
+                StatsService::last_updated(&game.league, game_uuid),
+                PlayerService::last_updated(&game.league, &game.season, game_uuid),
+            ].into_iter().flatten().min().map(DateTime::<Utc>::from),
         });
 
         log::debug!("[API.DETAILS] read {:.2?}", before.elapsed());
@@ -2,14 +2,13 @@ use std::{collections::HashMap, time::Instant};
 
 use tracing::log;
 
-use crate::{player_service::{PlayerService, ApiAthleteStats, ApiAthlete, ApiPlayerStats, ApiGoalkeeperStats}, api_season_service::{ApiGame}, db::Db, game_report_service::GameStatus, models::Season};
+use crate::{event_service::EventService, player_service::{PlayerService, ApiAthleteStats, ApiAthlete, ApiPlayerStats, ApiGoalkeeperStats, ATHLETE_SCHEMA_VERSION}, api_season_service::{ApiGame}, db::Db, game_report_service::GameStatus, models::Season};
 
 
 /**
  * keys: id -> see all seasons, teams etc.
  * season + team -> Players[]
  */
-
 type Team = String;
 
 #[derive(Clone, PartialEq, Eq, Hash)]
@@ -17,6 +16,7 @@ pub struct PlayerSeasonKey (// -> PlayerSeasonStats // when
     pub i32,
     pub Season,
     pub Team,
+    pub std::mem::Discriminant<ApiAthleteStats>, // never merge player and goalkeeper stats for the same id
 );
 impl std::fmt::Display for PlayerSeasonKey {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
@@ -45,6 +45,7 @@ impl ApiAthleteStats {
                 self_stats.toi_s += game_stats.toi_s;
                 self_stats.fow += game_stats.fow;
                 self_stats.hits += game_stats.hits;
+                self_stats.gwg += game_stats.gwg;
             },
             (ApiAthleteStats::Goalkeeper(self_stats), ApiAthleteStats::Goalkeeper(game_stats)) => {
                 self_stats.ga += game_stats.ga;
@@ -60,19 +61,34 @@ impl ApiAthleteStats {
 pub struct ApiPlayerStatsService {
 }
 
+// The players are gathered in a HashMap, sorted so the stored lists don't change order between runs
+fn sorted(mut players: Vec<ApiAthlete>) -> Vec<ApiAthlete> {
+    players.sort_by_key(|e| (e.id, e.season.to_string(), e.team_code.clone(), matches!(e.stats, ApiAthleteStats::Goalkeeper(_))));
+    players
+}
+
 impl ApiPlayerStatsService {
     pub fn update(games: &[ApiGame]) {
         log::info!("[API.PLAYERSTATS] Start with {} games", games.len());
         let before = Instant::now();
         let all_players = games.iter()
-            .filter(|e| !matches!(e.status, GameStatus::Coming))
-            .filter_map(|e| PlayerService::read(&e.league, &e.game_uuid).map(|stats| (e, stats)));
+            .filter(|e| !matches!(e.status, GameStatus::Coming) && !e.status.is_called_off())
+            .filter_map(|e| PlayerService::read(&e.league, &e.season, &e.game_uuid).map(|stats| (e, stats)));
         
         let mut player_map: HashMap<PlayerSeasonKey, ApiAthlete> = HashMap::new();
 
         for (game, stats) in all_players {
-            for e in stats {
-                let key = PlayerSeasonKey(e.id, game.season.clone(), e.team_code.clone());
+            let gwg = match game.status {
+                GameStatus::Finished => EventService::game_winning_goal(&game.game_uuid),
+                _ => None,
+            };
+            for mut e in stats {
+                if let (ApiAthleteStats::Player(p), Some(gwg)) = (&mut e.stats, &gwg) {
+                    if gwg.player_id == Some(e.id) && gwg.team == e.team_code {
+                        p.gwg = 1;
+                    }
+                }
+                let key = PlayerSeasonKey(e.id, game.season.clone(), e.team_code.clone(), std::mem::discriminant(&e.stats));
                 let entry = player_map.entry(key).or_insert_with(|| ApiAthlete {
                     id: e.id, 
                     season: game.season.clone(),
@@ -81,11 +97,15 @@ impl ApiPlayerStatsService {
                     first_name: e.first_name.clone(),
                     family_name: e.family_name.clone(),
                     jersey: e.jersey,
+                    image_url: e.image_url.clone(),
                     stats: match e.stats {
                         ApiAthleteStats::Player(_) => ApiAthleteStats::Player(ApiPlayerStats { ..Default::default() }),
                         ApiAthleteStats::Goalkeeper(_) => ApiAthleteStats::Goalkeeper(ApiGoalkeeperStats { ..Default::default() }),
                     },
                 });
+                if entry.image_url.is_none() {
+                    entry.image_url = e.image_url.clone();
+                }
                 entry.stats.add(&e.stats);
             }
         }
@@ -101,8 +121,8 @@ impl ApiPlayerStatsService {
             map
         });
         let teams_db = ApiPlayerStatsService::get_team_player_db();
-        for (id, p) in &teams_player_map {
-            _ = teams_db.write(id, p);
+        for (id, p) in teams_player_map {
+            _ = teams_db.write(&id, &sorted(p));
         }
 
         let player_career_map = player_map.iter().fold(HashMap::new(), |mut map, player_entry| {
@@ -114,19 +134,19 @@ impl ApiPlayerStatsService {
             map
         });
         let career_db = ApiPlayerStatsService::get_player_career_db();
-        for (id, p) in &player_career_map {
-            _ = career_db.write(id, p);
+        for (id, p) in player_career_map {
+            _ = career_db.write(&id, &sorted(p));
         }
 
         log::info!("[API.PLAYERSTATS] Finished in {:.0?}", before.elapsed());
     }
 
     pub fn get_player_career_db() -> Db<i32, Vec<ApiAthlete>> {
-        Db::<i32, Vec<ApiAthlete>>::new("v2_api_player_career")
+        Db::<i32, Vec<ApiAthlete>>::new("v2_api_player_career").with_version(ATHLETE_SCHEMA_VERSION)
     }
 
     pub fn get_team_player_db() -> Db<TeamSeasonKey, Vec<ApiAthlete>> {
-        Db::<TeamSeasonKey, Vec<ApiAthlete>>::new("v2_api_team_players")
+        Db::<TeamSeasonKey, Vec<ApiAthlete>>::new("v2_api_team_players").with_version(ATHLETE_SCHEMA_VERSION)
     }
 }
 
@@ -138,7 +158,7 @@ mod tests {
     use chrono::Utc;
     use tempdir::TempDir;
 
-    use crate::{api_player_stats_service::{ApiPlayerStatsService, TeamSeasonKey}, api_season_service::ApiGame, models2::external::player::{PlayerStatsRsp, EachTeamStats, PlayerName, PlayerStats, GoalkeeperStats}, rest_client, db::Db, player_service::ApiAthleteStats};
+    use crate::{api_player_stats_service::{ApiPlayerStatsService, TeamSeasonKey}, api_season_service::ApiGame, models2::external::player::{PlayerStatsRsp, EachTeamStats, PlayerName, PlayerStats, GoalkeeperStats}, rest_client, db::Db, player_service::ApiAthleteStats, models2::external::event::PlayByPlay, event_service::EventService};
 
     fn before() {
         std::env::set_var("DB_PATH", TempDir::new("test").expect("dir to be created").path().to_str().unwrap());
@@ -160,8 +180,8 @@ mod tests {
         };
 
         //Store
-        _ = rest_db.write(&rest_client::get_player_stats_url(&crate::models::League::SHL, &game1.game_uuid), &playerRsp);
-        _ = rest_db.write(&rest_client::get_player_stats_url(&crate::models::League::SHL, &game2.game_uuid), &playerRsp);
+        _ = rest_db.write(&rest_client::get_player_stats_key(&crate::models::League::SHL, &game1.season, &game1.game_uuid), &playerRsp);
+        _ = rest_db.write(&rest_client::get_player_stats_key(&crate::models::League::SHL, &game2.season, &game2.game_uuid), &playerRsp);
 
         ApiPlayerStatsService::update(&[game1, game2]);
 
@@ -186,7 +206,7 @@ mod tests {
         let team_db = ApiPlayerStatsService::get_team_player_db();
         let stored_team = team_db.read(&TeamSeasonKey(crate::models::Season::Season2022, team.to_string())).unwrap();
         assert_eq!(stored_team.len(), 1);
-        let team_player = stored_team.get(0).unwrap();
+        let team_player = stored_team.first().unwrap();
         assert_eq!(team_player.id, stored_player[0].id);
         let team_stats = match team_player.stats.clone() {
             ApiAthleteStats::Player(a) => a,
@@ -211,8 +231,8 @@ mod tests {
         };
 
         //Store player rsps
-        _ = rest_db.write(&rest_client::get_player_stats_url(&crate::models::League::SHL, &game1.game_uuid), &playerRsp);
-        _ = rest_db.write(&rest_client::get_player_stats_url(&crate::models::League::SHL, &game2.game_uuid), &playerRsp);
+        _ = rest_db.write(&rest_client::get_player_stats_key(&crate::models::League::SHL, &game1.season, &game1.game_uuid), &playerRsp);
+        _ = rest_db.write(&rest_client::get_player_stats_key(&crate::models::League::SHL, &game2.season, &game2.game_uuid), &playerRsp);
 
         ApiPlayerStatsService::update(&[game1, game2]);
         let player_db = ApiPlayerStatsService::get_player_career_db();
@@ -250,8 +270,8 @@ mod tests {
         };
 
         //Store player rsps
-        _ = rest_db.write(&rest_client::get_player_stats_url(&crate::models::League::SHL, &game1.game_uuid), &playerRsp);
-        _ = rest_db.write(&rest_client::get_player_stats_url(&crate::models::League::SHL, &game2.game_uuid), &playerRsp);
+        _ = rest_db.write(&rest_client::get_player_stats_key(&crate::models::League::SHL, &game1.season, &game1.game_uuid), &playerRsp);
+        _ = rest_db.write(&rest_client::get_player_stats_key(&crate::models::League::SHL, &game2.season, &game2.game_uuid), &playerRsp);
 
         ApiPlayerStatsService::update(&[game1, game2]);
         let player_db = ApiPlayerStatsService::get_player_career_db();
@@ -272,6 +292,71 @@ mod tests {
         assert_eq!(stats.gp, 0);
     }
 
+    #[test]
+    fn player_and_goalkeeper_with_same_id_are_not_merged() {
+        before();
+        let team = "HV71";
+        let rest_db = Db::<String, PlayerStatsRsp>::new("rest");
+        let player_id = 123456;
+        let game1 = get_played_game("game1_4", team);
+        let player = get_player(player_id, team);
+        let goalkeeper = get_goalkeeper(player_id, team);
+        let playerRsp = PlayerStatsRsp {
+            stats: EachTeamStats { homeTeamValue: [player.0.clone()].to_vec(), awayTeamValue: [].to_vec() },
+            players: EachTeamStats { homeTeamValue: HashMap::from([(player_id, player.1)]), awayTeamValue: HashMap::from([]), },
+            gkStats: EachTeamStats { homeTeamValue: [goalkeeper.0.clone()].to_vec(), awayTeamValue: [].to_vec() },
+            goalkeepers: EachTeamStats { homeTeamValue: HashMap::from([(player_id, goalkeeper.1)]), awayTeamValue: HashMap::from([]), },
+            ..Default::default()
+        };
+
+        _ = rest_db.write(&rest_client::get_player_stats_key(&crate::models::League::SHL, &game1.season, &game1.game_uuid), &playerRsp);
+
+        ApiPlayerStatsService::update(&[game1]);
+
+        let stored_player = ApiPlayerStatsService::get_player_career_db().read(&player_id).unwrap();
+        assert_eq!(stored_player.len(), 2);
+        let player_stats = stored_player.iter().find_map(|e| match &e.stats { ApiAthleteStats::Player(a) => Some(a.clone()), _ => None }).unwrap();
+        let gk_stats = stored_player.iter().find_map(|e| match &e.stats { ApiAthleteStats::Goalkeeper(a) => Some(a.clone()), _ => None }).unwrap();
+        assert_eq!(player_stats.g, 2);
+        assert_eq!(player_stats.gp, 1);
+        assert_eq!(gk_stats.svs, 5);
+        assert_eq!(gk_stats.gp, 1);
+    }
+
+    #[test]
+    fn game_winning_goals() {
+        before();
+        let team = "LHF";
+        let player_id = 777;
+        let rest_db = Db::<String, PlayerStatsRsp>::new("rest");
+        let player = get_player(player_id, team);
+        let player_rsp = PlayerStatsRsp {
+            stats: EachTeamStats { homeTeamValue: vec![player.0.clone()], awayTeamValue: vec![] },
+            players: EachTeamStats { homeTeamValue: HashMap::from([(player_id, player.1)]), awayTeamValue: HashMap::new() },
+            ..Default::default()
+        };
+        let games = [get_played_game("gwg_game1", team), get_played_game("gwg_game2", team)];
+        for game in &games {
+            _ = rest_db.write(&rest_client::get_player_stats_key(&game.league, &game.season, &game.game_uuid), &player_rsp);
+        }
+        let goal = |event_id: i32, scorer: i32, home: i16| serde_json::from_str::<PlayByPlay>(&format!(r#"{{
+            "eventId": {event_id}, "revision": 1, "hash": "abc", "period": 1, "gametime": "{event_id:02}:00", "description": "Mål",
+            "class": "Goal", "team": "{team}", "location": {{ "x": 1.0, "y": 2.0 }},
+            "extra": {{ "scorerId": {scorer}, "scorerLong": "1 Olle Olsson", "teamAdvantage": "EQ", "homeAgainst": 0, "homeForward": {home}, "assist": "" }}
+        }}"#)).unwrap();
+        // the winner in the first game, not in the second one
+        EventService::store_all_raw("gwg_game1", vec![goal(1, player_id, 1), goal(2, 1, 2)]);
+        EventService::store_all_raw("gwg_game2", vec![goal(1, 1, 1), goal(2, player_id, 2)]);
+
+        ApiPlayerStatsService::update(&games);
+
+        let stored = ApiPlayerStatsService::get_player_career_db().read(&player_id).unwrap();
+        match &stored[0].stats {
+            ApiAthleteStats::Player(p) => assert_eq!(p.gwg, 1),
+            _ => panic!("not a player"),
+        }
+    }
+
     pub fn get_played_game(game_uuid: &str, team: &str) -> ApiGame {
         ApiGame {
             game_uuid: game_uuid.to_string(),
@@ -280,6 +365,7 @@ mod tests {
             home_team_result: 3,
             away_team_result: 0,
             start_date_time: Utc::now(),
+            start_date_time_local: None,
             status: crate::game_report_service::GameStatus::Finished,
             shootout: false,
             overtime: false,
@@ -310,7 +396,7 @@ mod tests {
             SW: 1,
             TOI: "13:37".to_string(),
         };
-        let player_name = PlayerName{firstName: "olle".to_string(), lastName: "karlsson".to_string() };
+        let player_name = PlayerName{firstName: "olle".to_string(), lastName: "karlsson".to_string(), imageUrl: None };
         (player_stats, player_name)
     }
 
@@ -324,7 +410,7 @@ mod tests {
             SVS: 5,
             SVS_perc: 5.0,
         };
-        let player_name = PlayerName{firstName: "goalie".to_string(), lastName: "karlsson".to_string() };
+        let player_name = PlayerName{firstName: "goalie".to_string(), lastName: "karlsson".to_string(), imageUrl: None };
         (player_stats, player_name)
     }
 
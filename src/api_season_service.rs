@@ -1,11 +1,11 @@
 use std::{time::Instant, sync::Arc, collections::HashMap};
 
-use chrono::{DateTime, Utc};
+use chrono::{DateTime, Utc, FixedOffset};
 use serde::{Deserialize, Serialize};
 use tokio::sync::RwLock;
 use tracing::log;
 
-use crate::{models::{Season, League, GameType, SeasonKey}, game_report_service::{GameReportService, GameStatus, ApiGameReport}, db::Db, models2::external::season::{SeasonRsp}};
+use crate::{models::{Season, League, GameType, SeasonKey, to_stockholm_fixed_offset, normalize_team_code}, game_report_service::{GameReportService, GameStatus, ApiGameReport}, db::Db, models2::external::season::{SeasonRsp}};
 
 #[derive(Serialize, Deserialize, Debug, Clone)]
 pub struct ApiGame {
@@ -15,6 +15,8 @@ pub struct ApiGame {
     pub home_team_result: i16,
     pub away_team_result: i16,
     pub start_date_time: DateTime<Utc>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub start_date_time_local: Option<DateTime<FixedOffset>>,
     pub status: GameStatus,
     pub shootout: bool,
     pub overtime: bool,
@@ -44,17 +46,15 @@ impl ApiSeasonService {
     pub fn update(&mut self, season: &Season, responses: &[(SeasonKey, SeasonRsp)]) -> Vec<ApiGame> {
         let before = Instant::now();
         let decorated_games: Vec<ApiGame> = responses.iter().flat_map(|(key, rsp)| rsp.gameInfo.iter().map(|e| {
-            let base_status = match e.state.as_str() {
-                "post-game" => GameStatus::Finished,
-                _ => GameStatus::Coming,
-            };
+            let base_status = GameStatus::from_season_state(&e.state);
             let mut mapped = ApiGame {
                 game_uuid: e.uuid.clone(),
-                home_team_code: e.homeTeamInfo.code.clone(),
-                away_team_code: e.awayTeamInfo.code.clone(),
+                home_team_code: normalize_team_code(&e.homeTeamInfo.code),
+                away_team_code: normalize_team_code(&e.awayTeamInfo.code),
                 home_team_result: e.homeTeamInfo.score.to_num(),
                 away_team_result: e.awayTeamInfo.score.to_num(),
                 start_date_time: e.startDateTime,
+                start_date_time_local: Some(to_stockholm_fixed_offset(&e.startDateTime)),
                 played: GameStatus::Finished == base_status,
                 shootout: e.shootout,
                 overtime: e.overtime,
@@ -115,7 +115,7 @@ impl ApiSeasonService {
         if current_season_game.is_some() {
             current_season_game
         } else {
-            self.rest_games.get(&game_uuid.to_string()).cloned()
+            self.rest_games.get(game_uuid).cloned()
         }
     }
 
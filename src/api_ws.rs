@@ -3,10 +3,12 @@ use std::time::Duration;
 use axum::extract::ws::{WebSocket, Message};
 use futures::{StreamExt, SinkExt};
 use serde::{Serialize, Deserialize};
-use tokio::select;
+use tokio::{select, sync::broadcast::{self, Receiver, Sender, error::RecvError}};
 use tracing::log;
 
-use crate::{api::ApiState, event_service::ApiGameEvent, game_report_service::ApiGameReport, stats_service::ApiGameStats};
+use std::collections::HashMap;
+
+use crate::{api::ApiState, event_service::{ApiGameEvent, EventKey}, event_delta::ApiGameEventDelta, game_report_service::ApiGameReport, stats_service::ApiGameStats, CONFIG};
 
 
 
@@ -22,9 +24,14 @@ pub struct WsMsg {
 #[derive(Serialize, Deserialize, Clone, Debug)]
 #[serde(tag = "type", rename_all="snake_case")]
 pub enum WsMsgBody {
-    Event { event: ApiGameEvent },
+    Event { event: Box<ApiGameEvent> },
+    // A revision of an event sent before on the connection, for clients connected with deltas
+    EventDelta { delta: Box<ApiGameEventDelta> },
     Report { report: ApiGameReport },
-    Stats { stats: ApiGameStats }
+    Stats { stats: ApiGameStats },
+    // The subscriber fell behind and the oldest messages were dropped. The client resyncs
+    // each game it follows from its last event id with /v2/game/:game_uuid/events/unacked
+    Lagged { skipped: u64 },
 }
 
 #[derive(Serialize, Deserialize, Clone, Debug)]
@@ -35,7 +42,7 @@ pub struct WsReq {
 
 impl From<ApiGameEvent> for WsMsg {
     fn from(event: ApiGameEvent) -> Self {
-        WsMsg { game_uuid: event.game_uuid.clone(), body: WsMsgBody::Event { event } }
+        WsMsg { game_uuid: event.game_uuid.clone(), body: WsMsgBody::Event { event: Box::new(event) } }
     }
 }
 impl From<ApiGameReport> for WsMsg {
@@ -43,12 +50,25 @@ impl From<ApiGameReport> for WsMsg {
         WsMsg { game_uuid: report.game_uuid.clone(), body: WsMsgBody::Report { report } }
     }
 }
+impl WsMsg {
+    // Not about one game, game_uuid is empty
+    pub fn lagged(skipped: u64) -> WsMsg {
+        WsMsg { game_uuid: "".to_string(), body: WsMsgBody::Lagged { skipped } }
+    }
+}
+
+// Bounded, a full channel drops the oldest message for subscribers that haven't read it
+// instead of blocking the producer
+pub fn broadcast_channel() -> (Sender<WsMsg>, Receiver<WsMsg>) {
+    broadcast::channel(CONFIG.broadcast_capacity.max(1))
+}
+
 pub struct ApiWs {
 
 }
 
 impl ApiWs {
-    pub async fn handle(stream: WebSocket, state: ApiState) {
+    pub async fn handle(stream: WebSocket, state: ApiState, deltas: bool) {
         let (mut sender, mut receiver) = stream.split();
         let mut broadcast_receiver = state.broadcast_sender.subscribe();
 
@@ -62,14 +82,13 @@ impl ApiWs {
             }
         });
         _ = tokio::spawn(async move {
+            let mut sent = HashMap::new();
             loop {
                 let msg = select! {
-                    msg = broadcast_receiver.recv() => match msg {
-                        Ok(msg) => Message::Text(serde_json::to_string(&msg).unwrap_or_default()),
-                        Err(e) => {
-                            log::error!("[API.WS] broadcast receive {:?}", e);
-                            Message::Pong(vec![])
-                        }
+                    msg = ApiWs::recv(&mut broadcast_receiver) => match msg {
+                        Some(msg) if deltas => Message::Text(serde_json::to_string(&ApiWs::to_delta(&mut sent, msg)).unwrap_or_default()),
+                        Some(msg) => Message::Text(serde_json::to_string(&msg).unwrap_or_default()),
+                        None => break,
                     },
                     _ = tokio::time::sleep(Duration::from_secs(60)) => {
                         log::info!("[API.WS] ping");
@@ -89,9 +108,103 @@ impl ApiWs {
         log::info!("[API.WS] Close, in total = {}", ApiWs::update_nr_connections(-1, &state).await);
     }
 
+    // None once the producer is gone. A lagging subscriber gets a Lagged message and then continues
+    // with the oldest message still buffered
+    pub async fn recv(receiver: &mut Receiver<WsMsg>) -> Option<WsMsg> {
+        match receiver.recv().await {
+            Ok(msg) => Some(msg),
+            Err(RecvError::Lagged(n)) => {
+                log::warn!("[API.WS] Lagged {n} messages");
+                Some(WsMsg::lagged(n))
+            },
+            Err(RecvError::Closed) => None,
+        }
+    }
+
+    // An event sent before on the connection becomes the delta to the last one sent
+    fn to_delta(sent: &mut HashMap<(String, EventKey), ApiGameEvent>, msg: WsMsg) -> WsMsg {
+        let WsMsgBody::Event { event } = msg.body else {
+            return msg;
+        };
+        let delta = sent.get(&(event.game_uuid.clone(), event.key()))
+            .and_then(|prior| ApiGameEventDelta::new(prior, &event));
+        sent.insert((event.game_uuid.clone(), event.key()), (*event).clone());
+        match delta {
+            Some(delta) => WsMsg { game_uuid: msg.game_uuid, body: WsMsgBody::EventDelta { delta: Box::new(delta) } },
+            None => WsMsg { game_uuid: msg.game_uuid, body: WsMsgBody::Event { event } },
+        }
+    }
+
     async fn update_nr_connections(delta: i16, state: &ApiState) -> i16{
         let mut nr_ws = state.nr_ws.write().await;
         *nr_ws += delta;
         *nr_ws
     }
-}
\ No newline at end of file
+}
+#[cfg(test)]
+mod tests {
+    use std::{time::Duration, collections::HashMap};
+
+    use crate::{event_service::{ApiEventType, ApiGameEvent}, game_report_service::GameStatus, models::GamePeriod};
+
+    use super::{ApiWs, WsMsg, WsMsgBody};
+
+    fn get_msg(event_id: usize) -> WsMsg {
+        ApiGameEvent {
+            game_uuid: "slow_subscriber".to_string(),
+            event_id: event_id.to_string(),
+            revision: 1,
+            status: GameStatus::Period1,
+            period: GamePeriod::Regulation(1),
+            gametime: "01:00".to_string(),
+            description: "".to_string(),
+            info: ApiEventType::General,
+            tags: vec![],
+        }.into()
+    }
+
+    #[tokio::test]
+    async fn slow_subscriber_is_told_it_lagged() {
+        let (sender, _) = tokio::sync::broadcast::channel(4);
+        let mut slow = sender.subscribe();
+
+        // the producer never waits on the subscriber
+        let produce = async { (0..10).for_each(|i| _ = sender.send(get_msg(i))) };
+        assert!(tokio::time::timeout(Duration::from_secs(1), produce).await.is_ok());
+
+        let lagged = ApiWs::recv(&mut slow).await.unwrap();
+        assert!(matches!(lagged.body, WsMsgBody::Lagged { skipped: 6 }));
+        assert_eq!(serde_json::to_value(&lagged).unwrap()["type"], "lagged");
+        for i in 6..10 {
+            match ApiWs::recv(&mut slow).await.unwrap().body {
+                WsMsgBody::Event { event } => assert_eq!(event.event_id, i.to_string()),
+                _ => panic!("not an event"),
+            }
+        }
+
+        drop(sender);
+        assert!(ApiWs::recv(&mut slow).await.is_none());
+    }
+
+    #[test]
+    fn revisions_are_sent_as_deltas() {
+        let mut sent = HashMap::new();
+        let first = ApiWs::to_delta(&mut sent, get_msg(1));
+        assert!(matches!(first.body, WsMsgBody::Event { .. }));
+
+        let mut revised = get_msg(1);
+        if let WsMsgBody::Event { event } = &mut revised.body {
+            event.revision = 2;
+            event.description = "revised".to_string();
+        }
+        let delta = ApiWs::to_delta(&mut sent, revised);
+        assert_eq!(serde_json::to_value(&delta).unwrap()["type"], "event_delta");
+        match delta.body {
+            WsMsgBody::EventDelta { delta } => {
+                assert_eq!((delta.revision, delta.description.as_deref(), delta.gametime), (2, Some("revised"), None));
+            },
+            _ => panic!("not a delta"),
+        }
+        assert!(matches!(ApiWs::to_delta(&mut sent, get_msg(2)).body, WsMsgBody::Event { .. }));
+    }
+}
@@ -1,7 +1,6 @@
 use serde::{Deserialize};
-use std::fs;
 
-use crate::models::League;
+use crate::{models::League, event_service::PublishPolicy, game_refresher::RefreshIntervals, service_config::ServiceConfig};
 
 #[derive(Debug, Deserialize, Default)]
 pub struct Config {
@@ -20,12 +19,85 @@ pub struct Config {
 
     #[serde(default="default_db_path")]
     pub db_path: String,
+
+    #[serde(default)]
+    pub publish_policy: PublishPolicy,
+    #[serde(default)]
+    pub webhook_urls: Vec<String>,
+    // revisions of the same event within the window are published once. 0 publishes every revision
+    #[serde(default="default_publish_debounce_ms")]
+    pub publish_debounce_ms: u64,
+
+    #[serde(default="default_max_concurrency")]
+    pub max_concurrency: usize,
+
+    #[serde(default)]
+    pub refresh_intervals: RefreshIntervals,
+
+    #[serde(default)]
+    pub service_config: ServiceConfig,
+
+    // Player image url with {id} for the player id, used when the feed has no image
+    #[serde(default)]
+    pub player_image_url: Option<String>,
+
+    // Team logo url with {code} for the team code
+    #[serde(default)]
+    pub team_logo_url: Option<String>,
+
+    // Bearer token for the /admin routes, which are off without one
+    #[serde(default)]
+    pub admin_token: Option<String>,
+
+    // Db namespaces storing a checksum with each value, see Db::with_checksum
+    #[serde(default)]
+    pub checksum_namespaces: Vec<String>,
+
+    // Upstream calls slower than this are logged and kept for /admin/slow-calls. 0 is off
+    #[serde(default="default_slow_call_ms")]
+    pub slow_call_ms: u64,
+
+    // Messages buffered for each websocket subscriber before the oldest are dropped
+    #[serde(default="default_broadcast_capacity")]
+    pub broadcast_capacity: usize,
+
+    // Goals within this many game seconds of the previous goal are quick strikes. 0 is off
+    #[serde(default="default_quick_strike_s")]
+    pub quick_strike_s: u64,
+
+    // 0 is no limit
+    #[serde(default="default_max_events_per_game")]
+    pub max_events_per_game: usize,
 }
 
 fn default_db_path() -> String {
     "./db".to_string()
 }
 
+fn default_publish_debounce_ms() -> u64 {
+    1000
+}
+
+fn default_max_concurrency() -> usize {
+    4
+}
+
+fn default_slow_call_ms() -> u64 {
+    2000
+}
+
+fn default_quick_strike_s() -> u64 {
+    30
+}
+
+fn default_broadcast_capacity() -> usize {
+    1000
+}
+
+fn default_max_events_per_game() -> usize {
+    2000
+}
+
 impl Config {
     pub fn get_url(&self, league: &League) -> &str {
         match league {
@@ -35,11 +107,21 @@ impl Config {
     }
 }
 
+#[cfg(test)]
+pub fn get_config() -> Config {
+    Config {
+        db_path: std::env::var("DB_PATH").unwrap_or_else(|_| default_db_path()),
+        max_events_per_game: default_max_events_per_game(),
+        ..Default::default()
+    }
+}
+
+#[cfg(not(test))]
 pub fn get_config() -> Config {
     let path = std::env::var("CONFIG_PATH").ok()
         .unwrap_or_else(|| "./deployment/config.json".to_string());
     println!("[CONFIG] {}", path);
-    let data = fs::read_to_string(path.clone())
+    let data = std::fs::read_to_string(path.clone())
         .expect("Unable to read file");
     let mut result: Config = serde_json::from_str(&data)
         .unwrap_or_else(|_| panic!("{}", &format!("Could not parse JSON at {path}!")));
@@ -47,5 +129,11 @@ pub fn get_config() -> Config {
         result.db_path = db_path;
         println!("[CONFIG] DB_PATH {}", result.db_path);
     }
+    // e.g. a staging feed or a recording proxy, for both leagues
+    if let Ok(base_url) = std::env::var("BASE_URL") {
+        result.shl_url = base_url.trim_end_matches('/').to_string();
+        result.ha_url = result.shl_url.clone();
+        println!("[CONFIG] BASE_URL {}", result.shl_url);
+    }
     result
 }
\ No newline at end of file
@@ -1,19 +1,67 @@
+use flate2::{Compression, Crc, CrcReader};
+use flate2::read::GzDecoder;
+use flate2::write::GzEncoder;
+use lazy_static::lazy_static;
 use serde::{Serialize};
 use serde::de::DeserializeOwned;
 use tracing::log;
+use std::collections::HashMap;
+use std::collections::hash_map::DefaultHasher;
+use std::hash::{Hash, Hasher};
 use std::fmt::Display;
+use std::fs::File;
+use std::io::{BufRead, BufReader, Read, Write};
+use std::sync::{Arc, Mutex};
 use std::time::{Instant, Duration, SystemTime};
 use walkdir::WalkDir;
 use crate::{CONFIG};
 
+// (namespace, key)
+type LockKey = (String, String);
+
+// Upgrades a value stored under an older schema version, returns None when it can't be
+pub type Migration = fn(u32, serde_json::Value) -> Option<serde_json::Value>;
+
+lazy_static! {
+    // one lock per key for the whole process, shared by every Db instance of the namespace
+    static ref KEY_LOCKS: Mutex<HashMap<LockKey, Arc<Mutex<()>>>> = Mutex::new(HashMap::new());
+}
+
+// Staleness is measured against this, so tests can move time without sleeping
+pub trait Clock: Send + Sync {
+    fn now(&self) -> SystemTime;
+}
+
+pub struct SystemClock;
+impl Clock for SystemClock {
+    fn now(&self) -> SystemTime {
+        SystemTime::now()
+    }
+}
+
 pub struct Db<K: Display, V: DeserializeOwned + Serialize> {
     pub name: String,
     pub key_type: std::marker::PhantomData<K>,
     pub value_type: std::marker::PhantomData<V>,
 
-    sender: tokio::sync::broadcast::Sender<(K, V)>
+    sender: tokio::sync::broadcast::Sender<(K, V)>,
+    compressed: bool,
+    version: Option<u32>,
+    migration: Option<Migration>,
+    checksum: bool,
+    clock: Arc<dyn Clock>,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum DbError {
+    // Nothing stored, or not readable as the value type
+    Missing,
+    // The stored bytes aren't the ones written, the value should be refetched
+    ChecksumMismatch(String),
 }
 
+const GZIP_MAGIC: [u8; 2] = [0x1f, 0x8b];
+
 impl<K: Display + Clone, V: DeserializeOwned + Serialize + Clone> Db<K, V> {
     pub fn new(name: &str) -> Db<K, V> {
         Db {
@@ -21,12 +69,57 @@ impl<K: Display + Clone, V: DeserializeOwned + Serialize + Clone> Db<K, V> {
             key_type: std::marker::PhantomData,
             value_type: std::marker::PhantomData,
             sender: tokio::sync::broadcast::channel(1000).0,
+            compressed: false,
+            version: None,
+            migration: None,
+            checksum: CONFIG.checksum_namespaces.iter().any(|e| e == name),
+            clock: Arc::new(SystemClock),
         }
     }
 
+    #[cfg(test)]
+    pub fn with_clock(self, clock: Arc<dyn Clock>) -> Db<K, V> {
+        Db { clock, ..self }
+    }
+
+    // entries written with another schema version are treated as missing, unless there is a migration
+    pub fn with_version(self, version: u32) -> Db<K, V> {
+        Db { version: Some(version), ..self }
+    }
+
+    // Older entries are upgraded on read and stored with the current version on the next write.
+    // Entries written before the namespace was versioned are version 0
+    pub fn with_migration(self, migration: Migration) -> Db<K, V> {
+        Db { migration: Some(migration), ..self }
+    }
+
+    // A crc32 of each stored file is kept next to it and verified on read. Files written before are trusted.
+    // Outside of tests it's on for the checksum_namespaces, so every instance writing a namespace agrees
+    #[cfg(test)]
+    pub fn with_checksum(self) -> Db<K, V> {
+        Db { checksum: true, ..self }
+    }
+
+    // values are gzipped on write, reads handle both gzipped and plain files
+    pub fn new_compressed(name: &str) -> Db<K, V> {
+        Db { compressed: true, ..Db::new(name) }
+    }
+
     pub fn read(&self, key: &K) -> Option<V> {
+        match self.read_checked(key) {
+            Ok(e) => Some(e),
+            Err(DbError::Missing) => None,
+            Err(DbError::ChecksumMismatch(path)) => {
+                log::error!("[DB] Checksum mismatch {path}");
+                None
+            },
+        }
+    }
+
+    pub fn read_checked(&self, key: &K) -> Result<V, DbError> {
         let path = self.get_path(&key.to_string());
-        Db::<K, V>::read_file(&path)
+        self.verify(&path)?;
+        Db::<K, V>::read_file(&path, self.version, self.migration).ok_or(DbError::Missing)
     }
 
     pub fn read_all(&self) -> Vec<V> {
@@ -36,7 +129,8 @@ impl<K: Display + Clone, V: DeserializeOwned + Serialize + Clone> Db<K, V> {
         let result: Vec<V> = WalkDir::new(path).into_iter()
             .filter_map(|e| e.ok())
             .filter(|e| e.metadata().ok().map(|e| e.is_file()).unwrap_or(false))
-            .filter_map(|entry| Db::<K, V>::read_file(entry.path().to_str().unwrap()))
+            .filter(|entry| self.verify(entry.path().to_str().unwrap()).is_ok())
+            .filter_map(|entry| Db::<K, V>::read_file(entry.path().to_str().unwrap(), self.version, self.migration))
             .collect();
 
         log::info!("[DB] read all {} {} {:.0?}", self.name, result.len(), before.elapsed());
@@ -45,28 +139,94 @@ impl<K: Display + Clone, V: DeserializeOwned + Serialize + Clone> Db<K, V> {
 
     pub fn stream_all(&self) -> impl Iterator<Item = V> {
         let path = format!("{}/{}", CONFIG.db_path, self.name);
+        let (version, migration) = (self.version, self.migration);
+        let (name, checksum) = (self.name.clone(), self.checksum);
         WalkDir::new(path).into_iter()
             .filter_map(|e| e.ok())
             .filter(|e| e.metadata().ok().map(|e| e.is_file()).unwrap_or(false))
-            .filter_map(|entry| Db::<K, V>::read_file(entry.path().to_str().unwrap()))
+            .filter(move |entry| !checksum || Db::<K, V>::verify_file(&name, entry.path().to_str().unwrap()).is_ok())
+            .filter_map(move |entry| Db::<K, V>::read_file(entry.path().to_str().unwrap(), version, migration))
+    }
+
+    // Items of a stored json array one at a time, without reading the whole value into memory.
+    // Empty if nothing is stored, and for versioned namespaces, their arrays are inside an envelope.
+    // With checksums the file is read twice, once in chunks to verify it, then to stream it
+    pub fn stream_items<T: DeserializeOwned>(&self, key: &K) -> impl Iterator<Item = T> {
+        let path = self.get_path(&key.to_string());
+        let reader = match self.version.is_none() && self.verify(&path).is_ok() {
+            true => open_reader(&path),
+            false => None,
+        };
+        ArrayItems { reader, started: false, path, item_type: std::marker::PhantomData }
     }
 
     pub fn read_raw(&self, key: &K) -> String {
         let path = self.get_path(&key.to_string());
-        let data = std::fs::read_to_string(path);
+        if let Err(DbError::ChecksumMismatch(path)) = self.verify(&path) {
+            log::error!("[DB] Checksum mismatch {path}");
+            return String::new();
+        }
+        let data = match self.version {
+            Some(version) => Db::<K, V>::read_data(&path)
+                .and_then(|e| Db::<K, V>::unwrap_version(&path, &e, version, self.migration))
+                .map(|e| e.to_string()),
+            None => Db::<K, V>::read_data(&path),
+        };
         log::info!("[DB] Read raw from file {}", &key.to_string());
         data.unwrap_or_default()
     }
 
     pub fn write(&self, key: &K, obj: &V) -> std::io::Result<()> {
+        let json = self.serialize(obj)?;
+        let lock = self.get_lock(&key.to_string());
+        let _guard = lock.lock().unwrap_or_else(|e| e.into_inner());
+        self.write_json(key, obj, json)
+    }
+
+    // Skips the write, leaving the file untouched, when the content hash is unchanged
+    pub fn write_if_changed(&self, key: &K, obj: &V) -> std::io::Result<bool> {
+        let json = self.serialize(obj)?;
+        let lock = self.get_lock(&key.to_string());
+        let _guard = lock.lock().unwrap_or_else(|e| e.into_inner());
+        let stored_hash = Db::<K, V>::read_data(&self.get_path(&key.to_string())).map(|e| Db::<K, V>::get_hash(&e));
+        if stored_hash == Some(Db::<K, V>::get_hash(&json)) {
+            log::debug!("[DB] Unchanged {}/{}", self.name, key);
+            return Ok(false);
+        }
+        self.write_json(key, obj, json).map(|_| true)
+    }
+
+    fn serialize(&self, obj: &V) -> serde_json::Result<String> {
+        match self.version {
+            Some(version) => serde_json::to_string(&serde_json::json!({ "version": version, "value": obj })),
+            None => serde_json::to_string(&obj),
+        }
+    }
+
+    fn get_hash(data: &str) -> u64 {
+        let mut hasher = DefaultHasher::new();
+        data.hash(&mut hasher);
+        hasher.finish()
+    }
+
+    fn write_json(&self, key: &K, obj: &V, json: String) -> std::io::Result<()> {
         let before = Instant::now();
-        let json = serde_json::to_string(&obj)?;
         let path = std::path::PathBuf::from(self.get_path(&key.to_string()));
         std::fs::create_dir_all(path.parent().unwrap())?;
-        let result = std::fs::write(path, json);
-        
+        let data = if self.compressed {
+            let mut encoder = GzEncoder::new(Vec::new(), Compression::default());
+            encoder.write_all(json.as_bytes()).and_then(|_| encoder.finish())?
+        } else {
+            json.into_bytes()
+        };
+        let result = std::fs::write(&path, &data).and_then(|_| match self.checksum {
+            true => self.write_checksum(path.to_str().unwrap(), &data),
+            false => self.remove_checksum(path.to_str().unwrap()),
+        });
+
         match result {
             Ok(e) => {
+                self.write_reverse_key(&key.to_string());
                 log::debug!("[DB] Wrote to file {}/{} {:.2?}", self.name, key, before.elapsed());
                 _ = self.sender.send((key.clone(), obj.clone()));
                 Ok(e)
@@ -78,13 +238,43 @@ impl<K: Display + Clone, V: DeserializeOwned + Serialize + Clone> Db<K, V> {
         }
     }
 
+    // Read-modify-write of a single key, holding the key lock for the whole cycle
+    pub fn update<R>(&self, key: &K, f: impl FnOnce(&mut V) -> R) -> R where V: Default {
+        let path = self.get_path(&key.to_string());
+        let lock = self.get_lock(&key.to_string());
+        let _guard = lock.lock().unwrap_or_else(|e| e.into_inner());
+
+        let mut obj = Db::<K, V>::read_file(&path, self.version, self.migration).unwrap_or_default();
+        let result = f(&mut obj);
+        if let Ok(json) = self.serialize(&obj) {
+            _ = self.write_json(key, &obj, json);
+        }
+        result
+    }
+
     pub fn is_stale(&self, key: &K, delta_s: Option<Duration>) -> bool {
         let path = self.get_path(&key.to_string());
+        // corrupt values are refetched, even final ones
+        if self.verify(&path).is_err() {
+            return true;
+        }
+        // as are versioned values the current shape can't read, even after migrating
+        if let Some(version) = self.version {
+            let current = Db::<K, V>::read_data(&path)
+                .and_then(|e| Db::<K, V>::unwrap_version(&path, &e, version, self.migration))
+                .is_some_and(|e| serde_json::from_value::<V>(e).is_ok());
+            if !current {
+                return true;
+            }
+        }
         std::fs::metadata(path)
             .and_then(|e| e.modified())
             .map(|m| {
-                if let Some(delta_s) = delta_s {
-                    SystemTime::now().duration_since(m).unwrap() > delta_s
+                if self.is_final(&key.to_string()) {
+                    false
+                } else if let Some(delta_s) = delta_s {
+                    // a file from the future is fresh
+                    self.clock.now().duration_since(m).unwrap_or_default() > delta_s
                 } else {
                     false // if None and file exists => never stale
                 }
@@ -92,14 +282,71 @@ impl<K: Display + Clone, V: DeserializeOwned + Serialize + Clone> Db<K, V> {
             .unwrap_or(true) // file doesn't exists => stale
     }
 
+    // When the value was last written. Unchanged writes keep the old time
+    pub fn last_updated(&self, key: &K) -> Option<SystemTime> {
+        std::fs::metadata(self.get_path(&key.to_string())).and_then(|e| e.modified()).ok()
+    }
+
+    // A final value is never stale again, whatever the throttle
+    pub fn mark_final(&self, key: &K) -> std::io::Result<()> {
+        let path = std::path::PathBuf::from(self.get_final_path(&key.to_string()));
+        std::fs::create_dir_all(path.parent().unwrap())?;
+        std::fs::write(path, "")
+    }
+
+    fn verify(&self, path: &str) -> Result<(), DbError> {
+        match self.checksum {
+            true => Db::<K, V>::verify_file(&self.name, path),
+            false => Ok(()),
+        }
+    }
+
+    fn verify_file(name: &str, path: &str) -> Result<(), DbError> {
+        let Ok(stored) = std::fs::read_to_string(Db::<K, V>::get_checksum_path(name, path)) else { return Ok(()) };
+        match stored == get_file_checksum(path).map_err(|_| DbError::Missing)? {
+            true => Ok(()),
+            false => Err(DbError::ChecksumMismatch(path.to_string())),
+        }
+    }
+
+    fn write_checksum(&self, path: &str, data: &[u8]) -> std::io::Result<()> {
+        let checksum_path = std::path::PathBuf::from(Db::<K, V>::get_checksum_path(&self.name, path));
+        std::fs::create_dir_all(checksum_path.parent().unwrap())?;
+        std::fs::write(checksum_path, get_checksum(data))
+    }
+
+    // A checksum left from a write with checksums on would no longer match
+    fn remove_checksum(&self, path: &str) -> std::io::Result<()> {
+        match std::fs::remove_file(Db::<K, V>::get_checksum_path(&self.name, path)) {
+            Err(e) if e.kind() != std::io::ErrorKind::NotFound => Err(e),
+            _ => Ok(()),
+        }
+    }
+
+    // outside the db folder like the final markers, path is the value file
+    fn get_checksum_path(name: &str, path: &str) -> String {
+        let db_folder = format!("{}/{}/", CONFIG.db_path, name);
+        let safe_key = path.strip_prefix(&db_folder).unwrap_or(path);
+        format!("{}/checksums/{name}/{safe_key}", CONFIG.db_path)
+    }
+
+    fn is_final(&self, key: &str) -> bool {
+        std::path::Path::new(&self.get_final_path(key)).exists()
+    }
+
     // pub fn listen(&self) -> tokio::sync::broadcast::Receiver<(K, V)> {
     //     self.sender.subscribe()
     // }
 
-    fn read_file(path: &str) -> Option<V> {
+    fn read_file(path: &str, version: Option<u32>, migration: Option<Migration>) -> Option<V> {
         let before = Instant::now();
-        let data = std::fs::read_to_string(path).ok()?;
-        let res = match serde_json::from_str(&data) {
+        let data = Db::<K, V>::read_data(path)?;
+        let parsed = match version {
+            Some(version) => Db::<K, V>::unwrap_version(path, &data, version, migration)
+                .map(serde_json::from_value)?,
+            None => serde_json::from_str(&data),
+        };
+        let res = match parsed {
             Ok(e) => Some(e),
             Err(e) => {
                 log::error!("[DB] Read failed {} {}", path, e);
@@ -110,7 +357,381 @@ impl<K: Display + Clone, V: DeserializeOwned + Serialize + Clone> Db<K, V> {
         res
     }
 
+    fn unwrap_version(path: &str, data: &str, version: u32, migration: Option<Migration>) -> Option<serde_json::Value> {
+        let mut envelope: serde_json::Value = serde_json::from_str(data).ok()?;
+        let (stored_version, value) = match (envelope.get("version").and_then(|e| e.as_u64()), envelope.get_mut("value")) {
+            (Some(stored_version), Some(value)) => (stored_version as u32, value.take()),
+            _ => (0, envelope),
+        };
+        if stored_version == version {
+            return Some(value);
+        }
+        let migrated = migration.filter(|_| stored_version < version).and_then(|e| e(stored_version, value));
+        match migrated {
+            Some(_) => log::info!("[DB] Migrated {path} {stored_version} -> {version}"),
+            None => log::info!("[DB] Version mismatch {path} {stored_version} != {version}"),
+        }
+        migrated
+    }
+
+    fn read_data(path: &str) -> Option<String> {
+        let bytes = std::fs::read(path).ok()?;
+        if bytes.starts_with(&GZIP_MAGIC) {
+            let mut data = String::new();
+            match GzDecoder::new(bytes.as_slice()).read_to_string(&mut data) {
+                Ok(_) => Some(data),
+                Err(e) => {
+                    log::error!("[DB] Decompress failed {} {}", path, e);
+                    None
+                }
+            }
+        } else {
+            String::from_utf8(bytes).ok()
+        }
+    }
+
+    fn get_lock(&self, key: &str) -> Arc<Mutex<()>> {
+        let mut locks = KEY_LOCKS.lock().unwrap_or_else(|e| e.into_inner());
+        locks.entry((self.name.clone(), key.to_string())).or_default().clone()
+    }
+
     fn get_path(&self, key: &str) -> String {
-        format!("{}/{}/{}", CONFIG.db_path, self.name, key)
+        format!("{}/{}/{}", CONFIG.db_path, self.name, get_safe_key(key))
+    }
+
+    // outside the db folder, so read_all doesn't pick up the markers
+    fn get_final_path(&self, key: &str) -> String {
+        format!("{}/final/{}/{}", CONFIG.db_path, self.name, get_safe_key(key))
+    }
+
+    fn get_reverse_path(&self, safe_key: &str) -> String {
+        format!("{}/keys/{}/{}", CONFIG.db_path, self.name, safe_key)
+    }
+
+    // For debugging, the key a hashed file name was written with
+    pub fn original_key(&self, safe_key: &str) -> Option<String> {
+        std::fs::read_to_string(self.get_reverse_path(safe_key)).ok()
+    }
+
+    fn write_reverse_key(&self, key: &str) {
+        let safe_key = get_safe_key(key);
+        if safe_key == key {
+            return;
+        }
+        let path = std::path::PathBuf::from(self.get_reverse_path(&safe_key));
+        if !path.exists() {
+            _ = std::fs::create_dir_all(path.parent().unwrap()).and_then(|_| std::fs::write(path, key));
+        }
+    }
+}
+
+const MAX_KEY_LEN: usize = 100;
+
+fn open_reader(path: &str) -> Option<Box<dyn BufRead>> {
+    let mut file = BufReader::new(File::open(path).ok()?);
+    let compressed = file.fill_buf().ok()?.starts_with(&GZIP_MAGIC);
+    Some(match compressed {
+        true => Box::new(BufReader::new(GzDecoder::new(file))),
+        false => Box::new(file),
+    })
+}
+
+// Items are parsed straight from the reader. They're objects or arrays, ending on a closing
+// bracket, so the parser never reads into the separator after them
+struct ArrayItems<T> {
+    reader: Option<Box<dyn BufRead>>,
+    started: bool,
+    path: String,
+    item_type: std::marker::PhantomData<T>,
+}
+
+impl<T> ArrayItems<T> {
+    fn next_byte(reader: &mut dyn BufRead) -> Option<u8> {
+        let mut byte = [0u8];
+        loop {
+            reader.read_exact(&mut byte).ok()?;
+            if !byte[0].is_ascii_whitespace() {
+                return Some(byte[0]);
+            }
+        }
+    }
+}
+
+impl<T: DeserializeOwned> Iterator for ArrayItems<T> {
+    type Item = T;
+    fn next(&mut self) -> Option<T> {
+        let reader = self.reader.as_mut()?;
+        let mut byte = ArrayItems::<T>::next_byte(reader.as_mut());
+        if !self.started {
+            self.started = true;
+            byte = byte.filter(|e| *e == b'[').and_then(|_| ArrayItems::<T>::next_byte(reader.as_mut()));
+        } else if byte == Some(b',') {
+            byte = ArrayItems::<T>::next_byte(reader.as_mut());
+        }
+        let item = match byte {
+            Some(b']') | None => None,
+            Some(first) => {
+                let first = [first];
+                let mut de = serde_json::Deserializer::from_reader(first.as_slice().chain(reader.as_mut()));
+                T::deserialize(&mut de).map_err(|e| log::error!("[DB] Stream failed {} {}", self.path, e)).ok()
+            },
+        };
+        if item.is_none() {
+            self.reader = None;
+        }
+        item
+    }
+}
+
+// Urls and other long or odd keys become the last path segments + a hash, so they are valid file names
+fn get_safe_key(key: &str) -> String {
+    let is_safe = key.len() <= MAX_KEY_LEN
+        && !key.split('/').any(|e| e.is_empty() || e == "..")
+        && key.chars().all(|c| c.is_ascii_alphanumeric() || matches!(c, '-' | '_' | '.' | '/'));
+    if is_safe {
+        return key.to_string();
+    }
+    let mut segments: Vec<String> = key.split(['/', '?', '&'])
+        .map(|e| e.chars().filter(|c| c.is_ascii_alphanumeric() || matches!(c, '-' | '_')).collect::<String>())
+        .filter(|e| !e.is_empty())
+        .rev()
+        .take(2)
+        .collect();
+    segments.reverse();
+    let prefix: String = segments.join("-").chars().take(48).collect();
+    format!("{prefix}-{:032x}", get_stable_hash(key))
+}
+
+fn get_checksum(data: &[u8]) -> String {
+    let mut crc = Crc::new();
+    crc.update(data);
+    format!("{:08x}", crc.sum())
+}
+
+// Read in chunks, so verifying a large value before streaming it doesn't load it whole
+fn get_file_checksum(path: &str) -> std::io::Result<String> {
+    let mut reader = CrcReader::new(File::open(path)?);
+    std::io::copy(&mut reader, &mut std::io::sink())?;
+    Ok(format!("{:08x}", reader.crc().sum()))
+}
+
+// FNV-1a, unlike DefaultHasher it is the same across Rust versions
+fn get_stable_hash(key: &str) -> u128 {
+    const OFFSET: u128 = 0x6c62272e07bb014262b821756295c58d;
+    const PRIME: u128 = 0x0000000001000000000000000000013b;
+    key.bytes().fold(OFFSET, |hash, b| (hash ^ b as u128).wrapping_mul(PRIME))
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{sync::{Arc, Mutex}, time::{Duration, SystemTime}};
+
+    use tempdir::TempDir;
+
+    use super::{Db, DbError, GZIP_MAGIC, get_safe_key, Clock};
+
+    struct FakeClock(Mutex<SystemTime>);
+    impl FakeClock {
+        fn advance(&self, delta: Duration) {
+            *self.0.lock().unwrap() += delta;
+        }
+    }
+    impl Clock for FakeClock {
+        fn now(&self) -> SystemTime {
+            *self.0.lock().unwrap()
+        }
+    }
+
+    fn before() {
+        std::env::set_var("DB_PATH", TempDir::new("test").expect("dir to be created").path().to_str().unwrap());
+    }
+
+    #[test]
+    fn compressed_round_trip() {
+        before();
+        let db = Db::<String, Vec<String>>::new_compressed("test_compressed");
+        let key = "round_trip".to_string();
+        let value = vec!["Åke".to_string(), "Öberg".to_string()];
+        db.write(&key, &value).unwrap();
+
+        let bytes = std::fs::read(db.get_path(&key)).unwrap();
+        assert!(bytes.starts_with(&GZIP_MAGIC));
+        assert_eq!(db.read(&key), Some(value));
+        assert_eq!(db.read_raw(&key), r#"["Åke","Öberg"]"#);
+    }
+
+    #[test]
+    fn old_version_is_missing() {
+        before();
+        let key = "versioned".to_string();
+        let old_db = Db::<String, Vec<String>>::new("test_versioned").with_version(1);
+        let new_db = Db::<String, Vec<String>>::new("test_versioned").with_version(2);
+        old_db.write(&key, &vec!["old".to_string()]).unwrap();
+
+        assert_eq!(old_db.read(&key), Some(vec!["old".to_string()]));
+        assert_eq!(old_db.read_raw(&key), r#"["old"]"#);
+        assert!(!old_db.is_stale(&key, None));
+
+        assert_eq!(new_db.read(&key), None);
+        assert_eq!(new_db.read_raw(&key), "");
+        assert!(new_db.is_stale(&key, None));
+
+        let unversioned_key = "unversioned".to_string();
+        Db::<String, Vec<String>>::new("test_versioned").write(&unversioned_key, &vec!["plain".to_string()]).unwrap();
+        assert_eq!(new_db.read(&unversioned_key), None);
+    }
+
+    #[test]
+    fn unversioned_is_migrated() {
+        before();
+        let key = "unversioned".to_string();
+        let migration: super::Migration = |version, value| match version {
+            0 => Some(serde_json::json!(value.as_array()?.iter().map(|e| format!("{}!", e.as_str().unwrap_or_default())).collect::<Vec<String>>())),
+            _ => None,
+        };
+        let versioned = Db::<String, Vec<String>>::new("test_migrated").with_version(1).with_migration(migration);
+        Db::<String, Vec<String>>::new("test_migrated").write(&key, &vec!["plain".to_string()]).unwrap();
+
+        assert_eq!(versioned.read(&key), Some(vec!["plain!".to_string()]));
+        assert!(!versioned.is_stale(&key, None));
+        versioned.write(&key, &vec!["new".to_string()]).unwrap();
+        assert_eq!(versioned.read(&key), Some(vec!["new".to_string()]));
+
+        // newer than the code are still missing
+        let older = Db::<String, Vec<String>>::new("test_migrated").with_version(0).with_migration(migration);
+        assert_eq!(older.read(&key), None);
+    }
+
+    #[test]
+    fn write_if_changed_keeps_mtime() {
+        before();
+        let db = Db::<String, Vec<String>>::new("test_changed");
+        let key = "stats".to_string();
+        let value = vec!["1".to_string()];
+        assert!(db.write_if_changed(&key, &value).unwrap());
+        let mtime = std::fs::metadata(db.get_path(&key)).unwrap().modified().unwrap();
+
+        std::thread::sleep(std::time::Duration::from_millis(20));
+        assert!(!db.write_if_changed(&key, &value).unwrap());
+        assert_eq!(std::fs::metadata(db.get_path(&key)).unwrap().modified().unwrap(), mtime);
+
+        assert!(db.write_if_changed(&key, &vec!["2".to_string()]).unwrap());
+        assert_ne!(std::fs::metadata(db.get_path(&key)).unwrap().modified().unwrap(), mtime);
+    }
+
+    #[test]
+    fn instances_share_key_locks() {
+        before();
+        let key = "shared".to_string();
+        let handles: Vec<_> = (0..2).map(|i| {
+            let key = key.clone();
+            std::thread::spawn(move || {
+                let db = Db::<String, Vec<String>>::new("test_shared_locks");
+                for n in 0..50 {
+                    db.update(&key, |e| e.push(format!("{i}-{n}")));
+                }
+            })
+        }).collect();
+        handles.into_iter().for_each(|e| e.join().unwrap());
+
+        let stored = Db::<String, Vec<String>>::new("test_shared_locks").read(&key).unwrap();
+        assert_eq!(stored.len(), 100);
+    }
+
+    #[test]
+    fn urls_get_safe_keys() {
+        before();
+        let long = "a".repeat(200);
+        let first = format!("Season2022/https://example.com/gameday/boxscore/{long}?x=1");
+        let second = format!("Season2022/https://example.com/gameday/boxscore/{long}?x=2");
+        let first_key = get_safe_key(&first);
+        let second_key = get_safe_key(&second);
+        assert_ne!(first_key, second_key);
+        assert!(first_key.len() < 100);
+        assert!(first_key.chars().all(|c| c.is_ascii_alphanumeric() || c == '-'));
+        assert_eq!(get_safe_key("123/Season2022/LHF"), "123/Season2022/LHF");
+        assert_ne!(get_safe_key("../etc"), "../etc");
+
+        let db = Db::<String, String>::new("test_safe_keys");
+        db.write(&first, &"first".to_string()).unwrap();
+        db.write(&second, &"second".to_string()).unwrap();
+        assert_eq!(db.read(&first), Some("first".to_string()));
+        assert_eq!(db.read(&second), Some("second".to_string()));
+        assert_eq!(db.original_key(&first_key), Some(first));
+        assert_eq!(db.original_key(&second_key), Some(second));
+    }
+
+    #[test]
+    fn last_updated_is_recent() {
+        before();
+        let db = Db::<String, String>::new("test_last_updated");
+        let key = "recent".to_string();
+        assert_eq!(db.last_updated(&key), None);
+        db.write(&key, &"value".to_string()).unwrap();
+        let age = SystemTime::now().duration_since(db.last_updated(&key).unwrap()).unwrap_or_default();
+        assert!(age < Duration::from_secs(5), "{age:?}");
+    }
+
+    #[test]
+    fn stale_after_fake_clock_advances() {
+        before();
+        let clock = Arc::new(FakeClock(Mutex::new(SystemTime::now())));
+        let db = Db::<String, String>::new("test_clock").with_clock(clock.clone());
+        let key = "throttled".to_string();
+        db.write(&key, &"value".to_string()).unwrap();
+        assert!(!db.is_stale(&key, Some(Duration::from_secs(30))));
+
+        clock.advance(Duration::from_secs(31));
+        assert!(db.is_stale(&key, Some(Duration::from_secs(30))));
+        assert!(!db.is_stale(&key, Some(Duration::from_secs(60))));
+        assert!(!db.is_stale(&key, None));
+    }
+
+    #[test]
+    fn compressed_reads_uncompressed() {
+        before();
+        let db = Db::<String, Vec<String>>::new_compressed("test_compressed");
+        let key = "old_entry".to_string();
+        let path = std::path::PathBuf::from(db.get_path(&key));
+        std::fs::create_dir_all(path.parent().unwrap()).unwrap();
+        std::fs::write(&path, r#"["old"]"#).unwrap();
+
+        assert_eq!(db.read(&key), Some(vec!["old".to_string()]));
+    }
+
+    #[test]
+    fn flipped_byte_fails_checksum() {
+        before();
+        let db = Db::<String, Vec<String>>::new("test_checksum").with_checksum();
+        let key = "corrupt".to_string();
+        db.write(&key, &vec!["value".to_string()]).unwrap();
+        assert_eq!(db.read_checked(&key), Ok(vec!["value".to_string()]));
+        assert!(!db.is_stale(&key, None));
+
+        let path = db.get_path(&key);
+        let mut data = std::fs::read(&path).unwrap();
+        data[3] ^= 0x01;
+        std::fs::write(&path, data).unwrap();
+
+        assert_eq!(db.read_checked(&key), Err(DbError::ChecksumMismatch(path)));
+        assert_eq!(db.read(&key), None);
+        assert!(db.is_stale(&key, None));
+        assert!(db.read_all().is_empty());
+        assert_eq!(db.read_raw(&key), "");
+        // without the opt-in the file is read as is
+        assert_eq!(Db::<String, Vec<String>>::new("test_checksum").read(&key), Some(vec!["v`lue".to_string()]));
+    }
+
+    #[test]
+    fn write_without_checksum_removes_it() {
+        before();
+        let db = Db::<String, Vec<String>>::new("test_checksum_removed").with_checksum();
+        let key = "written_twice".to_string();
+        db.write(&key, &vec!["first".to_string()]).unwrap();
+        Db::<String, Vec<String>>::new("test_checksum_removed").write(&key, &vec!["second".to_string()]).unwrap();
+
+        assert_eq!(db.read_checked(&key), Ok(vec!["second".to_string()]));
+        assert!(!db.is_stale(&key, None));
+        assert_eq!(db.read_raw(&key), r#"["second"]"#);
     }
 }
@@ -0,0 +1,113 @@
+use serde::{Serialize, Deserialize};
+
+use crate::{event_service::{ApiGameEvent, ApiEventType}, game_report_service::GameStatus, models::GamePeriod};
+
+// A revision of an already sent event, with only the fields that changed
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct ApiGameEventDelta {
+    pub game_uuid: String,
+    pub event_id: String,
+    pub revision: u16,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub status: Option<GameStatus>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub period: Option<GamePeriod>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub gametime: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub description: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub info: Option<ApiEventType>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub tags: Option<Vec<String>>,
+}
+
+fn changed<T: PartialEq + Clone>(prior: &T, current: &T) -> Option<T> {
+    (prior != current).then(|| current.clone())
+}
+
+impl ApiGameEventDelta {
+    // None if the events aren't the same event
+    pub fn new(prior: &ApiGameEvent, current: &ApiGameEvent) -> Option<ApiGameEventDelta> {
+        if prior.game_uuid != current.game_uuid || prior.key() != current.key() {
+            return None;
+        }
+        Some(ApiGameEventDelta {
+            game_uuid: current.game_uuid.clone(),
+            event_id: current.event_id.clone(),
+            revision: current.revision,
+            status: changed(&prior.status, &current.status),
+            period: changed(&prior.period, &current.period),
+            gametime: changed(&prior.gametime, &current.gametime),
+            description: changed(&prior.description, &current.description),
+            info: changed(&prior.info, &current.info),
+            tags: changed(&prior.tags, &current.tags),
+        })
+    }
+
+    // Client side reconstruction of the full event from the prior one, what clients do with a delta
+    #[cfg(test)]
+    pub fn apply(&self, prior: &ApiGameEvent) -> Option<ApiGameEvent> {
+        if prior.game_uuid != self.game_uuid || prior.key() != crate::event_service::EventKey::new(&self.event_id) {
+            return None;
+        }
+        Some(ApiGameEvent {
+            game_uuid: self.game_uuid.clone(),
+            event_id: self.event_id.clone(),
+            revision: self.revision,
+            status: self.status.clone().unwrap_or_else(|| prior.status.clone()),
+            period: self.period.unwrap_or(prior.period),
+            gametime: self.gametime.clone().unwrap_or_else(|| prior.gametime.clone()),
+            description: self.description.clone().unwrap_or_else(|| prior.description.clone()),
+            info: self.info.clone().unwrap_or_else(|| prior.info.clone()),
+            tags: self.tags.clone().unwrap_or_else(|| prior.tags.clone()),
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{models2::external::event::PlayByPlay, event_service::{ApiGameEvent, ApiEventType}};
+
+    use super::ApiGameEventDelta;
+
+    #[test]
+    fn delta_for_revised_goal() {
+        let prior = get_goal(1, "Olle Olsson", "");
+        let current = get_goal(2, "Olle Olsson", "2 Nils Nilsson");
+
+        let delta = ApiGameEventDelta::new(&prior, &current).unwrap();
+        assert_eq!(delta.revision, 2);
+        assert_eq!(delta.gametime, None);
+        assert_eq!(delta.description, None);
+        assert!(delta.info.is_some());
+
+        let json = serde_json::to_value(&delta).unwrap();
+        assert!(json.get("gametime").is_none());
+        let parsed: ApiGameEventDelta = serde_json::from_value(json).unwrap();
+
+        let rebuilt = parsed.apply(&prior).unwrap();
+        assert_eq!(serde_json::to_value(&rebuilt).unwrap(), serde_json::to_value(&current).unwrap());
+        match rebuilt.info {
+            ApiEventType::Goal(g) => assert_eq!(g.assist.unwrap(), "2 Nils Nilsson"),
+            _ => panic!("not good"),
+        }
+    }
+
+    #[test]
+    fn delta_requires_same_event() {
+        let prior = get_goal(1, "Olle Olsson", "");
+        let mut other = get_goal(1, "Olle Olsson", "");
+        other.event_id = "13".to_string();
+        assert_eq!(ApiGameEventDelta::new(&prior, &other), None);
+    }
+
+    fn get_goal(revision: u16, scorer: &str, assist: &str) -> ApiGameEvent {
+        let json = format!(r#"{{
+            "eventId": 12, "revision": {revision}, "hash": "abc", "period": 1, "gametime": "12:34", "description": "Mål",
+            "class": "Goal", "team": "LHF", "location": {{ "x": 1.0, "y": 2.0 }},
+            "extra": {{ "scorerLong": "1 {scorer}", "teamAdvantage": "EQ", "homeAgainst": 0, "homeForward": 1, "assist": "{assist}" }}
+        }}"#);
+        serde_json::from_str::<PlayByPlay>(&json).unwrap().into_mapped_event("game_uuid")
+    }
+}
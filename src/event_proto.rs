@@ -0,0 +1,460 @@
+use prost::Message;
+
+use crate::{event_service::{ApiGameEvent, ApiEventType, GoalInfo, PenaltyInfo, ShotInfo, ShotKind, PenaltyReason, HitInfo, GameEndInfo, Player, Location}, game_report_service::GameStatus, models::GamePeriod};
+
+pub const PROTOBUF_CONTENT_TYPE: &str = "application/x-protobuf";
+
+// Protobuf of ApiGameEvent, for clients following many games. Tags must never be reused,
+// add new fields with new tags. Optional strings are proto3 optional to tell None from ""
+#[derive(Clone, PartialEq, Message)]
+pub struct ProtoGameEvents {
+    #[prost(message, repeated, tag = "1")]
+    pub events: Vec<ProtoGameEvent>,
+}
+
+#[derive(Clone, PartialEq, Message)]
+pub struct ProtoGameEvent {
+    #[prost(string, tag = "1")]
+    pub game_uuid: String,
+    #[prost(string, tag = "2")]
+    pub event_id: String,
+    #[prost(uint32, tag = "3")]
+    pub revision: u32,
+    #[prost(enumeration = "ProtoGameStatus", tag = "4")]
+    pub status: i32,
+    // the feed period number, like the json
+    #[prost(sint32, tag = "5")]
+    pub period: i32,
+    #[prost(string, tag = "6")]
+    pub gametime: String,
+    #[prost(string, tag = "7")]
+    pub description: String,
+    #[prost(string, repeated, tag = "8")]
+    pub tags: Vec<String>,
+    #[prost(oneof = "ProtoEventInfo", tags = "10, 11, 12, 13, 14, 15, 16, 17, 18, 19, 20, 21, 22")]
+    pub info: Option<ProtoEventInfo>,
+}
+
+#[derive(Clone, PartialEq, prost::Oneof)]
+pub enum ProtoEventInfo {
+    #[prost(message, tag = "10")]
+    Goal(ProtoGoal),
+    #[prost(message, tag = "11")]
+    PeriodEnd(ProtoEmpty),
+    #[prost(message, tag = "12")]
+    PeriodStart(ProtoEmpty),
+    #[prost(message, tag = "13")]
+    GameEnd(ProtoGameEnd),
+    #[prost(message, tag = "14")]
+    GameStart(ProtoEmpty),
+    #[prost(message, tag = "15")]
+    Penalty(ProtoPenalty),
+    #[prost(message, tag = "16")]
+    Shot(ProtoShot),
+    #[prost(message, tag = "17")]
+    Hit(ProtoHit),
+    #[prost(message, tag = "18")]
+    Timeout(ProtoEmpty),
+    #[prost(message, tag = "19")]
+    Review(ProtoEmpty),
+    #[prost(message, tag = "20")]
+    Challenge(ProtoEmpty),
+    #[prost(message, tag = "21")]
+    General(ProtoEmpty),
+    #[prost(message, tag = "22")]
+    OvertimeStart(ProtoEmpty),
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, prost::Enumeration)]
+#[repr(i32)]
+pub enum ProtoGameStatus {
+    Coming = 0,
+    Finished = 1,
+    Period1 = 2,
+    Period2 = 3,
+    Period3 = 4,
+    Overtime = 5,
+    Shootout = 6,
+    Intermission = 7,
+    Postponed = 8,
+    Cancelled = 9,
+}
+
+#[derive(Clone, PartialEq, Message)]
+pub struct ProtoEmpty {}
+
+#[derive(Clone, PartialEq, Message)]
+pub struct ProtoPlayer {
+    #[prost(string, tag = "1")]
+    pub first_name: String,
+    #[prost(string, tag = "2")]
+    pub family_name: String,
+    #[prost(int32, tag = "3")]
+    pub jersey: i32,
+}
+
+#[derive(Clone, PartialEq, Message)]
+pub struct ProtoLocation {
+    #[prost(float, tag = "1")]
+    pub x: f32,
+    #[prost(float, tag = "2")]
+    pub y: f32,
+}
+
+#[derive(Clone, PartialEq, Message)]
+pub struct ProtoGoal {
+    #[prost(string, tag = "1")]
+    pub team: String,
+    #[prost(message, optional, tag = "2")]
+    pub player: Option<ProtoPlayer>,
+    #[prost(int32, optional, tag = "3")]
+    pub player_id: Option<i32>,
+    #[prost(string, tag = "4")]
+    pub team_advantage: String,
+    #[prost(string, optional, tag = "5")]
+    pub assist: Option<String>,
+    #[prost(int32, repeated, tag = "6")]
+    pub assist_ids: Vec<i32>,
+    #[prost(bool, tag = "7")]
+    pub unassisted: bool,
+    #[prost(int32, tag = "8")]
+    pub home_team_result: i32,
+    #[prost(int32, tag = "9")]
+    pub away_team_result: i32,
+    #[prost(message, optional, tag = "10")]
+    pub location: Option<ProtoLocation>,
+    #[prost(string, optional, tag = "11")]
+    pub media_url: Option<String>,
+    #[prost(bool, tag = "12")]
+    pub shootout: bool,
+    #[prost(bool, tag = "13")]
+    pub own_goal: bool,
+    #[prost(bool, tag = "14")]
+    pub scorer_unknown: bool,
+    #[prost(bool, tag = "15")]
+    pub quick_strike: bool,
+    #[prost(int32, optional, tag = "16")]
+    pub quick_strike_s: Option<i32>,
+}
+
+#[derive(Clone, PartialEq, Message)]
+pub struct ProtoGameEnd {
+    #[prost(string, optional, tag = "1")]
+    pub winner: Option<String>,
+    #[prost(bool, tag = "2")]
+    pub is_final: bool,
+}
+
+#[derive(Clone, PartialEq, Message)]
+pub struct ProtoPenalty {
+    #[prost(string, tag = "1")]
+    pub team: String,
+    #[prost(message, optional, tag = "2")]
+    pub player: Option<ProtoPlayer>,
+    #[prost(int32, optional, tag = "3")]
+    pub player_id: Option<i32>,
+    #[prost(string, tag = "4")]
+    pub reason: String,
+    #[prost(string, optional, tag = "5")]
+    pub penalty: Option<String>,
+    #[prost(bool, tag = "6")]
+    pub bench: bool,
+    // PenaltyReason name, empty for Unknown
+    #[prost(string, tag = "7")]
+    pub reason_code: String,
+}
+
+#[derive(Clone, PartialEq, Message)]
+pub struct ProtoShot {
+    #[prost(string, tag = "1")]
+    pub team: String,
+    #[prost(message, optional, tag = "2")]
+    pub location: Option<ProtoLocation>,
+    #[prost(enumeration = "ProtoShotKind", tag = "3")]
+    pub kind: i32,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, prost::Enumeration)]
+#[repr(i32)]
+pub enum ProtoShotKind {
+    OnGoal = 0,
+    Blocked = 1,
+    Wide = 2,
+    Iron = 3,
+    Penalty = 4,
+}
+
+#[derive(Clone, PartialEq, Message)]
+pub struct ProtoHit {
+    #[prost(string, tag = "1")]
+    pub team: String,
+    #[prost(message, optional, tag = "2")]
+    pub player: Option<ProtoPlayer>,
+}
+
+impl From<&Player> for ProtoPlayer {
+    fn from(e: &Player) -> Self {
+        ProtoPlayer { first_name: e.first_name.clone(), family_name: e.family_name.clone(), jersey: e.jersey }
+    }
+}
+impl From<ProtoPlayer> for Player {
+    fn from(e: ProtoPlayer) -> Self {
+        Player { first_name: e.first_name, family_name: e.family_name, jersey: e.jersey }
+    }
+}
+
+impl From<&Location> for ProtoLocation {
+    fn from(e: &Location) -> Self {
+        ProtoLocation { x: e.x, y: e.y }
+    }
+}
+impl From<ProtoLocation> for Location {
+    fn from(e: ProtoLocation) -> Self {
+        Location { x: e.x, y: e.y }
+    }
+}
+
+impl From<&GameStatus> for ProtoGameStatus {
+    fn from(e: &GameStatus) -> Self {
+        match e {
+            GameStatus::Coming => ProtoGameStatus::Coming,
+            GameStatus::Finished => ProtoGameStatus::Finished,
+            GameStatus::Period1 => ProtoGameStatus::Period1,
+            GameStatus::Period2 => ProtoGameStatus::Period2,
+            GameStatus::Period3 => ProtoGameStatus::Period3,
+            GameStatus::Overtime => ProtoGameStatus::Overtime,
+            GameStatus::Shootout => ProtoGameStatus::Shootout,
+            GameStatus::Intermission => ProtoGameStatus::Intermission,
+            GameStatus::Postponed => ProtoGameStatus::Postponed,
+            GameStatus::Cancelled => ProtoGameStatus::Cancelled,
+        }
+    }
+}
+impl From<ProtoGameStatus> for GameStatus {
+    fn from(e: ProtoGameStatus) -> Self {
+        match e {
+            ProtoGameStatus::Coming => GameStatus::Coming,
+            ProtoGameStatus::Finished => GameStatus::Finished,
+            ProtoGameStatus::Period1 => GameStatus::Period1,
+            ProtoGameStatus::Period2 => GameStatus::Period2,
+            ProtoGameStatus::Period3 => GameStatus::Period3,
+            ProtoGameStatus::Overtime => GameStatus::Overtime,
+            ProtoGameStatus::Shootout => GameStatus::Shootout,
+            ProtoGameStatus::Intermission => GameStatus::Intermission,
+            ProtoGameStatus::Postponed => GameStatus::Postponed,
+            ProtoGameStatus::Cancelled => GameStatus::Cancelled,
+        }
+    }
+}
+
+impl From<ShotKind> for ProtoShotKind {
+    fn from(e: ShotKind) -> Self {
+        match e {
+            ShotKind::OnGoal => ProtoShotKind::OnGoal,
+            ShotKind::Blocked => ProtoShotKind::Blocked,
+            ShotKind::Wide => ProtoShotKind::Wide,
+            ShotKind::Iron => ProtoShotKind::Iron,
+            ShotKind::Penalty => ProtoShotKind::Penalty,
+        }
+    }
+}
+impl From<ProtoShotKind> for ShotKind {
+    fn from(e: ProtoShotKind) -> Self {
+        match e {
+            ProtoShotKind::OnGoal => ShotKind::OnGoal,
+            ProtoShotKind::Blocked => ShotKind::Blocked,
+            ProtoShotKind::Wide => ShotKind::Wide,
+            ProtoShotKind::Iron => ShotKind::Iron,
+            ProtoShotKind::Penalty => ShotKind::Penalty,
+        }
+    }
+}
+
+impl From<&ApiEventType> for ProtoEventInfo {
+    fn from(e: &ApiEventType) -> Self {
+        match e {
+            ApiEventType::Goal(g) => ProtoEventInfo::Goal(ProtoGoal {
+                team: g.team.clone(),
+                player: g.player.as_ref().map(ProtoPlayer::from),
+                player_id: g.player_id,
+                team_advantage: g.team_advantage.clone(),
+                assist: g.assist.clone(),
+                assist_ids: g.assist_ids.clone(),
+                unassisted: g.unassisted,
+                home_team_result: g.home_team_result as i32,
+                away_team_result: g.away_team_result as i32,
+                location: Some(ProtoLocation::from(&g.location)),
+                media_url: g.media_url.clone(),
+                shootout: g.shootout,
+                own_goal: g.own_goal,
+                scorer_unknown: g.scorer_unknown,
+                quick_strike: g.quick_strike,
+                quick_strike_s: g.quick_strike_s,
+            }),
+            ApiEventType::PeriodEnd => ProtoEventInfo::PeriodEnd(ProtoEmpty {}),
+            ApiEventType::PeriodStart => ProtoEventInfo::PeriodStart(ProtoEmpty {}),
+            ApiEventType::OvertimeStart => ProtoEventInfo::OvertimeStart(ProtoEmpty {}),
+            ApiEventType::GameEnd(g) => ProtoEventInfo::GameEnd(ProtoGameEnd { winner: g.winner.clone(), is_final: g.is_final }),
+            ApiEventType::GameStart => ProtoEventInfo::GameStart(ProtoEmpty {}),
+            ApiEventType::Penalty(p) => ProtoEventInfo::Penalty(ProtoPenalty {
+                team: p.team.clone(),
+                player: p.player.as_ref().map(ProtoPlayer::from),
+                player_id: p.player_id,
+                reason: p.reason.clone(),
+                reason_code: get_reason_code(&p.reason_code),
+                penalty: p.penalty.clone(),
+                bench: p.bench,
+            }),
+            ApiEventType::Shot(s) => ProtoEventInfo::Shot(ProtoShot {
+                team: s.team.clone(),
+                location: Some(ProtoLocation::from(&s.location)),
+                kind: ProtoShotKind::from(s.kind) as i32,
+            }),
+            ApiEventType::Hit(h) => ProtoEventInfo::Hit(ProtoHit { team: h.team.clone(), player: h.player.as_ref().map(ProtoPlayer::from) }),
+            ApiEventType::Timeout => ProtoEventInfo::Timeout(ProtoEmpty {}),
+            ApiEventType::Review => ProtoEventInfo::Review(ProtoEmpty {}),
+            ApiEventType::Challenge => ProtoEventInfo::Challenge(ProtoEmpty {}),
+            ApiEventType::General => ProtoEventInfo::General(ProtoEmpty {}),
+        }
+    }
+}
+
+fn get_reason_code(reason: &PenaltyReason) -> String {
+    match reason {
+        PenaltyReason::Unknown => "".to_string(),
+        e => serde_json::to_value(e).ok().and_then(|e| e.as_str().map(|e| e.to_string())).unwrap_or_default(),
+    }
+}
+
+fn parse_reason_code(code: &str) -> PenaltyReason {
+    serde_json::from_value(serde_json::Value::String(code.to_string())).unwrap_or_default()
+}
+
+fn get_location(location: Option<ProtoLocation>) -> Location {
+    location.map(Location::from).unwrap_or(Location { x: 0.0, y: 0.0 })
+}
+
+// Unknown or missing infos, from a newer schema, are General
+impl From<Option<ProtoEventInfo>> for ApiEventType {
+    fn from(e: Option<ProtoEventInfo>) -> Self {
+        match e {
+            Some(ProtoEventInfo::Goal(g)) => ApiEventType::Goal(GoalInfo {
+                team: g.team,
+                player: g.player.map(Player::from),
+                player_id: g.player_id,
+                team_advantage: g.team_advantage,
+                assist: g.assist,
+                assist_ids: g.assist_ids,
+                unassisted: g.unassisted,
+                home_team_result: g.home_team_result as i16,
+                away_team_result: g.away_team_result as i16,
+                location: get_location(g.location),
+                media_url: g.media_url,
+                shootout: g.shootout,
+                own_goal: g.own_goal,
+                scorer_unknown: g.scorer_unknown,
+                quick_strike: g.quick_strike,
+                quick_strike_s: g.quick_strike_s,
+            }),
+            Some(ProtoEventInfo::PeriodEnd(_)) => ApiEventType::PeriodEnd,
+            Some(ProtoEventInfo::PeriodStart(_)) => ApiEventType::PeriodStart,
+            Some(ProtoEventInfo::OvertimeStart(_)) => ApiEventType::OvertimeStart,
+            Some(ProtoEventInfo::GameEnd(g)) => ApiEventType::GameEnd(GameEndInfo { winner: g.winner, is_final: g.is_final }),
+            Some(ProtoEventInfo::GameStart(_)) => ApiEventType::GameStart,
+            Some(ProtoEventInfo::Penalty(p)) => ApiEventType::Penalty(PenaltyInfo {
+                team: p.team,
+                player: p.player.map(Player::from),
+                player_id: p.player_id,
+                reason_code: parse_reason_code(&p.reason_code),
+                reason: p.reason,
+                penalty: p.penalty,
+                bench: p.bench,
+            }),
+            Some(ProtoEventInfo::Shot(s)) => ApiEventType::Shot(ShotInfo {
+                team: s.team,
+                location: get_location(s.location),
+                kind: ProtoShotKind::try_from(s.kind).unwrap_or(ProtoShotKind::OnGoal).into(),
+            }),
+            Some(ProtoEventInfo::Hit(h)) => ApiEventType::Hit(HitInfo { team: h.team, player: h.player.map(Player::from) }),
+            Some(ProtoEventInfo::Timeout(_)) => ApiEventType::Timeout,
+            Some(ProtoEventInfo::Review(_)) => ApiEventType::Review,
+            Some(ProtoEventInfo::Challenge(_)) => ApiEventType::Challenge,
+            Some(ProtoEventInfo::General(_)) | None => ApiEventType::General,
+        }
+    }
+}
+
+impl From<&ApiGameEvent> for ProtoGameEvent {
+    fn from(e: &ApiGameEvent) -> Self {
+        ProtoGameEvent {
+            game_uuid: e.game_uuid.clone(),
+            event_id: e.event_id.clone(),
+            revision: e.revision as u32,
+            status: ProtoGameStatus::from(&e.status) as i32,
+            period: i16::from(e.period) as i32,
+            gametime: e.gametime.clone(),
+            description: e.description.clone(),
+            tags: e.tags.clone(),
+            info: Some(ProtoEventInfo::from(&e.info)),
+        }
+    }
+}
+
+impl From<ProtoGameEvent> for ApiGameEvent {
+    fn from(e: ProtoGameEvent) -> Self {
+        ApiGameEvent {
+            status: ProtoGameStatus::try_from(e.status).unwrap_or(ProtoGameStatus::Coming).into(),
+            game_uuid: e.game_uuid,
+            event_id: e.event_id,
+            revision: e.revision as u16,
+            period: GamePeriod::from(e.period as i16),
+            gametime: e.gametime,
+            description: e.description,
+            info: e.info.into(),
+            tags: e.tags,
+        }
+    }
+}
+
+pub fn encode_events(events: &[ApiGameEvent]) -> Vec<u8> {
+    ProtoGameEvents { events: events.iter().map(ProtoGameEvent::from).collect() }.encode_to_vec()
+}
+
+#[cfg(test)]
+pub fn decode_events(bytes: &[u8]) -> Result<Vec<ApiGameEvent>, prost::DecodeError> {
+    Ok(ProtoGameEvents::decode(bytes)?.events.into_iter().map(ApiGameEvent::from).collect())
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::event_service::ApiGameEvent;
+
+    use super::{encode_events, decode_events};
+
+    #[test]
+    fn round_trip_preserves_all_fields() {
+        let json = r#"[
+            { "game_uuid": "proto", "event_id": "12", "revision": 3, "status": "Overtime", "period": 4, "gametime": "61:05",
+              "description": "Mål", "type": "Goal", "tags": ["game_winner"], "team": "LHF",
+              "player": { "first_name": "Olle", "family_name": "Olsson", "jersey": 1 }, "player_id": 123,
+              "team_advantage": "PP1", "assist": "9 Erik Eriksson", "assist_ids": [9], "unassisted": false,
+              "home_team_result": 3, "away_team_result": 2, "location": { "x": 1.25, "y": -2.5 },
+              "media_url": "https://example.com/clip/1", "own_goal": true, "scorer_unknown": true },
+            { "game_uuid": "proto", "event_id": "13", "revision": 1, "status": "Period2", "period": 2, "gametime": "25:00",
+              "description": "utvisas", "type": "Penalty", "team": "FHC", "player": null, "reason": "Slashing", "reason_code": "Slashing",
+              "penalty": "2 min", "bench": true },
+            { "game_uuid": "proto", "event_id": "14", "revision": 1, "status": "Finished", "period": 99, "gametime": "65:00",
+              "description": "", "type": "GameEnd", "winner": null, "is_final": true },
+            { "game_uuid": "proto", "event_id": "15", "revision": 1, "status": "Period1", "period": 1, "gametime": "01:00",
+              "description": "", "type": "Shot", "team": "LHF", "location": { "x": 0.5, "y": 0.75 }, "kind": "Wide" },
+            { "game_uuid": "proto", "event_id": "16", "revision": 1, "status": "Period1", "period": 1, "gametime": "02:00",
+              "description": "", "type": "Challenge" },
+            { "game_uuid": "proto", "event_id": "17", "revision": 1, "status": "Overtime", "period": 4, "gametime": "60:00",
+              "description": "Period 4 startade", "type": "OvertimeStart" }
+        ]"#;
+        let events: Vec<ApiGameEvent> = serde_json::from_str(json).unwrap();
+        let decoded = decode_events(&encode_events(&events)).unwrap();
+        assert_eq!(decoded, events);
+        assert_eq!(serde_json::to_value(&decoded).unwrap(), serde_json::to_value(&events).unwrap());
+    }
+}
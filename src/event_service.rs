@@ -1,8 +1,12 @@
-use std::{time::Duration, str::FromStr, fmt::{Display}};
+use std::{time::SystemTime, str::FromStr, fmt::{Display}, cmp::Ordering, ops::Range, collections::{HashMap, BTreeMap}, sync::RwLock};
 
+use futures::{Stream, Future};
+use lazy_static::lazy_static;
 use serde::{Deserialize, Serialize};
+use tokio::sync::broadcast::{Receiver, error::RecvError};
+use tracing::{log, Instrument};
 
-use crate::{db::Db, rest_client::{self}, models2::external::{event::{PlayByPlayType, Penalty, Shot, Goal}, self}, game_report_service::{GameStatus}, models::ParseStringError};
+use crate::{db::Db, rest_client::{self, ThrottlePolicy}, models2::external::{event::{PlayByPlayType, Penalty, Shot, Goal, Hit}, self}, game_report_service::{GameStatus, GameReportService}, models::{ParseStringError, normalize_team_code, GamePeriod}, single_flight::SingleFlight, api_ws::{WsMsg, WsMsgBody}, service_error::ServiceError, publish_service::PublishService, player_service::{ApiAthlete, PlayerService}, shutdown_service::ShutdownService, CONFIG};
 
 #[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
 pub struct Player {
@@ -14,13 +18,13 @@ impl FromStr for Player {
     type Err = ParseStringError;
     fn from_str(s: &str) -> Result<Self, Self::Err> {
         // 1 Johan Johansson Olsson => Player
-        if s.is_empty() {
-            return Err(ParseStringError)
-        }
-        let parts: Vec<&str> = s.split(' ').collect();
-        let jersey = parts.first().cloned().unwrap_or_default().to_string().parse::<i32>().ok().unwrap_or_default();
-        let first_name = parts.get(1).cloned().unwrap_or_default().to_string();
-        let family_name = s.replace(format!("{jersey} {first_name} ").as_str(), "");
+        let mut parts = s.split_whitespace().peekable();
+        let jersey = match parts.peek().and_then(|e| e.parse::<i32>().ok()) {
+            Some(jersey) => { parts.next(); jersey },
+            None => 0,
+        };
+        let first_name = parts.next().unwrap_or_default().to_string();
+        let family_name = parts.collect::<Vec<&str>>().join(" ");
         if first_name.is_empty() && family_name.is_empty() {
             Err(ParseStringError)
         } else {
@@ -29,10 +33,21 @@ impl FromStr for Player {
     }
 }
 
+// Rink coordinates are serialized rounded to LOCATION_DECIMALS decimals,
+// so 12.345 is sent as 12.35 and f32 noise like 0.30000001 never reaches clients
 #[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
 pub struct Location {
-    x: f32,
-    y: f32,
+    #[serde(serialize_with = "serialize_coordinate")]
+    pub x: f32,
+    #[serde(serialize_with = "serialize_coordinate")]
+    pub y: f32,
+}
+
+const LOCATION_DECIMALS: i32 = 2;
+
+fn serialize_coordinate<S: serde::Serializer>(value: &f32, serializer: S) -> Result<S::Ok, S::Error> {
+    let scale = 10f64.powi(LOCATION_DECIMALS);
+    serializer.serialize_f64((*value as f64 * scale).round() / scale)
 }
 
 #[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
@@ -40,25 +55,109 @@ pub struct Location {
 pub struct GoalInfo {
     pub team: String,
     pub player: Option<Player>,
+    // same id as ApiAthlete.id
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub player_id: Option<i32>,
     pub team_advantage: String,
+    // None for unassisted goals, never empty
     pub assist: Option<String>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub assist_ids: Vec<i32>,
+    #[serde(default)]
+    pub unassisted: bool,
     pub home_team_result: i16,
     pub away_team_result: i16,
     pub location: Location,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub media_url: Option<String>,
+    // shootout attempt, not a goal for the scorer. Only the shootout winner gets one goal on the score
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    pub shootout: bool,
+    // credited to the team without a scorer of their own, player is None
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    pub own_goal: bool,
+    // not an own goal, but the scorer couldn't be parsed
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    pub scorer_unknown: bool,
+    // scored within the quick strike window of the previous goal, by either team. Set by EventService::mark_quick_strikes
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    pub quick_strike: bool,
+    // game seconds since the previous goal, only for quick strikes
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub quick_strike_s: Option<i32>,
 }
 
+const OWN_GOAL_KEYWORDS: [&str; 3] = ["självmål", "own goal", "owngoal"];
+
 impl GoalInfo {
-    pub fn new(a: &Goal) -> GoalInfo {
+    pub fn new(description: &str, a: &Goal) -> GoalInfo {
+        let own_goal = [description, &a.extra.scorerLong, &a.extra.teamAdvantage].iter()
+            .map(|e| e.to_lowercase())
+            .any(|e| OWN_GOAL_KEYWORDS.iter().any(|k| e.contains(k)))
+            || a.extra.teamAdvantage.trim().eq_ignore_ascii_case("OG");
+        let player: Option<Player> = match own_goal {
+            true => None,
+            false => a.extra.scorerLong.parse().ok(),
+        };
         GoalInfo { 
-            team: a.team.clone(),
-            player: a.extra.scorerLong.parse().ok(),
+            team: normalize_team_code(&a.team),
+            scorer_unknown: !own_goal && player.is_none(),
+            own_goal,
+            player,
+            player_id: a.extra.scorerId.as_ref().and_then(parse_player_id),
             team_advantage: a.extra.teamAdvantage.clone(),
-            assist: Some(a.extra.assist.clone()),
+            assist: Some(a.extra.assist.trim()).filter(|e| !e.is_empty()).map(|e| e.to_string()),
+            assist_ids: a.extra.assistIds.iter().filter_map(parse_player_id).collect(),
+            unassisted: a.extra.assist.trim().is_empty(),
             home_team_result: a.extra.homeForward.to_num(),
             away_team_result: a.extra.homeAgainst.to_num(),
-            location: Location { x: a.location.x, y: a.location.y }
+            location: Location { x: a.location.x, y: a.location.y },
+            media_url: a.extra.mediaUrl.clone().filter(|e| !e.is_empty()),
+            shootout: false,
+            quick_strike: false,
+            quick_strike_s: None,
         }
     }
+
+    // Matches each assister on jersey within the scoring team's roster, taking the roster's name
+    // and id. Returns the assisters missing from the roster, those are left as parsed
+    pub fn resolve_assists(&mut self, roster: &[ApiAthlete]) -> Vec<String> {
+        let Some(assist) = self.assist.as_deref().filter(|e| !e.trim().is_empty()) else {
+            return vec![];
+        };
+        let team: Vec<&ApiAthlete> = roster.iter().filter(|e| e.team_code == self.team).collect();
+        let mut missing = vec![];
+        let mut resolved = vec![];
+        let mut ids = vec![];
+        for parsed in assist.split(',').map(|e| e.trim()).filter(|e| !e.is_empty()) {
+            let found = parsed.parse::<Player>().ok().and_then(|p| team.iter().find(|e| match p.jersey {
+                0 => e.family_name.eq_ignore_ascii_case(&p.family_name),
+                jersey => e.jersey == jersey,
+            }));
+            match found {
+                Some(e) => {
+                    resolved.push(format!("{} {} {}", e.jersey, e.first_name, e.family_name));
+                    ids.push(e.id);
+                },
+                None => {
+                    resolved.push(parsed.to_string());
+                    missing.push(parsed.to_string());
+                },
+            }
+        }
+        self.assist = Some(resolved.join(", "));
+        if self.assist_ids.is_empty() && missing.is_empty() {
+            self.assist_ids = ids;
+        }
+        missing
+    }
+}
+
+// Upstream sends ids as numbers or strings
+fn parse_player_id(value: &serde_json::Value) -> Option<i32> {
+    value.as_i64().or_else(|| value.as_str().and_then(|e| e.trim().parse().ok()))
+        .and_then(|e| i32::try_from(e).ok())
+        .filter(|e| *e > 0)
 }
 
 #[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
@@ -66,42 +165,185 @@ impl GoalInfo {
 pub struct PenaltyInfo {
     pub team: String,
     pub player: Option<Player>,
+    // same id as ApiAthlete.id
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub player_id: Option<i32>,
     pub reason: String,
+    // reason mapped to a language independent code
+    #[serde(default)]
+    pub reason_code: PenaltyReason,
     pub penalty: Option<String>,
+    #[serde(default)]
+    pub bench: bool,
+}
+
+// A plain string. Unknown is for reasons not in PENALTY_REASON_KEYWORDS, and for codes from a newer
+// version. The reason as sent is in PenaltyInfo.reason
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Default)]
+pub enum PenaltyReason {
+    Tripping,
+    Hooking,
+    Slashing,
+    Interference,
+    Holding,
+    HoldingStick,
+    HighSticking,
+    CrossChecking,
+    Roughing,
+    Boarding,
+    Charging,
+    Elbowing,
+    Kneeing,
+    CheckingFromBehind,
+    HeadContact,
+    Spearing,
+    Fighting,
+    DelayOfGame,
+    TooManyMen,
+    Unsportsmanlike,
+    Diving,
+    GameMisconduct,
+    Misconduct,
+    #[default]
+    #[serde(other)]
+    Unknown,
+}
+
+// Swedish and English, lowercased. Checked in order, so longer phrases go before the words in them
+const PENALTY_REASON_KEYWORDS: [(PenaltyReason, &[&str]); 23] = [
+    (PenaltyReason::HoldingStick, &["hålla klubba", "hållande av klubba", "holding the stick", "holding stick"]),
+    (PenaltyReason::Holding, &["fasthållning", "hållning", "holding"]),
+    (PenaltyReason::Tripping, &["fällning", "fälla", "tripping"]),
+    (PenaltyReason::Hooking, &["hakning", "haka", "hooking"]),
+    (PenaltyReason::Slashing, &["slashing", "slag med klubba", "klubbslag"]),
+    (PenaltyReason::HighSticking, &["hög klubba", "high sticking", "high-sticking"]),
+    (PenaltyReason::CrossChecking, &["cross-checking", "crosschecking", "cross checking", "crosscheck"]),
+    (PenaltyReason::CheckingFromBehind, &["tackling bakifrån", "checking from behind"]),
+    (PenaltyReason::HeadContact, &["tackling mot huvud", "huvudtackling", "head", "huvud"]),
+    (PenaltyReason::Boarding, &["tackling mot sarg", "boarding"]),
+    (PenaltyReason::Elbowing, &["armbåg", "elbowing"]),
+    (PenaltyReason::Kneeing, &["knätackling", "kneeing"]),
+    (PenaltyReason::Charging, &["charging", "otillåten tackling"]),
+    (PenaltyReason::Spearing, &["spearing", "stick med klubba"]),
+    (PenaltyReason::Interference, &["obstruktion", "interference", "hindrande"]),
+    (PenaltyReason::Roughing, &["ruffighet", "roughing"]),
+    (PenaltyReason::Fighting, &["slagsmål", "fighting"]),
+    (PenaltyReason::TooManyMen, &["för många spelare", "too many men", "too many players"]),
+    (PenaltyReason::DelayOfGame, &["fördröjning", "delay of game", "delaying"]),
+    (PenaltyReason::Diving, &["filmning", "diving", "embellishment"]),
+    (PenaltyReason::Unsportsmanlike, &["osportsligt", "unsportsmanlike"]),
+    (PenaltyReason::GameMisconduct, &["game misconduct", "matchstraff"]),
+    (PenaltyReason::Misconduct, &["misconduct"]),
+];
+
+impl PenaltyReason {
+    pub fn parse(reason: &str) -> PenaltyReason {
+        let lowercased = reason.to_lowercase();
+        PENALTY_REASON_KEYWORDS.iter()
+            .find(|(_, keywords)| keywords.iter().any(|e| lowercased.contains(e)))
+            .map(|(e, _)| e.clone())
+            .unwrap_or(PenaltyReason::Unknown)
+    }
 }
+
+// Bench minors and coach misconducts have no player in the description. Looked for in the reason,
+// and as the first word instead of a player, so a player's name can't make it a bench penalty
+const BENCH_PENALTY_KEYWORDS: [&str; 3] = ["lagstraff", "bench", "coach"];
+
 impl PenaltyInfo {
     pub fn new(description: &str, p: &Penalty) -> PenaltyInfo {
         let (player_info, penalty_info) = description.split_once(" utvisas ")
             .map(|e| (Some(e.0), Some(e.1)))
             .unwrap_or_else(|| (None, None));
-        let (penalty, reason) = penalty_info.unwrap_or_default().split_once(',')
-            .map(|e| (Some(e.0.to_string()), e.1.to_string()))
+        let (penalty, reason) = penalty_info.and_then(PenaltyInfo::split_penalty)
             .unwrap_or_else(|| (None, description.to_string()));
 
-        let player = player_info.unwrap_or_default().parse::<Player>().ok();
+        let lowercased = reason.to_lowercase();
+        let first_word = player_info.and_then(|e| e.split_whitespace().next()).unwrap_or_default().to_lowercase();
+        let bench = BENCH_PENALTY_KEYWORDS.iter().any(|e| lowercased.contains(e) || &first_word == e);
+        let player = match bench {
+            true => None,
+            false => player_info.unwrap_or_default().parse::<Player>().ok(),
+        };
         PenaltyInfo { 
-            team: p.team.clone(), 
+            team: normalize_team_code(&p.team),
             player, 
+            player_id: p.playerId.as_ref().and_then(parse_player_id).filter(|_| !bench),
+            reason_code: PenaltyReason::parse(reason.trim()),
             reason: reason.trim().to_string(), 
-            penalty 
+            penalty,
+            bench,
         }
     }
+
+    // "2 min", "2+10 min" => 2
+    pub fn get_minutes(&self) -> i32 {
+        self.penalty.as_deref()
+            .and_then(|e| e.split(|c: char| !c.is_ascii_digit()).next())
+            .and_then(|e| e.parse().ok())
+            .unwrap_or_default()
+    }
+
+    // "2 min, Hooking", "2 min Hooking", "2+10 min Roughing" => (penalty, reason)
+    fn split_penalty(penalty_info: &str) -> Option<(Option<String>, String)> {
+        if let Some((penalty, reason)) = penalty_info.split_once(',') {
+            return Some((Some(penalty.trim().to_string()), reason.trim().to_string()));
+        }
+        let pos = penalty_info.find("min")?;
+        let minutes = penalty_info[..pos].trim();
+        if minutes.is_empty() || !minutes.chars().all(|c| c.is_ascii_digit() || c == '+' || c == ' ') {
+            return None;
+        }
+        let (penalty, reason) = penalty_info.split_at(pos + "min".len());
+        Some((Some(penalty.trim().to_string()), reason.trim_start_matches('.').trim().to_string()))
+    }
 }
 
 
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Default)]
+pub enum ShotKind {
+    // saved, goals are their own events
+    #[default]
+    OnGoal,
+    Blocked,
+    Wide,
+    // hit the post or crossbar
+    Iron,
+    // penalty shots and shootout attempts
+    Penalty,
+}
+
 #[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
 pub struct ShotInfo {
     pub team: String,
     pub location: Location,
+    #[serde(default)]
+    pub kind: ShotKind,
 }
 impl ShotInfo {
-    pub fn new(info: &Shot) -> ShotInfo {
-        ShotInfo { team: info.team.clone(), location: Location { x: info.location.x, y: info.location.y } }
+    pub fn new(info: &Shot, kind: ShotKind) -> ShotInfo {
+        ShotInfo { team: normalize_team_code(&info.team), location: Location { x: info.location.x, y: info.location.y }, kind }
+    }
+}
+// Only the team is certain, the player is parsed from the description
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct HitInfo {
+    pub team: String,
+    pub player: Option<Player>,
+}
+impl HitInfo {
+    pub fn new(description: &str, info: &Hit) -> HitInfo {
+        HitInfo { team: normalize_team_code(&info.team), player: description.parse().ok() }
     }
 }
+
+// winner is None while undecided or tied (abandoned games), is_final once the game is Finished, tied or not.
+// Forfeits are reported with a final scoreline, so they get a winner like any other final.
 #[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
 pub struct GameEndInfo {
     pub winner: Option<String>,
+    #[serde(default)]
+    pub is_final: bool,
 }
 
 #[derive(PartialEq)]
@@ -111,20 +353,67 @@ pub enum ApiEventTypeLevel {
     High // alert
 }
 
+#[derive(Deserialize, Debug, Clone)]
+#[serde(default)]
+pub struct PublishPolicy {
+    pub goal: bool,
+    pub game_start: bool,
+    pub game_end: bool,
+    pub penalty: bool,
+    pub period_start: bool,
+    pub period_end: bool,
+    pub timeout: bool,
+    pub shot: bool,
+    pub hit: bool,
+    pub review: bool,
+    pub challenge: bool,
+}
+impl Default for PublishPolicy {
+    fn default() -> Self {
+        PublishPolicy {
+            goal: true,
+            game_start: true,
+            game_end: true,
+            penalty: false,
+            period_start: false,
+            period_end: false,
+            timeout: false,
+            shot: false,
+            hit: false,
+            review: false,
+            challenge: false,
+        }
+    }
+}
+
 #[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
 #[serde(tag = "type")]
 pub enum ApiEventType {
     Goal(GoalInfo),
     PeriodEnd,
     PeriodStart,
+    // start of an overtime period, instead of PeriodStart. Overtime is sudden death, the first goal
+    // ends the game. A scoreless regular season overtime goes to a shootout, playoff overtimes are
+    // played until someone scores
+    OvertimeStart,
     GameEnd(GameEndInfo),
     GameStart,
     Penalty(PenaltyInfo),
     Shot(ShotInfo),
+    Hit(HitInfo),
     Timeout,
+    // video review of a play, e.g. a possible goal
+    Review,
+    // coach's challenge, usually followed by a review
+    Challenge,
     General,
 }
 impl ApiEventType {
+    // never dropped when a game has too many events
+    pub fn is_key(&self) -> bool {
+        matches!(self, Self::Goal(_) | Self::GameStart | Self::GameEnd(_))
+    }
+
     pub fn get_level(&self) -> ApiEventTypeLevel {
         match self {
             Self::Goal(_) => ApiEventTypeLevel::High,
@@ -132,53 +421,281 @@ impl ApiEventType {
             Self::GameEnd(_) => ApiEventTypeLevel::High,
             Self::Penalty(_) => ApiEventTypeLevel::Medium,
             Self::PeriodStart => ApiEventTypeLevel::Medium,
+            Self::OvertimeStart => ApiEventTypeLevel::Medium,
             Self::PeriodEnd => ApiEventTypeLevel::Medium,
             Self::Timeout => ApiEventTypeLevel::Medium,
+            Self::Review => ApiEventTypeLevel::Medium,
+            Self::Challenge => ApiEventTypeLevel::Medium,
             Self::Shot(_) => ApiEventTypeLevel::Low,
+            Self::Hit(_) => ApiEventTypeLevel::Low,
             Self::General => ApiEventTypeLevel::Low,
         }
     }
 }
 
-#[derive(Serialize, Deserialize, Debug, Clone)]
+// ApiEventType without the payload, serialized as e.g. "period_start"
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq, Hash, PartialOrd, Ord)]
+#[serde(rename_all = "snake_case")]
+pub enum EventTypeKey {
+    Goal,
+    PeriodEnd,
+    PeriodStart,
+    OvertimeStart,
+    GameEnd,
+    GameStart,
+    Penalty,
+    Shot,
+    Hit,
+    Timeout,
+    Review,
+    Challenge,
+    General,
+}
+
+impl From<&ApiEventType> for EventTypeKey {
+    fn from(value: &ApiEventType) -> Self {
+        match value {
+            ApiEventType::Goal(_) => EventTypeKey::Goal,
+            ApiEventType::PeriodEnd => EventTypeKey::PeriodEnd,
+            ApiEventType::PeriodStart => EventTypeKey::PeriodStart,
+            ApiEventType::OvertimeStart => EventTypeKey::OvertimeStart,
+            ApiEventType::GameEnd(_) => EventTypeKey::GameEnd,
+            ApiEventType::GameStart => EventTypeKey::GameStart,
+            ApiEventType::Penalty(_) => EventTypeKey::Penalty,
+            ApiEventType::Shot(_) => EventTypeKey::Shot,
+            ApiEventType::Hit(_) => EventTypeKey::Hit,
+            ApiEventType::Timeout => EventTypeKey::Timeout,
+            ApiEventType::Review => EventTypeKey::Review,
+            ApiEventType::Challenge => EventTypeKey::Challenge,
+            ApiEventType::General => EventTypeKey::General,
+        }
+    }
+}
+
+// What an update would change in the stored events, by event id
+#[derive(Serialize, Debug, Clone, PartialEq, Default)]
+pub struct ApiEventsDiff {
+    pub new: Vec<ApiGameEvent>,
+    pub updated: Vec<ApiGameEvent>,
+    pub unchanged: Vec<String>,
+    // stored but no longer in the feed
+    pub removed: Vec<String>,
+    // the feed went backwards and would be ignored, nothing changes
+    pub regressed: bool,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct ApiScoreChange {
+    pub game_uuid: String,
+    pub home_team_result: i16,
+    pub away_team_result: i16,
+}
+
+// bump on breaking changes to the stored ApiGameEvent shape, and migrate the old one in migrate_events
+pub const EVENTS_SCHEMA_VERSION: u32 = 2;
+
+// Version 0 is the unversioned list, the same shape as version 1.
+// Up to version 1 an unknown penalty reason_code was {"Unknown": reason}, now it's "Unknown"
+fn migrate_events(version: u32, mut value: serde_json::Value) -> Option<serde_json::Value> {
+    if version > 1 {
+        return None;
+    }
+    for event in value.as_array_mut()? {
+        if let Some(reason_code) = event.pointer_mut("/reason_code").filter(|e| e.is_object()) {
+            *reason_code = serde_json::Value::String("Unknown".to_string());
+        }
+    }
+    Some(value)
+}
+
+// How both event stores identify an event. The feed's numeric id is canonical, so "12" and " 012"
+// in the mapped store are the same event as eventId 12 in the raw store. Events made here, like
+// GameStarted from the report, have no feed id and are keyed on their name
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq, Hash)]
+pub enum EventKey {
+    Feed(i32),
+    Local(String),
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
 pub struct ApiGameEvent {
     pub game_uuid: String,
     pub event_id: String,
     pub revision: u16,
     pub status: GameStatus,
+    // serialized as the feed period number
+    #[serde(default)]
+    pub period: GamePeriod,
     pub gametime: String,
     pub description: String,
     #[serde(flatten)]
     pub info: ApiEventType,
+    // annotations added by event processors, e.g. "hat_trick"
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub tags: Vec<String>,
+}
+
+impl EventKey {
+    pub fn new(event_id: &str) -> EventKey {
+        match event_id.trim().parse() {
+            Ok(id) => EventKey::Feed(id),
+            Err(_) => EventKey::Local(event_id.to_string()),
+        }
+    }
+}
+
+impl ApiGameEvent {
+    pub fn key(&self) -> EventKey {
+        EventKey::new(&self.event_id)
+    }
+
+    pub fn should_publish(&self, policy: &PublishPolicy) -> bool {
+        match self.info {
+            ApiEventType::Goal(_) => policy.goal,
+            ApiEventType::GameStart => policy.game_start,
+            ApiEventType::GameEnd(_) => policy.game_end,
+            ApiEventType::Penalty(_) => policy.penalty,
+            ApiEventType::PeriodStart => policy.period_start,
+            ApiEventType::OvertimeStart => policy.period_start,
+            ApiEventType::PeriodEnd => policy.period_end,
+            ApiEventType::Timeout => policy.timeout,
+            ApiEventType::Review => policy.review,
+            ApiEventType::Challenge => policy.challenge,
+            ApiEventType::Shot(_) => policy.shot,
+            ApiEventType::Hit(_) => policy.hit,
+            ApiEventType::General => false,
+        }
+    }
+}
+
+impl GameStatus {
+    // An intermission goes with the period it follows, the feed's period is still that one
+    fn get_period_order(&self, period: GamePeriod) -> u8 {
+        match self {
+            GameStatus::Coming | GameStatus::Postponed | GameStatus::Cancelled => 0,
+            GameStatus::Period1 => 1,
+            GameStatus::Period2 => 2,
+            GameStatus::Period3 => 3,
+            GameStatus::Intermission => match period {
+                GamePeriod::Regulation(n) => n.min(3),
+                GamePeriod::Overtime(_) => 4,
+                GamePeriod::Shootout => 5,
+            },
+            GameStatus::Overtime => 4,
+            GameStatus::Shootout => 5,
+            GameStatus::Finished => 6,
+        }
+    }
 }
 
+fn get_gametime_seconds(gametime: &str) -> i32 {
+    let (min_str, secs_str) = gametime.split_once(':').unwrap_or((gametime, "0"));
+    let min: i32 = min_str.trim().parse().ok().unwrap_or_default();
+    let secs: i32 = secs_str.trim().parse().ok().unwrap_or_default();
+    min * 60 + secs
+}
+
+// Seconds since the start of the game, periods are 20 minutes
+pub fn get_elapsed_seconds(status: &GameStatus, period: GamePeriod, gametime: &str) -> i32 {
+    let period = status.get_period_order(period).clamp(1, 4) as i32;
+    (period - 1) * 20 * 60 + get_gametime_seconds(gametime)
+}
+
+impl ApiGameEvent {
+    // period first, then time within the period. Shootout goes after overtime, 2OT after OT
+    pub fn cmp_gametime(&self, other: &ApiGameEvent) -> Ordering {
+        self.status.get_period_order(self.period).cmp(&other.status.get_period_order(other.period))
+            .then_with(|| self.period.cmp(&other.period))
+            .then_with(|| get_gametime_seconds(&self.gametime).cmp(&get_gametime_seconds(&other.gametime)))
+    }
+}
+
+impl ApiGameEvent {
+    // 0-100, how much the event is worth surfacing:
+    // 70-100 goals, more when late, tying or go-ahead, and 100 for an overtime winner
+    // 40-60 game start/end, penalties and period boundaries, late penalties a bit more
+    // 10-30 timeouts, reviews, challenges, shots and hits
+    // 0 general events
+    pub fn importance(&self) -> u8 {
+        let elapsed_s = get_elapsed_seconds(&self.status, self.period, &self.gametime);
+        let is_late = self.period >= GamePeriod::Overtime(1) || elapsed_s >= LATE_GAME_S;
+        match &self.info {
+            ApiEventType::Goal(g) if g.shootout => 70,
+            ApiEventType::Goal(_) if matches!(self.period, GamePeriod::Overtime(_)) => 100,
+            ApiEventType::Goal(g) => {
+                let lead = (g.home_team_result - g.away_team_result).abs();
+                let close = match lead {
+                    0 => 15,
+                    1 => 10,
+                    _ => 0,
+                };
+                70 + close + if is_late { 10 } else { 0 }
+            },
+            ApiEventType::GameEnd(_) => 60,
+            ApiEventType::GameStart => 50,
+            ApiEventType::Penalty(_) => if is_late { 50 } else { 45 },
+            ApiEventType::OvertimeStart => 45,
+            ApiEventType::PeriodStart | ApiEventType::PeriodEnd => 40,
+            ApiEventType::Challenge => 30,
+            ApiEventType::Review => 30,
+            ApiEventType::Timeout => 25,
+            ApiEventType::Shot(_) => 20,
+            ApiEventType::Hit(_) => 10,
+            ApiEventType::General => 0,
+        }
+    }
+}
+
+// The last ten minutes of regulation
+const LATE_GAME_S: i32 = 50 * 60;
+
 impl Display for ApiGameEvent {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
         write!(f, "{:?} {} :: {:?} • {}", self.info, self.description, self.status, self.gametime)
     }
 }
 
+// General and livefeed events have no payload, the description is all there is to go on
+fn get_general_type(description: &str) -> ApiEventType {
+    let description = description.to_lowercase();
+    if ["challenge", "utmaning"].iter().any(|e| description.contains(e)) {
+        ApiEventType::Challenge
+    } else if ["videogranskning", "video review", "granskning"].iter().any(|e| description.contains(e)) {
+        ApiEventType::Review
+    } else {
+        ApiEventType::General
+    }
+}
+
 impl external::event::PlayByPlay {
     fn to_type(&self) -> ApiEventType {
         match &self.class {
-            PlayByPlayType::General(_) => ApiEventType::General,
-            PlayByPlayType::Livefeed(_) => ApiEventType::General,
+            PlayByPlayType::General(_) => get_general_type(&self.description),
+            PlayByPlayType::Livefeed(_) => get_general_type(&self.description),
             PlayByPlayType::GoolkeeperEvent(_) => ApiEventType::General,
 
-            PlayByPlayType::Goal(a) => ApiEventType::Goal(GoalInfo::new(a)),
+            PlayByPlayType::Goal(a) => ApiEventType::Goal(GoalInfo {
+                shootout: GamePeriod::from(self.period.to_num()) == GamePeriod::Shootout,
+                ..GoalInfo::new(&self.description, a)
+            }),
 
-            PlayByPlayType::Shot(a) =>          ApiEventType::Shot(ShotInfo::new(a)),
-            PlayByPlayType::ShotBlocked(a) =>   ApiEventType::Shot(ShotInfo::new(a)),
-            PlayByPlayType::ShotWide(a) =>      ApiEventType::Shot(ShotInfo::new(a)),
-            PlayByPlayType::ShotIron(a) =>      ApiEventType::Shot(ShotInfo::new(a)),
-            PlayByPlayType::PenaltyShot(a) =>   ApiEventType::Shot(ShotInfo::new(a)),
-            PlayByPlayType::ShootoutPenaltyShot(a) => ApiEventType::Shot(ShotInfo::new(a)),
+            PlayByPlayType::Shot(a) =>          ApiEventType::Shot(ShotInfo::new(a, ShotKind::OnGoal)),
+            PlayByPlayType::ShotBlocked(a) =>   ApiEventType::Shot(ShotInfo::new(a, ShotKind::Blocked)),
+            PlayByPlayType::ShotWide(a) =>      ApiEventType::Shot(ShotInfo::new(a, ShotKind::Wide)),
+            PlayByPlayType::ShotIron(a) =>      ApiEventType::Shot(ShotInfo::new(a, ShotKind::Iron)),
+            PlayByPlayType::PenaltyShot(a) =>   ApiEventType::Shot(ShotInfo::new(a, ShotKind::Penalty)),
+            PlayByPlayType::ShootoutPenaltyShot(a) => ApiEventType::Shot(ShotInfo::new(a, ShotKind::Penalty)),
 
             PlayByPlayType::Penalty(a) => ApiEventType::Penalty(PenaltyInfo::new(&self.description, a)),
 
+            PlayByPlayType::Hit(a) => ApiEventType::Hit(HitInfo::new(&self.description, a)),
+
             PlayByPlayType::Timeout(_) => ApiEventType::Timeout,
 
+            // the feed marks some overtime starts with their own status
             PlayByPlayType::Period(a) => match a.extra.gameStatus.as_str() {
+                "OverTime" => ApiEventType::OvertimeStart,
+                "Playing" if matches!(GamePeriod::from(self.period.to_num()), GamePeriod::Overtime(_)) => ApiEventType::OvertimeStart,
                 "Playing" => ApiEventType::PeriodStart,
                 _ => ApiEventType::PeriodEnd,
             },
@@ -187,6 +704,10 @@ impl external::event::PlayByPlay {
 }
 
 impl external::event::PlayByPlay {
+    pub fn key(&self) -> EventKey {
+        EventKey::Feed(self.eventId)
+    }
+
     pub fn into_mapped_event(self, game_uuid: &str) -> ApiGameEvent {
         let info: ApiEventType = self.to_type();
         ApiGameEvent {
@@ -194,75 +715,568 @@ impl external::event::PlayByPlay {
             event_id: format!("{}", self.eventId),
             revision: self.revision,
             status: self.period.to_num().into(),
+            period: self.period.to_num().into(),
             gametime: self.gametime.clone(),
             description: self.description,
             info,
+            tags: vec![],
+        }
+    }
+}
+
+// Runs on each mapped event before it's stored, along with the game's already stored events. Reads
+// and refetches run them too, with the events before it in game time order
+pub trait EventProcessor: Send + Sync {
+    fn process(&self, event: &mut ApiGameEvent, stored: &[ApiGameEvent]);
+}
+
+impl<F: Fn(&mut ApiGameEvent, &[ApiGameEvent]) + Send + Sync> EventProcessor for F {
+    fn process(&self, event: &mut ApiGameEvent, stored: &[ApiGameEvent]) {
+        self(event, stored)
+    }
+}
+
+lazy_static! {
+    static ref EVENTS_IN_FLIGHT: SingleFlight<Result<Vec<external::event::PlayByPlay>, ServiceError>> = SingleFlight::new();
+    static ref EVENT_PROCESSORS: RwLock<Vec<Box<dyn EventProcessor>>> = RwLock::new(vec![]);
+}
+
+fn get_scorer(event: &ApiGameEvent) -> Option<&Player> {
+    match &event.info {
+        ApiEventType::Goal(g) if !g.shootout && !g.own_goal => g.player.as_ref(),
+        _ => None,
+    }
+}
+
+// The scorer's third goal of the game
+fn tag_hat_trick(event: &mut ApiGameEvent, stored: &[ApiGameEvent]) {
+    let Some(scorer) = get_scorer(event) else { return };
+    let previous = stored.iter()
+        .filter(|e| e.key() != event.key() && get_scorer(e) == Some(scorer))
+        .count();
+    if previous == 2 && !event.tags.iter().any(|e| e == "hat_trick") {
+        event.tags.push("hat_trick".to_string());
+    }
+}
+
+// Flags a goal within window_s of the previous goal by either team, and clears the flag of one that isn't,
+// as a revision can move a goal. Shootout goals are never quick strikes. A window of 0 flags none
+fn tag_quick_strike(event: &mut ApiGameEvent, stored: &[ApiGameEvent], window_s: u64) {
+    let is_goal = |e: &ApiGameEvent| matches!(&e.info, ApiEventType::Goal(g) if !g.shootout);
+    if !is_goal(event) {
+        return;
+    }
+    let elapsed = |e: &ApiGameEvent| get_elapsed_seconds(&e.status, e.period, &e.gametime);
+    let seconds = stored.iter()
+        .filter(|e| is_goal(e) && e.key() != event.key() && e.cmp_gametime(event) != Ordering::Greater)
+        .max_by(|a, b| a.cmp_gametime(b))
+        .map(|e| elapsed(event) - elapsed(e))
+        .filter(|e| window_s > 0 && *e as i64 <= window_s as i64);
+    if let ApiEventType::Goal(g) = &mut event.info {
+        g.quick_strike = seconds.is_some();
+        g.quick_strike_s = seconds;
+    }
+}
+
+// Drops the oldest non key events above max, 0 is no limit
+fn cap_events<T>(game_uuid: &str, events: &mut Vec<T>, max: usize, is_key: impl Fn(&T) -> bool) {
+    if max == 0 || events.len() <= max {
+        return;
+    }
+    log::warn!("[EVENT] {game_uuid} has {} events, over the limit of {max}", events.len());
+    while events.len() > max {
+        match events.iter().position(|e| !is_key(e)) {
+            Some(pos) => { events.remove(pos); },
+            None => break,
         }
     }
 }
 
 pub struct EventService;
 impl EventService {
- 
-    pub async fn update(game_uuid: &str, throttle_s: Option<Duration>) -> Option<Vec<ApiGameEvent>> {
-        let db_raw: Db<String, Vec<external::event::PlayByPlay>> = Db::new("v2_events_raw");
+    pub fn get_raw_db() -> Db<String, Vec<external::event::PlayByPlay>> {
+        Db::<String, Vec<external::event::PlayByPlay>>::new_compressed("v2_events_raw")
+    }
+
+    pub fn get_mapped_db() -> Db<String, Vec<ApiGameEvent>> {
+        Db::<String, Vec<ApiGameEvent>>::new_compressed("v2_events_2").with_version(EVENTS_SCHEMA_VERSION).with_migration(migrate_events)
+    }
+
+    pub async fn update(game_uuid: &str, throttle: ThrottlePolicy) -> Result<Vec<ApiGameEvent>, ServiceError> {
+        let db_raw = EventService::get_raw_db();
         // let db: Db<String, Vec<ApiGameEvent>> = Db::new("v2_events_2");
 
         
-        let raw_events = if !db_raw.is_stale(&game_uuid.to_string(), throttle_s) {
-            db_raw.read(&game_uuid.to_string()).unwrap_or_default()
-        } else {
-            rest_client::get_events(game_uuid).await.unwrap_or_default()
+        // Postponed and cancelled games have no events coming, so only ever served from the cache
+        let called_off = GameReportService::read(game_uuid).is_some_and(|e| e.status.is_called_off());
+        let raw_events = async {
+            if called_off {
+                Ok(db_raw.read(&game_uuid.to_string()).unwrap_or_default())
+            } else if !throttle.is_stale(&db_raw, &game_uuid.to_string()) {
+                db_raw.read(&game_uuid.to_string()).ok_or_else(|| ServiceError::Cache(format!("{game_uuid} events not cached")))
+            } else {
+                let uuid = game_uuid.to_string();
+                EVENTS_IN_FLIGHT.run(game_uuid, || async move {
+                    rest_client::get_events(&uuid).await
+                }).await
+            }
+        }.instrument(tracing::info_span!("fetch", service = "events", game_uuid)).await?;
+        // Callers sharing a fetch each store its result, db.update is atomic and the later writes replace with the same list
+        let raw_events = tracing::info_span!("store", service = "events", game_uuid)
+            .in_scope(|| EventService::store_all_raw(game_uuid, raw_events));
+
+        let events = tracing::info_span!("map", service = "events", game_uuid)
+            .in_scope(|| EventService::map_all(game_uuid, raw_events));
+        Ok(events)
+    }
+
+    // Cached events right away, refreshing in the background when stale. New events are published once stored
+    pub fn update_swr(game_uuid: &str, throttle: ThrottlePolicy, publish_service: &PublishService) -> Vec<ApiGameEvent> {
+        EventService::update_swr_with(game_uuid, throttle, publish_service, |uuid| async move { rest_client::get_events(&uuid).await })
+    }
+
+    fn update_swr_with<F, Fut>(game_uuid: &str, throttle: ThrottlePolicy, publish_service: &PublishService, fetch: F) -> Vec<ApiGameEvent>
+    where
+        F: FnOnce(String) -> Fut + Send + 'static,
+        Fut: Future<Output = Result<Vec<external::event::PlayByPlay>, ServiceError>> + Send + 'static,
+    {
+        let cached = EventService::read(game_uuid);
+        let db_raw = EventService::get_raw_db();
+        if throttle.is_stale(&db_raw, &game_uuid.to_string()) {
+            let known: Vec<(EventKey, u16)> = cached.iter().map(|e| (e.key(), e.revision)).collect();
+            let uuid = game_uuid.to_string();
+            let publish_service = publish_service.clone();
+            ShutdownService::spawn(async move {
+                let fetch_uuid = uuid.clone();
+                match EVENTS_IN_FLIGHT.run(&uuid, || fetch(fetch_uuid)).await {
+                    Ok(raw_events) => {
+                        EventService::map_all(&uuid, EventService::store_all_raw(&uuid, raw_events)).into_iter()
+                            .filter(|e| !known.contains(&(e.key(), e.revision)))
+                            .for_each(|e| _ = publish_service.publish(&e));
+                    },
+                    Err(e) => log::error!("[EVENT] Background refresh failed {uuid} {e}"),
+                }
+            });
+        }
+        cached
+    }
+
+    // Fetches and maps like update, without writing the Db or publishing, for trying mapping changes on live caches
+    pub async fn update_dry_run(game_uuid: &str) -> Result<ApiEventsDiff, ServiceError> {
+        EventService::update_dry_run_with(game_uuid, |uuid| async move { rest_client::get_events(&uuid).await }).await
+    }
+
+    async fn update_dry_run_with<F, Fut>(game_uuid: &str, fetch: F) -> Result<ApiEventsDiff, ServiceError>
+    where
+        F: FnOnce(String) -> Fut,
+        Fut: Future<Output = Result<Vec<external::event::PlayByPlay>, ServiceError>>,
+    {
+        let db_raw = EventService::get_raw_db();
+        let stored_raw = db_raw.read(&game_uuid.to_string()).unwrap_or_default();
+        let raw_events = fetch(game_uuid.to_string()).await?;
+        let next_raw = EventService::next_raw(game_uuid, &stored_raw, raw_events);
+        let stored = EventService::map_all(game_uuid, stored_raw);
+        let Some(next_raw) = next_raw else {
+            return Ok(ApiEventsDiff {
+                unchanged: stored.into_iter().map(|e| e.event_id).collect(),
+                regressed: true,
+                ..Default::default()
+            });
         };
-        _ = db_raw.write(&game_uuid.to_string(), &raw_events);
 
-        Some(raw_events.into_iter().map(|e| e.into_mapped_event(game_uuid)).collect())
+        let events = EventService::map_all(game_uuid, next_raw);
+        let seen: Vec<EventKey> = events.iter().map(ApiGameEvent::key).collect();
+        let mut diff = ApiEventsDiff::default();
+        for event in events {
+            match stored.iter().find(|e| e.key() == event.key()) {
+                None => diff.new.push(event),
+                Some(e) if *e != event => diff.updated.push(event),
+                Some(_) => diff.unchanged.push(event.event_id),
+            }
+        }
+        diff.removed = stored.into_iter()
+            .filter(|e| !seen.contains(&e.key()))
+            .map(|e| e.event_id)
+            .collect();
+        Ok(diff)
+    }
+
+    // Replaces all stored events, unless the new list goes back in time without a revision bump
+    pub fn store_all_raw(game_uuid: &str, events: Vec<external::event::PlayByPlay>) -> Vec<external::event::PlayByPlay> {
+        let db = EventService::get_raw_db();
+        db.update(&game_uuid.to_string(), |stored| {
+            let len = events.len();
+            match EventService::next_raw(game_uuid, stored, events) {
+                Some(events) => *stored = events,
+                None => log::warn!("[EVENT] Ignoring regressed events {game_uuid} {} -> {len}", stored.len()),
+            }
+            stored.clone()
+        })
+    }
+
+    // The raw events replacing the stored ones, capped, or None when they regressed
+    fn next_raw(game_uuid: &str, stored: &[external::event::PlayByPlay], mut events: Vec<external::event::PlayByPlay>) -> Option<Vec<external::event::PlayByPlay>> {
+        if EventService::is_regression(stored, &events) {
+            return None;
+        }
+        cap_events(game_uuid, &mut events, CONFIG.max_events_per_game, |e| e.to_type().is_key());
+        Some(events)
+    }
+
+    fn is_regression(stored: &[external::event::PlayByPlay], events: &[external::event::PlayByPlay]) -> bool {
+        let max_id = |e: &[external::event::PlayByPlay]| e.iter().map(|e| e.eventId).max();
+        let max_revision = |e: &[external::event::PlayByPlay]| e.iter().map(|e| e.revision).max();
+        max_id(events) < max_id(stored) && max_revision(events) <= max_revision(stored)
     }
 
     pub fn store_raw(game_uuid: &str, event: &external::event::PlayByPlay) -> bool {
-        let db = Db::<String, Vec<external::event::PlayByPlay>>::new("v2_events_raw");
-        let mut events = db.read(&game_uuid.to_string()).unwrap_or_default();
-        let new_event;
-        if let Some(pos) = events.iter().position(|e| e.eventId == event.eventId) {
-            events[pos] = event.clone();
-            new_event = false;
-        } else {
-            events.push(event.clone());
-            new_event = true;
+        let db = EventService::get_raw_db();
+        db.update(&game_uuid.to_string(), |events| {
+            if let Some(pos) = events.iter().position(|e| e.key() == event.key()) {
+                events[pos] = event.clone();
+                false
+            } else {
+                events.push(event.clone());
+                cap_events(game_uuid, events, CONFIG.max_events_per_game, |e| e.to_type().is_key());
+                true
+            }
+        })
+    }
+
+    pub fn add_processor(processor: Box<dyn EventProcessor>) {
+        EVENT_PROCESSORS.write().unwrap_or_else(|e| e.into_inner()).push(processor);
+    }
+
+    // The processors every stored, read and published event goes through
+    pub fn add_default_processors() {
+        EventService::add_processor(Box::new(tag_hat_trick));
+        let quick_strike_s = CONFIG.quick_strike_s;
+        EventService::add_processor(Box::new(move |event: &mut ApiGameEvent, stored: &[ApiGameEvent]| tag_quick_strike(event, stored, quick_strike_s)));
+    }
+
+    // Maps raw events in game time order, each processed with the events before it
+    fn map_all(game_uuid: &str, raw_events: Vec<external::event::PlayByPlay>) -> Vec<ApiGameEvent> {
+        let processors = EVENT_PROCESSORS.read().unwrap_or_else(|e| e.into_inner());
+        let mut events = EventService::map_with(game_uuid, raw_events, &processors);
+        EventService::resolve_cached_assists(game_uuid, &mut events);
+        events
+    }
+
+    // With the game's cached roster, only looked up when there are assists to resolve
+    fn resolve_cached_assists(game_uuid: &str, events: &mut [ApiGameEvent]) {
+        if events.iter().any(|e| matches!(&e.info, ApiEventType::Goal(g) if g.assist.is_some())) {
+            EventService::resolve_assists(game_uuid, events, &PlayerService::roster(game_uuid));
         }
-        _ = db.write(&game_uuid.to_string(), &events);
-        new_event
     }
 
-    pub fn store(game_uuid: &str, event: &ApiGameEvent) -> bool {
-        let db = Db::<String, Vec<ApiGameEvent>>::new("v2_events_2");
-        let mut events: Vec<ApiGameEvent> = db.read(&game_uuid.to_string()).unwrap_or_default();
-        let new_event;
-        if let Some(pos) = events.iter().position(|e| e.event_id == event.event_id) {
-            events[pos] = event.clone();
-            new_event = false;
-        } else {
-            events.push(event.clone());
-            new_event = true;
+    fn map_with(game_uuid: &str, raw_events: Vec<external::event::PlayByPlay>, processors: &[Box<dyn EventProcessor>]) -> Vec<ApiGameEvent> {
+        let mut events: Vec<ApiGameEvent> = raw_events.into_iter().map(|e| e.into_mapped_event(game_uuid)).collect();
+        events.sort_by(ApiGameEvent::cmp_gametime);
+        for i in 0..events.len() {
+            let (before, rest) = events.split_at_mut(i);
+            for p in processors {
+                p.process(&mut rest[0], before);
+            }
+        }
+        events
+    }
+
+    pub fn store(game_uuid: &str, event: &mut ApiGameEvent) -> bool {
+        EventService::resolve_cached_assists(game_uuid, std::slice::from_mut(event));
+        let processors = EVENT_PROCESSORS.read().unwrap_or_else(|e| e.into_inner());
+        EventService::store_with(game_uuid, event, &processors)
+    }
+
+    fn store_with(game_uuid: &str, event: &mut ApiGameEvent, processors: &[Box<dyn EventProcessor>]) -> bool {
+        let db = EventService::get_mapped_db();
+        db.update(&game_uuid.to_string(), |events| {
+            for p in processors {
+                p.process(event, events);
+            }
+            if let Some(pos) = events.iter().position(|e| e.key() == event.key()) {
+                events[pos] = event.clone();
+                false
+            } else {
+                events.push(event.clone());
+                cap_events(game_uuid, events, CONFIG.max_events_per_game, |e| e.info.is_key());
+                true
+            }
+        })
+    }
+
+    // Goals from the given games, out of the broadcast of all live games
+    pub fn live_goals(mut receiver: Receiver<WsMsg>, game_uuids: Vec<String>) -> impl Stream<Item = ApiGameEvent> {
+        async_stream::stream! {
+            loop {
+                match receiver.recv().await {
+                    Ok(WsMsg { body: WsMsgBody::Event { event }, .. }) => {
+                        if matches!(event.info, ApiEventType::Goal(_)) && game_uuids.contains(&event.game_uuid) {
+                            yield *event;
+                        }
+                    },
+                    Ok(_) => {},
+                    Err(RecvError::Lagged(n)) => log::warn!("[EVENT] Live goals lagged {n} messages"),
+                    Err(RecvError::Closed) => break,
+                }
+            }
+        }
+    }
+
+    // Emits only when a game's score differs from the last emission, also when a disallowed goal lowers it
+    pub fn score_changes(mut receiver: Receiver<WsMsg>, game_uuids: Vec<String>) -> impl Stream<Item = ApiScoreChange> {
+        async_stream::stream! {
+            let mut last: HashMap<String, (i16, i16)> = HashMap::new();
+            loop {
+                let score = match receiver.recv().await {
+                    Ok(WsMsg { game_uuid, body: WsMsgBody::Event { event } }) => match event.info {
+                        ApiEventType::Goal(g) => Some((game_uuid, (g.home_team_result, g.away_team_result))),
+                        _ => None,
+                    },
+                    Ok(WsMsg { game_uuid, body: WsMsgBody::Report { report } }) => Some((game_uuid, (report.home_team_result, report.away_team_result))),
+                    Ok(_) => None,
+                    Err(RecvError::Lagged(n)) => { log::warn!("[EVENT] Score changes lagged {n} messages"); None },
+                    Err(RecvError::Closed) => break,
+                };
+                if let Some((game_uuid, score)) = score.filter(|(game_uuid, _)| game_uuids.contains(game_uuid)) {
+                    if last.insert(game_uuid.clone(), score) != Some(score) {
+                        yield ApiScoreChange { game_uuid, home_team_result: score.0, away_team_result: score.1 };
+                    }
+                }
+            }
         }
-        _ = db.write(&game_uuid.to_string(), &events);
-        new_event
     }
 
     pub fn read(game_uuid: &str) -> Vec<ApiGameEvent> {
-        let db = Db::<String, Vec<external::event::PlayByPlay>>::new("v2_events_raw");
-        db.read(&game_uuid.to_string()).unwrap_or_default()
-            .into_iter().map(|e| e.into_mapped_event(game_uuid))
+        let db = EventService::get_raw_db();
+        EventService::map_all(game_uuid, db.read(&game_uuid.to_string()).unwrap_or_default())
+    }
+
+    // Like read, one event at a time in stored order. For going through many games without holding all of a game's events.
+    // Unprocessed, as processors need the events before each one
+    pub fn stream(game_uuid: &str) -> impl Iterator<Item = ApiGameEvent> {
+        let db = EventService::get_raw_db();
+        let game_uuid = game_uuid.to_string();
+        db.stream_items::<external::event::PlayByPlay>(&game_uuid).map(move |e| e.into_mapped_event(&game_uuid))
+    }
+
+    pub fn last_updated(game_uuid: &str) -> Option<SystemTime> {
+        let db = EventService::get_raw_db();
+        db.last_updated(&game_uuid.to_string())
+    }
+
+    pub fn mark_final(game_uuid: &str) {
+        let db = EventService::get_raw_db();
+        _ = db.mark_final(&game_uuid.to_string());
+    }
+
+    // Stored events with a feed id after event_id, in game time order
+    pub fn read_since(game_uuid: &str, event_id: i32) -> Vec<ApiGameEvent> {
+        EventService::read(game_uuid).into_iter()
+            .filter(|e| matches!(e.key(), EventKey::Feed(id) if id > event_id))
+            .collect()
+    }
+
+    // The client keeps its own cursor, last_acked is the highest event id it has acked. 0 is nothing acked
+    pub fn unacked(game_uuid: &str, last_acked: i32) -> Vec<ApiGameEvent> {
+        EventService::read_since(game_uuid, last_acked)
+    }
+
+    // Missing event id ranges in the stored events
+    pub fn detect_gaps(game_uuid: &str) -> Vec<Range<i32>> {
+        let db = EventService::get_raw_db();
+        let mut events = db.read(&game_uuid.to_string()).unwrap_or_default();
+        events.sort_by_key(|e| e.eventId);
+        events.windows(2)
+            .filter(|w| w[1].eventId > w[0].eventId + 1)
+            // Upstream drops ids when revising events, and bundles events happening at the same time
+            .filter(|w| w[0].revision <= 1 && w[1].revision <= 1)
+            .filter(|w| w[0].period.to_num() != w[1].period.to_num() || w[0].gametime != w[1].gametime)
+            .map(|w| w[0].eventId + 1..w[1].eventId)
+            .collect()
+    }
+
+    // Refetches all events if any are missing. Returns the gaps still left
+    pub async fn repair(game_uuid: &str) -> Vec<Range<i32>> {
+        let gaps = EventService::detect_gaps(game_uuid);
+        if !gaps.is_empty() {
+            log::warn!("[EVENT] Gaps in {game_uuid} {:?}, refetching", gaps);
+            _ = EventService::update(game_uuid, ThrottlePolicy::Force).await;
+        }
+        EventService::reconcile(game_uuid);
+        EventService::detect_gaps(game_uuid)
+    }
+
+    // Raw event ids the mapped store is missing, or holds another revision of
+    pub fn divergence(game_uuid: &str) -> Vec<String> {
+        let db_raw = EventService::get_raw_db();
+        let db = EventService::get_mapped_db();
+        let mapped = db.read(&game_uuid.to_string()).unwrap_or_default();
+        db_raw.read(&game_uuid.to_string()).unwrap_or_default().iter()
+            .filter(|raw| !mapped.iter().any(|e| e.key() == raw.key() && e.revision == raw.revision))
+            .map(|raw| raw.eventId.to_string())
             .collect()
     }
 
+    // Re-derives the mapped store from the raw store. Events only in the mapped store, like report
+    // events, are kept, as are unchanged events along with their tags. Returns true if anything changed
+    pub fn reconcile(game_uuid: &str) -> bool {
+        let diverged = EventService::divergence(game_uuid);
+        if diverged.is_empty() {
+            return false;
+        }
+        log::warn!("[EVENT] Mapped events for {game_uuid} diverged from raw {:?}, reconciling", diverged);
+        let db_raw = EventService::get_raw_db();
+        let raw_events = db_raw.read(&game_uuid.to_string()).unwrap_or_default();
+        let processors = EVENT_PROCESSORS.read().unwrap_or_else(|e| e.into_inner());
+        let db = EventService::get_mapped_db();
+        db.update(&game_uuid.to_string(), |events| {
+            let mut rebuilt: Vec<ApiGameEvent> = events.iter()
+                .filter(|e| !raw_events.iter().any(|raw| raw.key() == e.key()))
+                .cloned()
+                .collect();
+            for raw in &raw_events {
+                match events.iter().find(|e| e.key() == raw.key() && e.revision == raw.revision) {
+                    Some(e) => rebuilt.push(e.clone()),
+                    None => {
+                        let mut event = raw.clone().into_mapped_event(game_uuid);
+                        for p in processors.iter() {
+                            p.process(&mut event, &rebuilt);
+                        }
+                        rebuilt.push(event);
+                    },
+                }
+            }
+            rebuilt.sort_by(ApiGameEvent::cmp_gametime);
+            *events = rebuilt;
+        });
+        true
+    }
+
+    pub fn goal_gaps(game_uuid: &str, same_team: bool) -> Vec<ApiGoalGap> {
+        get_goal_gaps(EventService::read(game_uuid), same_team)
+    }
+
+    pub fn resolve_assists(game_uuid: &str, events: &mut [ApiGameEvent], roster: &[ApiAthlete]) {
+        if roster.is_empty() {
+            return;
+        }
+        for e in events.iter_mut() {
+            if let ApiEventType::Goal(g) = &mut e.info {
+                let missing = g.resolve_assists(roster);
+                if !missing.is_empty() {
+                    log::warn!("[EVENT] {game_uuid} {} assists not in the roster {:?}", e.event_id, missing);
+                }
+            }
+        }
+    }
+
+    // Goals scored or assisted by, and penalties taken by, the player with the given ApiAthlete.id
+    pub fn player_events(game_uuid: &str, player_id: i32) -> Vec<ApiGameEvent> {
+        EventService::read(game_uuid).into_iter()
+            .filter(|e| match &e.info {
+                ApiEventType::Goal(g) => g.player_id == Some(player_id) || g.assist_ids.contains(&player_id),
+                ApiEventType::Penalty(p) => p.player_id == Some(player_id),
+                _ => false,
+            })
+            .collect()
+    }
+
+    // Events within one period, e.g. GamePeriod::Overtime(2) for the second overtime or GamePeriod::Shootout
+    pub fn period_events(game_uuid: &str, period: GamePeriod) -> Vec<ApiGameEvent> {
+        let mut events: Vec<ApiGameEvent> = EventService::read(game_uuid).into_iter()
+            .filter(|e| e.period == period)
+            .collect();
+        events.sort_by(ApiGameEvent::cmp_gametime);
+        events
+    }
+
+    pub fn event_counts(game_uuid: &str) -> BTreeMap<EventTypeKey, usize> {
+        EventService::stream(game_uuid).fold(BTreeMap::new(), |mut counts, e| {
+            *counts.entry(EventTypeKey::from(&e.info)).or_default() += 1;
+            counts
+        })
+    }
+
+    pub fn game_winning_goal(game_uuid: &str) -> Option<GoalInfo> {
+        let goals = EventService::read(game_uuid).into_iter()
+            .filter(|e| matches!(e.info, ApiEventType::Goal(_)))
+            .collect();
+        get_game_winning_goal(goals)
+    }
+
+    // The goals scored in the shootout, only goals are mapped
+    pub fn shootout_goals(game_uuid: &str) -> Vec<GoalInfo> {
+        let db = EventService::get_raw_db();
+        db.read(&game_uuid.to_string()).unwrap_or_default().into_iter()
+            .filter(|e| matches!(e.class, PlayByPlayType::Goal(_)))
+            .filter_map(|e| match e.into_mapped_event(game_uuid).info {
+                ApiEventType::Goal(g) if g.shootout => Some(g),
+                _ => None,
+            })
+            .collect()
+    }
+
+}
+
+// Game time since the previous goal, or since the start of the game for the first one
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct ApiGoalGap {
+    pub event_id: String,
+    pub team: String,
+    pub seconds: i32,
+}
+
+// With same_team, the previous goal is the scoring team's own. Shootout goals are left out
+fn get_goal_gaps(mut goals: Vec<ApiGameEvent>, same_team: bool) -> Vec<ApiGoalGap> {
+    goals.sort_by(ApiGameEvent::cmp_gametime);
+    let mut previous: HashMap<String, i32> = HashMap::new();
+    goals.into_iter()
+        .filter_map(|e| match &e.info {
+            ApiEventType::Goal(g) if !g.shootout => Some((get_elapsed_seconds(&e.status, e.period, &e.gametime), g.team.clone(), e.event_id.clone())),
+            _ => None,
+        })
+        .map(|(elapsed, team, event_id)| {
+            let key = if same_team { team.clone() } else { String::new() };
+            let since = previous.insert(key, elapsed).unwrap_or_default();
+            ApiGoalGap { event_id, team, seconds: elapsed - since }
+        })
+        .collect()
+}
+
+// The winner's goal that put them one above the loser's final score, none for ties and shootouts
+fn get_game_winning_goal(mut goals: Vec<ApiGameEvent>) -> Option<GoalInfo> {
+    goals.sort_by(ApiGameEvent::cmp_gametime);
+    let goals: Vec<GoalInfo> = goals.into_iter()
+        .filter_map(|e| match e.info {
+            ApiEventType::Goal(g) if !g.shootout => Some(g),
+            _ => None,
+        })
+        .collect();
+    let last = goals.last()?;
+    let (home, away) = (last.home_team_result, last.away_team_result);
+    match home.cmp(&away) {
+        Ordering::Greater => goals.into_iter().find(|e| e.home_team_result == away + 1),
+        Ordering::Less => goals.into_iter().find(|e| e.away_team_result == home + 1),
+        Ordering::Equal => None,
+    }
 }
 
 #[cfg(test)]
 mod tests {
-    use crate::models2::external::event::Penalty;
+    use std::{sync::{Arc, Mutex}, time::Duration};
+
+    use futures::StreamExt;
+    use proptest::prelude::*;
+    use tracing::{Subscriber, span::{Attributes, Id}, field::{Field, Visit}};
+    use tracing_subscriber::{Layer, layer::{Context, SubscriberExt}};
+
+    use crate::models2::external::event::{Penalty, PlayByPlay, PlayByPlayType};
+
+    use tempdir::TempDir;
+
+    use crate::{game_report_service::{GameStatus, ApiGameReport, GameReportService}, models2::external::{self, player::PlayerStatsRsp}, models::{StringOrNum, GamePeriod, Season, GameType, League}, player_service::{ApiAthlete, ApiAthleteStats, ApiPlayerStats}, rest_client::{self, ThrottlePolicy}, api_season_service::ApiGame, db::Db, publish_service::PublishService};
 
-    use super::{Player, PenaltyInfo};
+    use super::{EventTypeKey, get_game_winning_goal, get_goal_gaps, get_elapsed_seconds, ApiGoalGap, Player, PenaltyInfo, ApiGameEvent, ApiEventType, PublishPolicy, EventService, Location, EventProcessor, GameEndInfo, ShotInfo, ShotKind, PenaltyReason, EventKey};
 
     #[test]
     fn parse_player() {
@@ -280,9 +1294,46 @@ mod tests {
         assert!(player_res.is_err());
     }
 
+    #[test]
+    fn parse_player_first_name_prefix_of_family_name() {
+        let player = "27 Erik Eriksson".parse::<Player>().unwrap();
+        assert_eq!(player.first_name, "Erik");
+        assert_eq!(player.family_name, "Eriksson");
+        assert_eq!(player.jersey, 27);
+    }
+
+    #[test]
+    fn parse_player_multiple_spaces() {
+        let player = " 7  Åke   Öberg-Lindström ".parse::<Player>().unwrap();
+        assert_eq!(player.first_name, "Åke");
+        assert_eq!(player.family_name, "Öberg-Lindström");
+        assert_eq!(player.jersey, 7);
+    }
+
+    proptest! {
+        #[test]
+        fn parse_player_never_panics(s in "\\PC*") {
+            _ = s.parse::<Player>();
+        }
+
+        #[test]
+        fn parse_player_round_trip(
+            jersey in 0..100i32,
+            first_name in "[A-Za-zÅÄÖåäöÆØæøÜü]{1,12}",
+            family_names in proptest::collection::vec("[A-Za-zÅÄÖåäöÆØæøÜü][A-Za-zÅÄÖåäöÆØæøÜü-]{0,12}", 1..3),
+            separator in " {1,3}",
+        ) {
+            let s = [jersey.to_string(), first_name.clone(), family_names.join(&separator)].join(&separator);
+            let player = s.parse::<Player>().unwrap();
+            prop_assert_eq!(player.jersey, jersey);
+            prop_assert_eq!(player.first_name, first_name);
+            prop_assert_eq!(player.family_name, family_names.join(" "));
+        }
+    }
+
     #[test]
     fn parse_penalty_info() {
-        let info = PenaltyInfo::new("1 Olle Olsson utvisas 5min, roughing", &Penalty { team: "LHF".to_string() });
+        let info = PenaltyInfo::new("1 Olle Olsson utvisas 5min, roughing", &Penalty { team: "LHF".to_string(), playerId: None });
         assert_eq!(info.penalty.unwrap(), "5min");
         assert_eq!(info.reason, "roughing");
         assert_eq!(info.player.unwrap().first_name, "Olle");
@@ -292,10 +1343,970 @@ mod tests {
 
     #[test]
     fn parse_penalty_info2() {
-        let info = PenaltyInfo::new("Too many players on ice", &Penalty { team: "LHF".to_string() });
+        let info = PenaltyInfo::new("Too many players on ice", &Penalty { team: "LHF".to_string(), playerId: None });
         assert_eq!(info.penalty, None);
         assert_eq!(info.reason, "Too many players on ice");
         assert_eq!(info.player, None);
         assert_eq!(info.team, "LHF");
     }
-}
\ No newline at end of file
+
+    #[test]
+    fn parse_penalty_info_without_comma() {
+        let info = PenaltyInfo::new("1 Olle Olsson utvisas 2 min Hooking", &Penalty { team: "LHF".to_string(), playerId: None });
+        assert_eq!(info.penalty.unwrap(), "2 min");
+        assert_eq!(info.reason, "Hooking");
+        assert_eq!(info.player.unwrap().family_name, "Olsson");
+
+        let info = PenaltyInfo::new("1 Olle Olsson utvisas 2+10 min. Roughing", &Penalty { team: "LHF".to_string(), playerId: None });
+        assert_eq!(info.penalty.unwrap(), "2+10 min");
+        assert_eq!(info.reason, "Roughing");
+
+        let info = PenaltyInfo::new("1 Olle Olsson utvisas Matchstraff", &Penalty { team: "LHF".to_string(), playerId: None });
+        assert_eq!(info.penalty, None);
+        assert_eq!(info.reason, "1 Olle Olsson utvisas Matchstraff");
+    }
+
+    #[test]
+    fn penalty_reason_codes() {
+        let reason = |description: &str| PenaltyInfo::new(description, &Penalty { team: "LHF".to_string(), playerId: None }).reason_code;
+        assert_eq!(reason("1 Olle Olsson utvisas 2 min, Fällning"), PenaltyReason::Tripping);
+        assert_eq!(reason("1 Olle Olsson utvisas 2 min, Hakning"), PenaltyReason::Hooking);
+        assert_eq!(reason("1 Olle Olsson utvisas 2 min, Hooking"), PenaltyReason::Hooking);
+        assert_eq!(reason("1 Olle Olsson utvisas 2 min, Hög klubba"), PenaltyReason::HighSticking);
+        assert_eq!(reason("1 Olle Olsson utvisas 2 min, Hålla klubba"), PenaltyReason::HoldingStick);
+        assert_eq!(reason("1 Olle Olsson utvisas 2 min, Fasthållning"), PenaltyReason::Holding);
+        assert_eq!(reason("1 Olle Olsson utvisas 5 min, Tackling bakifrån"), PenaltyReason::CheckingFromBehind);
+        assert_eq!(reason("Lagstraff utvisas 2 min, För många spelare på isen"), PenaltyReason::TooManyMen);
+        assert_eq!(reason("1 Olle Olsson utvisas 2 min, Ruffighet"), PenaltyReason::Roughing);
+
+        let info = PenaltyInfo::new("1 Olle Olsson utvisas 2 min, Något nytt", &Penalty { team: "LHF".to_string(), playerId: None });
+        assert_eq!((info.reason_code, info.reason.as_str()), (PenaltyReason::Unknown, "Något nytt"));
+        assert_eq!(reason("1 Olle Olsson utvisas 10 min, Game misconduct"), PenaltyReason::GameMisconduct);
+        assert_eq!(reason("1 Olle Olsson utvisas 10 min, Misconduct"), PenaltyReason::Misconduct);
+        assert_eq!(serde_json::to_value(PenaltyReason::Tripping).unwrap(), "Tripping");
+        assert_eq!(serde_json::to_value(PenaltyReason::default()).unwrap(), "Unknown");
+        assert_eq!(serde_json::from_str::<PenaltyReason>("\"SomethingNewer\"").unwrap(), PenaltyReason::Unknown);
+    }
+
+    #[test]
+    fn parse_bench_penalty() {
+        let info = PenaltyInfo::new("Lagstraff utvisas 2 min, Too many players on ice", &Penalty { team: "LHF".to_string(), playerId: None });
+        assert!(info.bench);
+        assert_eq!(info.player, None);
+        assert_eq!(info.penalty.unwrap(), "2 min");
+        assert_eq!(info.reason, "Too many players on ice");
+
+        let info = PenaltyInfo::new("Bench minor utvisas 2 min Unsportsmanlike conduct", &Penalty { team: "LHF".to_string(), playerId: None });
+        assert!(info.bench);
+        assert_eq!(info.player, None);
+
+        let info = PenaltyInfo::new("1 Olle Olsson utvisas 2 min, Hooking", &Penalty { team: "LHF".to_string(), playerId: None });
+        assert!(!info.bench);
+        assert!(info.player.is_some());
+
+        // only the reason counts, not the player's name
+        let info = PenaltyInfo::new("12 Kalle Benchsson utvisas 2 min, Hooking", &Penalty { team: "LHF".to_string(), playerId: None });
+        assert!(!info.bench);
+        assert!(info.player.is_some());
+        let info = PenaltyInfo::new("Coach utvisas 10 min, Misconduct", &Penalty { team: "LHF".to_string(), playerId: None });
+        assert!(info.bench);
+        assert_eq!(info.reason, "Misconduct");
+    }
+
+    #[test]
+    fn publish_policy_default() {
+        let penalty = get_event(ApiEventType::Penalty(PenaltyInfo::new("Too many players on ice", &Penalty { team: "LHF".to_string(), playerId: None })));
+        let start = get_event(ApiEventType::GameStart);
+        let policy = PublishPolicy::default();
+        assert!(!penalty.should_publish(&policy));
+        assert!(start.should_publish(&policy));
+    }
+
+    #[test]
+    fn publish_policy_with_penalties() {
+        let penalty = get_event(ApiEventType::Penalty(PenaltyInfo::new("Too many players on ice", &Penalty { team: "LHF".to_string(), playerId: None })));
+        let policy = PublishPolicy { penalty: true, ..Default::default() };
+        assert!(penalty.should_publish(&policy));
+    }
+
+    #[tokio::test(flavor = "multi_thread", worker_threads = 10)]
+    async fn concurrent_store() {
+        std::env::set_var("DB_PATH", TempDir::new("test").expect("dir to be created").path().to_str().unwrap());
+        let game_uuid = "concurrent_store";
+        let handles: Vec<_> = (0..10).map(|task| tokio::spawn(async move {
+            for i in 0..10 {
+                let mut event = get_event(ApiEventType::General);
+                event.game_uuid = game_uuid.to_string();
+                event.event_id = format!("{}", task * 10 + i);
+                assert!(EventService::store(game_uuid, &mut event));
+            }
+        })).collect();
+        for h in handles {
+            h.await.unwrap();
+        }
+
+        let stored = EventService::get_mapped_db().read(&game_uuid.to_string()).unwrap();
+        assert_eq!(stored.len(), 100);
+    }
+
+    #[test]
+    fn unversioned_events_are_kept() {
+        std::env::set_var("DB_PATH", TempDir::new("test").expect("dir to be created").path().to_str().unwrap());
+        let game_uuid = "unversioned_events_are_kept";
+        let mut event = get_event(ApiEventType::General);
+        event.game_uuid = game_uuid.to_string();
+        // stored before the namespace was versioned
+        _ = Db::<String, Vec<ApiGameEvent>>::new_compressed("v2_events_2").write(&game_uuid.to_string(), &vec![event.clone()]);
+
+        assert_eq!(EventService::get_mapped_db().read(&game_uuid.to_string()).map(|e| e.len()), Some(1));
+        // already stored, not new
+        assert!(!EventService::store(game_uuid, &mut event));
+    }
+
+    #[test]
+    fn unknown_reason_code_is_migrated() {
+        std::env::set_var("DB_PATH", TempDir::new("test").expect("dir to be created").path().to_str().unwrap());
+        let game_uuid = "unknown_reason_code_is_migrated";
+        let mut event = get_event(ApiEventType::Penalty(PenaltyInfo::new("1 Olle Olsson utvisas 2 min, Något nytt", &Penalty { team: "LHF".to_string(), playerId: None })));
+        event.game_uuid = game_uuid.to_string();
+        let mut stored = serde_json::to_value(vec![event.clone()]).unwrap();
+        // info is flattened into the event
+        stored[0]["reason_code"] = serde_json::json!({ "Unknown": "Något nytt" });
+        _ = Db::<String, serde_json::Value>::new_compressed("v2_events_2").with_version(1).write(&game_uuid.to_string(), &stored);
+
+        // not readable as is, and so refetched
+        let unmigrated = Db::<String, Vec<ApiGameEvent>>::new_compressed("v2_events_2").with_version(1);
+        assert_eq!(unmigrated.read(&game_uuid.to_string()), None);
+        assert!(unmigrated.is_stale(&game_uuid.to_string(), None));
+        assert!(!EventService::get_mapped_db().is_stale(&game_uuid.to_string(), None));
+        assert_eq!(EventService::get_mapped_db().read(&game_uuid.to_string()), Some(vec![event]));
+    }
+
+    #[test]
+    fn processor_tags_hat_trick() {
+        std::env::set_var("DB_PATH", TempDir::new("test").expect("dir to be created").path().to_str().unwrap());
+        let game_uuid = "processor_tags_hat_trick";
+        let processors: Vec<Box<dyn EventProcessor>> = vec![Box::new(super::tag_hat_trick)];
+
+        let mut tags = vec![];
+        for id in 1..=4 {
+            let mut goal = get_goal_json("").into_mapped_event(game_uuid);
+            goal.event_id = id.to_string();
+            EventService::store_with(game_uuid, &mut goal, &processors);
+            tags.push(goal.tags);
+        }
+        assert_eq!(tags, vec![vec![], vec![], vec!["hat_trick".to_string()], vec![]]);
+
+        // read and refetched events are processed from the raw store the same way
+        let raw: Vec<PlayByPlay> = (1..=4).map(|id| {
+            let mut goal = get_goal_json("");
+            goal.eventId = id;
+            goal.gametime = format!("0{id}:00");
+            goal
+        }).collect();
+        let tags: Vec<Vec<String>> = EventService::map_with(game_uuid, raw, &processors).into_iter().map(|e| e.tags).collect();
+        assert_eq!(tags, vec![vec![], vec![], vec!["hat_trick".to_string()], vec![]]);
+    }
+
+    #[test]
+    fn sort_by_gametime() {
+        let get = |id: &str, status: GameStatus, gametime: &str| {
+            let mut e = get_event(ApiEventType::General);
+            e.event_id = id.to_string();
+            e.status = status;
+            e.gametime = gametime.to_string();
+            e
+        };
+        let mut events = [
+            get("so", GameStatus::Shootout, "00:00"),
+            get("p2", GameStatus::Period2, "05:00"),
+            get("ot", GameStatus::Overtime, "03:12"),
+            get("p1_late", GameStatus::Period1, "19:59"),
+            get("p3", GameStatus::Period3, "00:10"),
+            get("p1_early", GameStatus::Period1, "01:05"),
+            get("end", GameStatus::Finished, "65:00"),
+        ];
+        events.sort_by(ApiGameEvent::cmp_gametime);
+        let ids: Vec<&str> = events.iter().map(|e| e.event_id.as_str()).collect();
+        assert_eq!(ids, vec!["p1_early", "p1_late", "p2", "p3", "ot", "so", "end"]);
+    }
+
+    #[test]
+    fn sort_intermissions_by_period() {
+        let get = |id: &str, status: GameStatus, period: GamePeriod, gametime: &str| {
+            let mut e = get_event(ApiEventType::General);
+            e.event_id = id.to_string();
+            e.status = status;
+            e.period = period;
+            e.gametime = gametime.to_string();
+            e
+        };
+        let mut events = [
+            get("p2", GameStatus::Period2, GamePeriod::Regulation(2), "01:00"),
+            get("after_p2", GameStatus::Intermission, GamePeriod::Regulation(2), "20:00"),
+            get("after_p1", GameStatus::Intermission, GamePeriod::Regulation(1), "20:00"),
+            get("p1", GameStatus::Period1, GamePeriod::Regulation(1), "19:00"),
+            get("p3", GameStatus::Period3, GamePeriod::Regulation(3), "00:30"),
+        ];
+        events.sort_by(ApiGameEvent::cmp_gametime);
+        let ids: Vec<&str> = events.iter().map(|e| e.event_id.as_str()).collect();
+        assert_eq!(ids, vec!["p1", "after_p1", "p2", "after_p2", "p3"]);
+        assert_eq!(get_elapsed_seconds(&GameStatus::Intermission, GamePeriod::Regulation(1), "20:00"), 20 * 60);
+    }
+
+    #[test]
+    fn sort_multiple_overtimes() {
+        let events: Vec<ApiGameEvent> = [("2ot", 5, "02:00"), ("ot", 4, "15:00"), ("p3", 3, "19:00")].iter()
+            .map(|(id, period, gametime)| {
+                let mut json = get_general_json(1, 1);
+                json.period = StringOrNum::Number(*period);
+                json.gametime = gametime.to_string();
+                let mut e = json.into_mapped_event("game_uuid");
+                e.event_id = id.to_string();
+                e
+            })
+            .collect();
+        let mut sorted = events.clone();
+        sorted.sort_by(ApiGameEvent::cmp_gametime);
+        let ids: Vec<&str> = sorted.iter().map(|e| e.event_id.as_str()).collect();
+        assert_eq!(ids, vec!["p3", "ot", "2ot"]);
+        assert_eq!(sorted[2].status, GameStatus::Overtime);
+        assert_eq!(sorted[2].period, GamePeriod::Overtime(2));
+    }
+
+    #[test]
+    fn late_tying_goal_is_important() {
+        let goal = |period: i16, gametime: &str, home: i16, away: i16| {
+            let mut event = PlayByPlay { period: StringOrNum::Number(period), gametime: gametime.to_string(), ..get_goal_json("") }.into_mapped_event("game_uuid");
+            if let ApiEventType::Goal(g) = &mut event.info {
+                (g.home_team_result, g.away_team_result) = (home, away);
+            }
+            event
+        };
+        let shot = ApiGameEvent { info: ApiEventType::Shot(ShotInfo { team: "LHF".to_string(), location: Location { x: 0.0, y: 0.0 }, kind: ShotKind::OnGoal }), ..get_event(ApiEventType::General) };
+
+        let late_tying = goal(3, "18:30", 2, 2);
+        assert!(late_tying.importance() > shot.importance());
+        assert!(late_tying.importance() > goal(1, "05:00", 2, 2).importance());
+        assert!(goal(1, "05:00", 4, 0).importance() > get_event(ApiEventType::GameEnd(GameEndInfo { winner: None, is_final: true })).importance());
+        assert_eq!(goal(4, "02:00", 3, 2).importance(), 100);
+        assert_eq!(get_event(ApiEventType::General).importance(), 0);
+    }
+
+    #[test]
+    fn map_livefeed_review() {
+        let livefeed = |description: &str| -> ApiEventType {
+            let json = format!(r#"{{
+                "eventId": 40, "revision": 1, "hash": "abc", "period": 2, "gametime": "05:12", "description": "{description}",
+                "class": "Livefeed_SHL"
+            }}"#);
+            serde_json::from_str::<PlayByPlay>(&json).unwrap().into_mapped_event("game_uuid").info
+        };
+        assert_eq!(livefeed("Videogranskning av målet"), ApiEventType::Review);
+        assert_eq!(livefeed("Coach's challenge från LHF"), ApiEventType::Challenge);
+        assert_eq!(livefeed("Tack för ikväll"), ApiEventType::General);
+        assert_eq!(serde_json::to_value(ApiEventType::Review).unwrap()["type"], "Review");
+    }
+
+    #[test]
+    fn map_unassisted_goal() {
+        match get_goal_json("").into_mapped_event("game_uuid").info {
+            ApiEventType::Goal(info) => assert_eq!((info.assist, info.unassisted), (None, true)),
+            _ => panic!("not good"),
+        }
+
+        let mut goal = get_goal_json("");
+        if let PlayByPlayType::Goal(g) = &mut goal.class {
+            g.extra.assist = "  ".to_string();
+        }
+        match goal.into_mapped_event("game_uuid").info {
+            ApiEventType::Goal(info) => assert_eq!((info.assist, info.unassisted), (None, true)),
+            _ => panic!("not good"),
+        }
+
+        let mut goal = get_goal_json("");
+        if let PlayByPlayType::Goal(g) = &mut goal.class {
+            g.extra.assist = "9 Erik Eriksson".to_string();
+        }
+        match goal.into_mapped_event("game_uuid").info {
+            ApiEventType::Goal(info) => assert_eq!((info.assist.as_deref(), info.unassisted), (Some("9 Erik Eriksson"), false)),
+            _ => panic!("not good"),
+        }
+    }
+
+    #[test]
+    fn map_overtime_start() {
+        let period = |period: i16, status: &str| {
+            let json = format!(r#"{{
+                "eventId": 1, "revision": 1, "hash": "abc", "period": {period}, "gametime": "00:00", "description": "Period {period} startade",
+                "class": "Period", "extra": {{ "gameStatus": "{status}" }}
+            }}"#);
+            serde_json::from_str::<PlayByPlay>(&json).unwrap().into_mapped_event("game_uuid").info
+        };
+        assert_eq!(period(4, "Playing"), ApiEventType::OvertimeStart);
+        assert_eq!(period(5, "Playing"), ApiEventType::OvertimeStart);
+        assert_eq!(period(4, "OverTime"), ApiEventType::OvertimeStart);
+        assert_eq!(period(3, "Playing"), ApiEventType::PeriodStart);
+        assert_eq!(period(4, "Intermission"), ApiEventType::PeriodEnd);
+        assert_eq!(serde_json::to_string(&EventTypeKey::OvertimeStart).unwrap(), r#""overtime_start""#);
+    }
+
+    #[test]
+    fn map_own_goal() {
+        let mut goal = get_goal_json("");
+        if let PlayByPlayType::Goal(g) = &mut goal.class {
+            g.extra.scorerLong = "Självmål".to_string();
+        }
+        match goal.into_mapped_event("game_uuid").info {
+            ApiEventType::Goal(info) => assert_eq!((info.own_goal, info.scorer_unknown, info.player), (true, false, None)),
+            _ => panic!("not good"),
+        }
+
+        let mut goal = get_goal_json("");
+        if let PlayByPlayType::Goal(g) = &mut goal.class {
+            g.extra.scorerLong = " ".to_string();
+        }
+        match goal.into_mapped_event("game_uuid").info {
+            ApiEventType::Goal(info) => assert_eq!((info.own_goal, info.scorer_unknown), (false, true)),
+            _ => panic!("not good"),
+        }
+
+        match get_goal_json("").into_mapped_event("game_uuid").info {
+            ApiEventType::Goal(info) => assert_eq!((info.own_goal, info.scorer_unknown), (false, false)),
+            _ => panic!("not good"),
+        }
+    }
+
+    #[test]
+    fn map_goal_player_id() {
+        let goal = get_goal_json(r#""playerId": 123456,"#);
+        match goal.into_mapped_event("game_uuid").info {
+            ApiEventType::Goal(info) => assert_eq!(info.player_id, Some(123456)),
+            _ => panic!("not good"),
+        }
+
+        let goal = get_goal_json(r#""scorerId": "1234","#);
+        match goal.into_mapped_event("game_uuid").info {
+            ApiEventType::Goal(info) => assert_eq!(info.player_id, Some(1234)),
+            _ => panic!("not good"),
+        }
+
+        match get_goal_json("").into_mapped_event("game_uuid").info {
+            ApiEventType::Goal(info) => assert_eq!(info.player_id, None),
+            _ => panic!("not good"),
+        }
+    }
+
+    #[test]
+    fn map_hit() {
+        // not in the fixture, the feed hasn't surfaced any hits yet
+        let hit: PlayByPlay = serde_json::from_str(r#"{
+            "eventId": 20, "revision": 1, "hash": "abc", "period": 2, "gametime": "04:10", "description": "23 Olle Olsson",
+            "class": "Hit", "team": "fbk"
+        }"#).unwrap();
+        let event = hit.into_mapped_event("game_uuid");
+        match &event.info {
+            ApiEventType::Hit(info) => {
+                assert_eq!(info.team, "FBK");
+                assert_eq!(info.player.as_ref().map(|e| e.jersey), Some(23));
+            },
+            _ => panic!("not a hit"),
+        }
+        assert!(!event.should_publish(&PublishPolicy::default()));
+    }
+
+    #[test]
+    fn map_goal_media_url() {
+        let goal = get_goal_json(r#""mediaUrl": "https://example.com/clip/1","#);
+        match goal.into_mapped_event("game_uuid").info {
+            ApiEventType::Goal(info) => assert_eq!(info.media_url.unwrap(), "https://example.com/clip/1"),
+            _ => panic!("not good"),
+        }
+
+        let goal = get_goal_json("");
+        match goal.into_mapped_event("game_uuid").info {
+            ApiEventType::Goal(info) => assert_eq!(info.media_url, None),
+            _ => panic!("not good"),
+        }
+    }
+
+    #[test]
+    fn regressed_events_are_ignored() {
+        std::env::set_var("DB_PATH", TempDir::new("test").expect("dir to be created").path().to_str().unwrap());
+        let game_uuid = "regressed_events";
+        let all: Vec<PlayByPlay> = (1..=5).map(|id| get_general_json(id, 1)).collect();
+        assert_eq!(EventService::store_all_raw(game_uuid, all.clone()).len(), 5);
+
+        let regressed = all[0..3].to_vec();
+        assert_eq!(EventService::store_all_raw(game_uuid, regressed).len(), 5);
+        assert_eq!(EventService::store_all_raw(game_uuid, vec![]).len(), 5);
+        assert_eq!(EventService::read(game_uuid).len(), 5);
+
+        let revised = vec![get_general_json(1, 2), get_general_json(2, 2)];
+        assert_eq!(EventService::store_all_raw(game_uuid, revised).len(), 2);
+    }
+
+    #[tokio::test]
+    async fn live_goals_from_multiple_games() {
+        let (sender, receiver) = tokio::sync::broadcast::channel(10);
+        let stream = EventService::live_goals(receiver, vec!["game_a".to_string(), "game_b".to_string()]);
+        futures::pin_mut!(stream);
+
+        let goal_a = get_goal_json("").into_mapped_event("game_a");
+        let shot_b = get_general_json(2, 1).into_mapped_event("game_b");
+        let goal_c = get_goal_json("").into_mapped_event("game_c");
+        let goal_b = get_goal_json("").into_mapped_event("game_b");
+        for e in [goal_a, shot_b, goal_c, goal_b] {
+            sender.send(e.into()).unwrap();
+        }
+        drop(sender);
+
+        let goals: Vec<ApiGameEvent> = stream.collect().await;
+        let games: Vec<&str> = goals.iter().map(|e| e.game_uuid.as_str()).collect();
+        assert_eq!(games, vec!["game_a", "game_b"]);
+    }
+
+    #[test]
+    fn location_without_float_noise() {
+        let location = Location { x: 0.1 + 0.2, y: 12.345678 };
+        let json = serde_json::to_string(&location).unwrap();
+        assert_eq!(json, r#"{"x":0.3,"y":12.35}"#);
+
+        let parsed: Location = serde_json::from_str(&json).unwrap();
+        assert_eq!(serde_json::to_string(&parsed).unwrap(), json);
+    }
+
+    #[tokio::test]
+    async fn update_swr_returns_cached_and_refreshes() {
+        std::env::set_var("DB_PATH", TempDir::new("test").expect("dir to be created").path().to_str().unwrap());
+        let game_uuid = "update_swr";
+        EventService::store_all_raw(game_uuid, vec![get_general_json(1, 1)]);
+
+        let before = std::time::Instant::now();
+        let publish_service = PublishService::new(PublishPolicy::default(), vec![]);
+        let events = EventService::update_swr_with(game_uuid, ThrottlePolicy::Force, &publish_service, |_| async {
+            tokio::time::sleep(Duration::from_millis(100)).await;
+            Ok(vec![get_general_json(1, 1), get_general_json(2, 1)])
+        });
+        assert!(before.elapsed() < Duration::from_millis(100));
+        assert_eq!(events.len(), 1);
+
+        tokio::time::sleep(Duration::from_millis(300)).await;
+        assert_eq!(EventService::read(game_uuid).len(), 2);
+    }
+
+    #[tokio::test]
+    async fn dry_run_leaves_db_unchanged() {
+        std::env::set_var("DB_PATH", TempDir::new("test").expect("dir to be created").path().to_str().unwrap());
+        let game_uuid = "dry_run_leaves_db_unchanged";
+        EventService::store_all_raw(game_uuid, vec![get_general_json(1, 1), get_general_json(2, 1), get_general_json(3, 1)]);
+        let before = EventService::read(game_uuid);
+
+        let diff = EventService::update_dry_run_with(game_uuid, |_| async {
+            Ok(vec![get_general_json(1, 1), get_general_json(2, 2), get_general_json(3, 1), get_general_json(4, 1)])
+        }).await.unwrap();
+        let ids = |e: &[ApiGameEvent]| e.iter().map(|e| e.event_id.clone()).collect::<Vec<String>>();
+        assert_eq!(ids(&diff.new), vec!["4"]);
+        assert_eq!((ids(&diff.updated), diff.updated[0].revision), (vec!["2".to_string()], 2));
+        assert_eq!(diff.unchanged, vec!["1", "3"]);
+        assert!(diff.removed.is_empty() && !diff.regressed);
+        assert_eq!(EventService::read(game_uuid), before);
+
+        let diff = EventService::update_dry_run_with(game_uuid, |_| async { Ok(vec![get_general_json(1, 1), get_general_json(3, 1), get_general_json(4, 1)]) }).await.unwrap();
+        assert_eq!(diff.removed, vec!["2"]);
+
+        let diff = EventService::update_dry_run_with(game_uuid, |_| async { Ok(vec![get_general_json(1, 1)]) }).await.unwrap();
+        assert!(diff.regressed);
+        assert_eq!(EventService::read(game_uuid), before);
+    }
+
+    #[test]
+    fn cap_keeps_key_events() {
+        let mut events: Vec<ApiGameEvent> = vec![
+            get_goal_json("").into_mapped_event("game_uuid"),
+            get_event(ApiEventType::General),
+            get_event(ApiEventType::Timeout),
+            get_goal_json("").into_mapped_event("game_uuid"),
+            get_event(ApiEventType::PeriodEnd),
+            get_event(ApiEventType::GameEnd(GameEndInfo { winner: None, is_final: false })),
+        ];
+        super::cap_events("game_uuid", &mut events, 4, |e| e.info.is_key());
+        let kept: Vec<bool> = events.iter().map(|e| e.info.is_key()).collect();
+        assert_eq!(kept, vec![true, true, false, true]);
+        assert!(matches!(events[2].info, ApiEventType::PeriodEnd));
+
+        super::cap_events("game_uuid", &mut events, 1, |e| e.info.is_key());
+        assert_eq!(events.len(), 3);
+    }
+
+    #[test]
+    fn detect_gaps() {
+        std::env::set_var("DB_PATH", TempDir::new("test").expect("dir to be created").path().to_str().unwrap());
+        let game_uuid = "detect_gaps";
+        let events = [1, 2, 5, 6, 9].into_iter().map(|id| get_general_json(id, 1)).collect();
+        EventService::store_all_raw(game_uuid, events);
+        assert_eq!(EventService::detect_gaps(game_uuid), vec![3..5, 7..9]);
+
+        let revised = vec![get_general_json(1, 1), get_general_json(4, 2)];
+        EventService::store_all_raw("detect_gaps_revised", revised);
+        assert_eq!(EventService::detect_gaps("detect_gaps_revised"), vec![]);
+    }
+
+    #[test]
+    fn game_winning_goal_after_loser_final_score() {
+        let goals = |scores: &[(i16, i16)]| scores.iter().enumerate()
+            .map(|(i, (home, away))| get_score_json(i as i32 + 1, 1, &format!("{:02}:00", i + 1), *home, *away).into_mapped_event("game_uuid"))
+            .collect::<Vec<ApiGameEvent>>();
+        let gwg = get_game_winning_goal(goals(&[(1, 0), (2, 0), (2, 1), (3, 1), (4, 2)])).unwrap();
+        assert_eq!((gwg.home_team_result, gwg.away_team_result), (3, 1));
+        let gwg = get_game_winning_goal(goals(&[(1, 0), (1, 1), (1, 2)])).unwrap();
+        assert_eq!((gwg.home_team_result, gwg.away_team_result), (1, 2));
+        assert!(get_game_winning_goal(goals(&[(1, 0), (1, 1)])).is_none());
+        assert!(get_game_winning_goal(vec![]).is_none());
+    }
+
+    #[derive(Clone, Default)]
+    struct SpanCapture {
+        spans: Arc<Mutex<Vec<String>>>,
+    }
+
+    struct GameUuidVisitor(Option<String>);
+
+    impl Visit for GameUuidVisitor {
+        fn record_str(&mut self, field: &Field, value: &str) {
+            if field.name() == "game_uuid" {
+                self.0 = Some(value.to_string());
+            }
+        }
+
+        fn record_debug(&mut self, _field: &Field, _value: &dyn std::fmt::Debug) {}
+    }
+
+    impl<S: Subscriber> Layer<S> for SpanCapture {
+        fn on_new_span(&self, attrs: &Attributes<'_>, _id: &Id, _ctx: Context<'_, S>) {
+            let mut visitor = GameUuidVisitor(None);
+            attrs.record(&mut visitor);
+            let name = format!("{}:{}", attrs.metadata().name(), visitor.0.unwrap_or_default());
+            self.spans.lock().unwrap().push(name);
+        }
+    }
+
+    #[tokio::test]
+    async fn update_emits_phase_spans() {
+        std::env::set_var("DB_PATH", TempDir::new("test").expect("dir to be created").path().to_str().unwrap());
+        let game_uuid = "update_emits_phase_spans";
+        EventService::store_all_raw(game_uuid, vec![get_general_json(1, 1)]);
+
+        let capture = SpanCapture::default();
+        let _guard = tracing::subscriber::set_default(tracing_subscriber::registry().with(capture.clone()));
+        let events = EventService::update(game_uuid, ThrottlePolicy::Window(Duration::from_secs(3600))).await.unwrap();
+        assert_eq!(events.len(), 1);
+
+        let spans = capture.spans.lock().unwrap().clone();
+        assert_eq!(spans, vec![
+            format!("fetch:{game_uuid}"),
+            format!("store:{game_uuid}"),
+            format!("map:{game_uuid}"),
+        ]);
+    }
+
+    fn get_score_json(event_id: i32, period: i16, gametime: &str, home: i16, away: i16) -> PlayByPlay {
+        let json = format!(r#"{{
+            "eventId": {event_id}, "revision": 1, "hash": "abc", "period": {period}, "gametime": "{gametime}", "description": "Mål",
+            "class": "Goal", "team": "LHF", "location": {{ "x": 1.0, "y": 2.0 }},
+            "extra": {{ "scorerLong": "1 Olle Olsson", "teamAdvantage": "EQ", "homeAgainst": {away}, "homeForward": {home}, "assist": "" }}
+        }}"#);
+        serde_json::from_str(&json).unwrap()
+    }
+
+    #[tokio::test]
+    async fn score_changes_on_goal_and_disallowance() {
+        let (sender, receiver) = tokio::sync::broadcast::channel(10);
+        let stream = EventService::score_changes(receiver, vec!["game_uuid".to_string()]);
+        futures::pin_mut!(stream);
+
+        let goal = get_goal_json("").into_mapped_event("game_uuid");
+        let mut revised = goal.clone();
+        revised.revision = 2;
+        let report = |home: i16| ApiGameReport {
+            game_uuid: "game_uuid".to_string(),
+            gametime: "12:34".to_string(),
+            status: GameStatus::Period1,
+            period: Default::default(),
+            home_team_code: "LHF".to_string(),
+            away_team_code: "FHC".to_string(),
+            home_team_result: home,
+            away_team_result: 0,
+        };
+        sender.send(goal.into()).unwrap();
+        sender.send(revised.into()).unwrap();
+        sender.send(report(1).into()).unwrap();
+        sender.send(get_general_json(13, 1).into_mapped_event("game_uuid").into()).unwrap();
+        sender.send(report(0).into()).unwrap();
+        drop(sender);
+
+        let changes: Vec<(i16, i16)> = stream.map(|e| (e.home_team_result, e.away_team_result)).collect().await;
+        assert_eq!(changes, vec![(1, 0), (0, 0)]);
+    }
+
+    #[test]
+    fn event_counts_by_type() {
+        std::env::set_var("DB_PATH", TempDir::new("test").expect("dir to be created").path().to_str().unwrap());
+        let game_uuid = "event_counts_by_type";
+        let penalty: PlayByPlay = serde_json::from_str(r#"{
+            "eventId": 7, "revision": 1, "hash": "abc", "period": 2, "gametime": "01:00", "description": "1 Olle Olsson utvisas 2 min, Hooking",
+            "class": "Penalty", "team": "LHF"
+        }"#).unwrap();
+        let shot: PlayByPlay = serde_json::from_str(r#"{
+            "eventId": 8, "revision": 1, "hash": "abc", "period": 2, "gametime": "02:00", "description": "",
+            "class": "ShotWide", "team": "LHF", "location": { "x": 1.0, "y": 2.0 }
+        }"#).unwrap();
+        EventService::store_all_raw(game_uuid, vec![
+            get_general_json(1, 1),
+            get_score_json(2, 1, "05:00", 1, 0),
+            get_score_json(3, 1, "06:00", 2, 0),
+            penalty,
+            shot.clone(),
+            PlayByPlay { eventId: 9, ..shot },
+        ]);
+
+        let counts = EventService::event_counts(game_uuid);
+        assert_eq!(counts.get(&EventTypeKey::Goal), Some(&2));
+        assert_eq!(counts.get(&EventTypeKey::Shot), Some(&2));
+        assert_eq!(counts.get(&EventTypeKey::Penalty), Some(&1));
+        assert_eq!(counts.get(&EventTypeKey::General), Some(&1));
+        assert_eq!(counts.get(&EventTypeKey::Timeout), None);
+        assert_eq!(serde_json::to_string(&EventTypeKey::PeriodStart).unwrap(), r#""period_start""#);
+        let json = serde_json::to_value(&counts).unwrap();
+        assert_eq!(json["goal"], 2);
+    }
+
+    #[test]
+    fn unacked_after_last_acked() {
+        std::env::set_var("DB_PATH", TempDir::new("test").expect("dir to be created").path().to_str().unwrap());
+        let game_uuid = "unacked_after_last_acked";
+        EventService::store_all_raw(game_uuid, (1..=5).rev().map(|id| get_general_json(id, 1)).collect());
+
+        let ids = |events: Vec<ApiGameEvent>| events.into_iter().map(|e| e.event_id).collect::<Vec<String>>();
+        assert_eq!(ids(EventService::unacked(game_uuid, 3)), vec!["4", "5"]);
+        assert_eq!(ids(EventService::unacked(game_uuid, 0)).len(), 5);
+        assert!(EventService::unacked(game_uuid, 5).is_empty());
+    }
+
+    #[test]
+    fn reconcile_restores_mapped_events() {
+        std::env::set_var("DB_PATH", TempDir::new("test").expect("dir to be created").path().to_str().unwrap());
+        let game_uuid = "reconcile_restores_mapped_events";
+        EventService::store_all_raw(game_uuid, vec![
+            get_general_json(1, 1),
+            get_general_json(2, 2),
+            get_general_json(3, 1),
+        ]);
+        // Event 2 was only stored at its first revision, and event 3 never made it
+        let mut first = get_general_json(1, 1).into_mapped_event(game_uuid);
+        first.tags.push("kept".to_string());
+        EventService::store_with(game_uuid, &mut first, &[]);
+        EventService::store_with(game_uuid, &mut get_general_json(2, 1).into_mapped_event(game_uuid), &[]);
+        let mut report = get_event(ApiEventType::GameStart);
+        report.event_id = "GameStart".to_string();
+        EventService::store_with(game_uuid, &mut report, &[]);
+
+        assert_eq!(EventService::divergence(game_uuid), vec!["2", "3"]);
+        assert!(EventService::reconcile(game_uuid));
+        assert!(EventService::divergence(game_uuid).is_empty());
+        assert!(!EventService::reconcile(game_uuid));
+
+        let stored = EventService::get_mapped_db().read(&game_uuid.to_string()).unwrap();
+        assert_eq!(stored.len(), 4);
+        assert!(stored.iter().any(|e| e.event_id == "GameStart"));
+        assert_eq!(stored.iter().find(|e| e.event_id == "1").unwrap().tags, vec!["kept"]);
+        assert_eq!(stored.iter().find(|e| e.event_id == "2").unwrap().revision, 2);
+    }
+
+    #[test]
+    fn stream_large_game() {
+        std::env::set_var("DB_PATH", TempDir::new("test").expect("dir to be created").path().to_str().unwrap());
+        let game_uuid = "stream_large_game";
+        // over the event cap, so written directly
+        let raw: Vec<PlayByPlay> = (1..=5000).map(|i| get_general_json(i, 1)).collect();
+        _ = EventService::get_raw_db().write(&game_uuid.to_string(), &raw);
+
+        // lazy, taking the first doesn't parse the rest
+        let first = EventService::stream(game_uuid).next().unwrap();
+        assert_eq!(first.event_id, "1");
+        let (count, last) = EventService::stream(game_uuid).fold((0, None), |(count, _), e| (count + 1, Some(e.key())));
+        assert_eq!((count, last), (5000, Some(EventKey::Feed(5000))));
+        assert_eq!(EventService::stream("never_stored").count(), 0);
+    }
+
+    #[test]
+    fn both_stores_share_event_key() {
+        std::env::set_var("DB_PATH", TempDir::new("test").expect("dir to be created").path().to_str().unwrap());
+        let game_uuid = "both_stores_share_event_key";
+        let raw = get_general_json(12, 1);
+        assert!(EventService::store_raw(game_uuid, &raw));
+        let mut mapped = raw.clone().into_mapped_event(game_uuid);
+        assert!(EventService::store_with(game_uuid, &mut mapped, &[]));
+        assert_eq!(mapped.key(), raw.key());
+        assert_eq!(mapped.key(), EventKey::Feed(12));
+
+        // the same event, however the id was formatted
+        let mut padded = ApiGameEvent { event_id: " 012".to_string(), revision: 2, ..mapped.clone() };
+        assert!(!EventService::store_with(game_uuid, &mut padded, &[]));
+        assert!(!EventService::store_raw(game_uuid, &get_general_json(12, 2)));
+        assert!(EventService::divergence(game_uuid).is_empty());
+
+        let report = ApiGameEvent { event_id: "GameStarted".to_string(), ..mapped };
+        assert_eq!(report.key(), EventKey::Local("GameStarted".to_string()));
+    }
+
+    #[test]
+    fn player_goal_and_penalty() {
+        std::env::set_var("DB_PATH", TempDir::new("test").expect("dir to be created").path().to_str().unwrap());
+        let game_uuid = "player_goal_and_penalty";
+        let penalty = |event_id: i32, player_id: i32| -> PlayByPlay {
+            serde_json::from_str(&format!(r#"{{
+                "eventId": {event_id}, "revision": 1, "hash": "abc", "period": 2, "gametime": "01:{event_id:02}", "description": "1 Olle Olsson utvisas 2 min, Hooking",
+                "class": "Penalty", "team": "LHF", "playerId": {player_id}
+            }}"#)).unwrap()
+        };
+        let goal = PlayByPlay { eventId: 13, ..get_goal_json(r#""scorerId": 55, "assistIds": ["77"],"#) };
+        EventService::store_all_raw(game_uuid, vec![
+            get_goal_json(r#""scorerId": 42,"#),
+            goal,
+            penalty(20, 42),
+            penalty(21, 55),
+            get_general_json(1, 1),
+        ]);
+
+        let ids = |events: Vec<ApiGameEvent>| events.into_iter().map(|e| e.event_id).collect::<Vec<String>>();
+        assert_eq!(ids(EventService::player_events(game_uuid, 42)), vec!["12", "20"]);
+        assert_eq!(ids(EventService::player_events(game_uuid, 77)), vec!["13"]);
+        assert!(EventService::player_events(game_uuid, 1).is_empty());
+    }
+
+    #[test]
+    fn events_in_period_two() {
+        std::env::set_var("DB_PATH", TempDir::new("test").expect("dir to be created").path().to_str().unwrap());
+        let game_uuid = "events_in_period_two";
+        let event = |event_id: i32, period: i16| PlayByPlay { period: StringOrNum::Number(period), ..get_general_json(event_id, 1) };
+        EventService::store_all_raw(game_uuid, vec![event(1, 1), event(5, 2), event(2, 2), event(3, 3), event(4, 4), event(6, 99)]);
+
+        let ids = |period: GamePeriod| EventService::period_events(game_uuid, period).into_iter().map(|e| e.event_id).collect::<Vec<String>>();
+        assert_eq!(ids(GamePeriod::Regulation(2)), vec!["2", "5"]);
+        assert_eq!(ids(GamePeriod::Overtime(1)), vec!["4"]);
+        assert_eq!(ids(GamePeriod::Shootout), vec!["6"]);
+        assert!(ids(GamePeriod::Overtime(2)).is_empty());
+    }
+
+    #[tokio::test]
+    async fn postponed_game_is_not_polled() {
+        std::env::set_var("DB_PATH", TempDir::new("test").expect("dir to be created").path().to_str().unwrap());
+        let game_uuid = "postponed_game_is_not_polled";
+        let report: external::event::GameReport = serde_json::from_str(&format!(r#"{{
+            "gameUuid": "{game_uuid}", "gameTime": "00:00", "statusString": "", "gameState": "Postponed", "period": 0,
+            "homeTeamId": "LHF", "awayTeamId": "FHC", "homeTeamScore": 0, "awayTeamScore": 0, "revision": 1
+        }}"#)).unwrap();
+        let report: ApiGameReport = report.into();
+        assert_eq!(report.status, GameStatus::Postponed);
+        assert_eq!(GameStatus::from_season_state("cancelled"), GameStatus::Cancelled);
+
+        // no upstream in tests, so a fetch would fail
+        assert!(EventService::update(game_uuid, ThrottlePolicy::Force).await.is_err());
+        GameReportService::store(game_uuid, &report);
+        assert!(EventService::update(game_uuid, ThrottlePolicy::Force).await.unwrap().is_empty());
+    }
+
+    #[tokio::test]
+    async fn concurrent_updates_share_one_fetch() {
+        std::env::set_var("DB_PATH", TempDir::new("test").expect("dir to be created").path().to_str().unwrap());
+        let game_uuid = "concurrent_updates_share_one_fetch";
+        let (base, paths) = crate::mock_server::recording_mock_server(vec![("200 OK", "", "[]", Duration::from_millis(100))]).await;
+
+        let (a, b, c) = rest_client::with_base_url(&base, async {
+            futures::join!(
+                EventService::update(game_uuid, ThrottlePolicy::Force),
+                EventService::update(game_uuid, ThrottlePolicy::Force),
+                EventService::update(game_uuid, ThrottlePolicy::Force),
+            )
+        }).await;
+        assert!(a.unwrap().is_empty() && b.unwrap().is_empty() && c.unwrap().is_empty());
+        assert_eq!(*paths.lock().unwrap(), vec![format!("/gameday/play-by-play/initial-events/{game_uuid}")]);
+    }
+
+    #[tokio::test]
+    async fn update_caps_events() {
+        std::env::set_var("DB_PATH", TempDir::new("test").expect("dir to be created").path().to_str().unwrap());
+        let game_uuid = "update_caps_events";
+        let report: external::event::GameReport = serde_json::from_str(&format!(r#"{{
+            "gameUuid": "{game_uuid}", "gameTime": "00:00", "statusString": "", "gameState": "Postponed", "period": 0,
+            "homeTeamId": "LHF", "awayTeamId": "FHC", "homeTeamScore": 0, "awayTeamScore": 0, "revision": 1
+        }}"#)).unwrap();
+        GameReportService::store(game_uuid, &report.into());
+        let max = crate::CONFIG.max_events_per_game as i32;
+        let raw: Vec<external::event::PlayByPlay> = (1..=max + 10).map(|id| get_general_json(id, 1)).collect();
+        _ = EventService::get_raw_db().write(&game_uuid.to_string(), &raw);
+
+        let events = EventService::update(game_uuid, ThrottlePolicy::Force).await.unwrap();
+        assert_eq!(events.len(), max as usize);
+        assert_eq!(EventService::get_raw_db().read(&game_uuid.to_string()).unwrap().len(), max as usize);
+        assert_eq!(events[0].event_id, "11");
+    }
+
+    #[test]
+    fn assists_resolved_from_roster() {
+        let athlete = |id: i32, jersey: i32, first_name: &str, family_name: &str, team_code: &str| ApiAthlete {
+            id,
+            first_name: first_name.to_string(),
+            family_name: family_name.to_string(),
+            jersey,
+            team_code: team_code.to_string(),
+            position: "FW".to_string(),
+            season: Season::Season2022,
+            image_url: None,
+            stats: ApiAthleteStats::Player(ApiPlayerStats::default()),
+        };
+        let roster = vec![
+            athlete(99, 9, "Erik", "Eriksson", "LHF"),
+            athlete(21, 21, "Linus", "Omark", "LHF"),
+            athlete(5, 5, "Karl", "Karlsson", "FHC"),
+        ];
+        let goal = |assist: &str| {
+            let mut event = get_goal_json("").into_mapped_event("game_uuid");
+            if let ApiEventType::Goal(g) = &mut event.info {
+                g.assist = Some(assist.to_string()).filter(|e| !e.is_empty());
+            }
+            event
+        };
+        let mut events = vec![goal("9 Erik Erikson, 21 L. Omark"), goal("5 Karl Karlsson"), goal("")];
+        EventService::resolve_assists("game_uuid", &mut events, &roster);
+        let assists: Vec<(Option<String>, Vec<i32>)> = events.into_iter().map(|e| match e.info {
+            ApiEventType::Goal(g) => (g.assist, g.assist_ids),
+            _ => panic!("not a goal"),
+        }).collect();
+        assert_eq!(assists[0], (Some("9 Erik Eriksson, 21 Linus Omark".to_string()), vec![99, 21]));
+        // scored by LHF, the FHC player can't have assisted
+        assert_eq!(assists[1], (Some("5 Karl Karlsson".to_string()), vec![]));
+        assert_eq!(assists[2], (None, vec![]));
+    }
+
+    #[test]
+    fn read_resolves_assists_from_cached_roster() {
+        std::env::set_var("DB_PATH", TempDir::new("test").expect("dir to be created").path().to_str().unwrap());
+        let game_uuid = "read_resolves_assists_from_cached_roster";
+        let game = ApiGame {
+            game_uuid: game_uuid.to_string(),
+            home_team_code: "LHF".to_string(),
+            away_team_code: "FHC".to_string(),
+            home_team_result: 2,
+            away_team_result: 1,
+            start_date_time: chrono::Utc::now(),
+            start_date_time_local: None,
+            status: GameStatus::Finished,
+            shootout: false,
+            overtime: false,
+            played: true,
+            game_type: GameType::Season,
+            league: League::SHL,
+            season: Season::Season2019,
+            gametime: None,
+        };
+        _ = Db::<Season, Vec<ApiGame>>::new("v2_season_decorated").write(&Season::Season2019, &vec![game.clone()]);
+        let fixture = |file: &str| std::fs::read_to_string(std::path::PathBuf::from(env!("CARGO_MANIFEST_DIR")).join("fixtures/game1").join(file)).unwrap();
+        let players: PlayerStatsRsp = serde_json::from_str(&fixture("players.json")).unwrap();
+        _ = Db::<String, PlayerStatsRsp>::new("rest").write(&rest_client::get_player_stats_key(&game.league, &game.season, game_uuid), &players);
+        EventService::store_all_raw(game_uuid, serde_json::from_str(&fixture("events.json")).unwrap());
+
+        let assist_ids: Vec<Vec<i32>> = EventService::read(game_uuid).into_iter()
+            .filter_map(|e| match e.info { ApiEventType::Goal(g) => Some(g.assist_ids), _ => None })
+            .collect();
+        assert_eq!(assist_ids, vec![vec![102], vec![], vec![101]]);
+    }
+
+    #[test]
+    fn gaps_between_goals() {
+        let goal = |event_id: i32, period: i16, gametime: &str, team: &str| {
+            let mut e = get_score_json(event_id, period, gametime, 0, 0).into_mapped_event("game_uuid");
+            if let ApiEventType::Goal(g) = &mut e.info {
+                g.team = team.to_string();
+            }
+            e
+        };
+        let goals = vec![
+            goal(3, 2, "05:00", "LHF"),
+            goal(1, 1, "12:34", "LHF"),
+            goal(2, 1, "15:00", "FHC"),
+        ];
+        let seconds = |gaps: Vec<ApiGoalGap>| gaps.into_iter().map(|e| (e.event_id, e.seconds)).collect::<Vec<(String, i32)>>();
+        assert_eq!(seconds(get_goal_gaps(goals.clone(), false)), vec![
+            ("1".to_string(), 12 * 60 + 34),
+            ("2".to_string(), 2 * 60 + 26),
+            ("3".to_string(), 10 * 60),
+        ]);
+        assert_eq!(seconds(get_goal_gaps(goals, true)), vec![
+            ("1".to_string(), 12 * 60 + 34),
+            ("2".to_string(), 15 * 60),
+            ("3".to_string(), 12 * 60 + 26),
+        ]);
+    }
+
+    #[test]
+    fn quick_strike_within_window() {
+        let goal = |event_id: i32, gametime: &str| get_score_json(event_id, 1, gametime, 0, 0);
+        let quick_strikes = |window_s: u64, raw: Vec<PlayByPlay>| {
+            let processors: Vec<Box<dyn EventProcessor>> = vec![Box::new(move |event: &mut ApiGameEvent, stored: &[ApiGameEvent]| super::tag_quick_strike(event, stored, window_s))];
+            EventService::map_with("game_uuid", raw, &processors).into_iter().map(|e| match e.info {
+                ApiEventType::Goal(g) => (e.event_id, g.quick_strike, g.quick_strike_s),
+                _ => panic!("not a goal"),
+            }).collect::<Vec<(String, bool, Option<i32>)>>()
+        };
+        // stored out of gametime order
+        assert_eq!(quick_strikes(30, vec![goal(3, "12:00"), goal(2, "05:15"), goal(1, "05:00")]), vec![
+            ("1".to_string(), false, None),
+            ("2".to_string(), true, Some(15)),
+            ("3".to_string(), false, None),
+        ]);
+        assert_eq!(quick_strikes(10, vec![goal(1, "05:00"), goal(2, "05:15")])[1], ("2".to_string(), false, None));
+        assert_eq!(quick_strikes(0, vec![goal(1, "05:00"), goal(2, "05:15")])[1], ("2".to_string(), false, None));
+
+        // a live revision moving the goal clears its flag
+        let stored = goal(1, "05:00").into_mapped_event("game_uuid");
+        let mut revised = goal(2, "05:15").into_mapped_event("game_uuid");
+        super::tag_quick_strike(&mut revised, std::slice::from_ref(&stored), 30);
+        assert!(matches!(&revised.info, ApiEventType::Goal(g) if g.quick_strike));
+        revised.gametime = "09:00".to_string();
+        super::tag_quick_strike(&mut revised, std::slice::from_ref(&stored), 30);
+        assert!(matches!(&revised.info, ApiEventType::Goal(g) if !g.quick_strike && g.quick_strike_s.is_none()));
+    }
+
+    fn get_general_json(event_id: i32, revision: u16) -> PlayByPlay {
+        let json = format!(r#"{{
+            "eventId": {event_id}, "revision": {revision}, "hash": "abc", "period": 1, "gametime": "00:{event_id:02}", "description": "",
+            "class": "General"
+        }}"#);
+        serde_json::from_str(&json).unwrap()
+    }
+
+    fn get_goal_json(extra: &str) -> PlayByPlay {
+        let json = format!(r#"{{
+            "eventId": 12, "revision": 1, "hash": "abc", "period": 1, "gametime": "12:34", "description": "Mål",
+            "class": "Goal", "team": "LHF", "location": {{ "x": 1.0, "y": 2.0 }},
+            "extra": {{ {extra} "scorerLong": "1 Olle Olsson", "teamAdvantage": "EQ", "homeAgainst": 0, "homeForward": 1, "assist": "" }}
+        }}"#);
+        serde_json::from_str(&json).unwrap()
+    }
+
+    fn get_event(info: ApiEventType) -> ApiGameEvent {
+        ApiGameEvent {
+            game_uuid: "game_uuid".to_string(),
+            event_id: "1".to_string(),
+            revision: 1,
+            status: GameStatus::Period1,
+            period: GamePeriod::Regulation(1),
+            gametime: "01:00".to_string(),
+            description: "".to_string(),
+            info,
+            tags: vec![],
+        }
+    }
+}
@@ -1,21 +1,27 @@
 
-use std::time::Duration;
+use std::{time::Duration, future::Future};
 
+use futures::StreamExt;
 use tracing::log;
 
-use crate::{stats_service::StatsService, api_season_service::{ApiGame, ApiSeasonService}, player_service::PlayerService, event_service::EventService, db::Db};
+use crate::{stats_service::StatsService, api_season_service::{ApiGame, ApiSeasonService}, player_service::PlayerService, event_service::EventService, db::Db, rest_client::{self, ThrottlePolicy}, service_config::ServiceConfig, CONFIG};
 
 pub struct FetchDetailsService;
 impl FetchDetailsService {
-    pub async fn update() {
+    pub async fn update(service_config: &ServiceConfig) {
         let db: Db<String, String> = Db::new("v2_fetch_details");
-        if !db.is_stale(&"key".to_string(), Some(Duration::from_secs(60 * 60))) {
+        if !db.is_stale(&"key".to_string(), Some(service_config.fetch_details_window())) {
             return;
         }
         let all_games = ApiSeasonService::read_all();
         let mut applicable_games: Vec<&ApiGame> = all_games.iter()
             .filter(|e| e.played)
-            .filter(|e| StatsService::is_stale(&e.league, &e.game_uuid) || PlayerService::is_stale(&e.league, &e.game_uuid))
+            // games whose fetches keep failing wait out the backoff, so they don't take every turn
+            .filter(|e| {
+                let stats = StatsService::is_stale(&e.league, &e.game_uuid) && !rest_client::is_backing_off(&rest_client::get_stats_url(&e.league, &e.game_uuid));
+                let players = PlayerService::is_stale(&e.league, &e.season, &e.game_uuid) && !rest_client::is_backing_off(&rest_client::get_player_stats_key(&e.league, &e.season, &e.game_uuid));
+                stats || players
+            })
             .collect();
 
         let nr_games_left = applicable_games.len();
@@ -23,18 +29,53 @@ impl FetchDetailsService {
             log::info!("[FETCHDETAILS] Done");
         }
         applicable_games.truncate(10);
-        for e in applicable_games {
+        FetchDetailsService::update_many(applicable_games.into_iter().cloned(), CONFIG.max_concurrency, |e| async move {
             log::info!("[FETCHDETAILS] {}", e.game_uuid);
-            futures::join!(
-                StatsService::update(&e.league, &e.game_uuid, Some(Duration::from_secs(0))),
-                PlayerService::update(&e.league, &e.game_uuid, Some(Duration::from_secs(0))),
-                EventService::update(&e.game_uuid, Some(Duration::from_secs(0)))
+            _ = futures::join!(
+                StatsService::update(&e.league, &e.game_uuid, ThrottlePolicy::Force),
+                PlayerService::update(&e.league, &e.season, &e.game_uuid, ThrottlePolicy::Force),
+                EventService::update(&e.game_uuid, ThrottlePolicy::Force)
             );
             
             tokio::time::sleep(Duration::from_secs(1)).await;
-        }
+        }).await;
         let info = format!("{} out of {} left", nr_games_left, all_games.len());
         log::info!("[FETCHDETAILS] {info}");
         _ = db.write(&"key".to_string(), &info);
     }
-}
\ No newline at end of file
+
+    // At most max_concurrency in flight, started in order so no item starves
+    pub async fn update_many<T, R, F: Future<Output = R>>(items: impl IntoIterator<Item = T>, max_concurrency: usize, f: impl Fn(T) -> F) -> Vec<R> {
+        futures::stream::iter(items)
+            .map(f)
+            .buffered(max_concurrency.max(1))
+            .collect()
+            .await
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{sync::{Arc, atomic::{AtomicUsize, Ordering}}, time::Duration};
+
+    use super::FetchDetailsService;
+
+    #[tokio::test]
+    async fn update_many_max_concurrency() {
+        let in_flight = Arc::new(AtomicUsize::new(0));
+        let max_in_flight = Arc::new(AtomicUsize::new(0));
+        let result = FetchDetailsService::update_many(0..10, 2, |i| {
+            let (in_flight, max_in_flight) = (in_flight.clone(), max_in_flight.clone());
+            async move {
+                let current = in_flight.fetch_add(1, Ordering::SeqCst) + 1;
+                max_in_flight.fetch_max(current, Ordering::SeqCst);
+                tokio::time::sleep(Duration::from_millis(10)).await;
+                in_flight.fetch_sub(1, Ordering::SeqCst);
+                i
+            }
+        }).await;
+
+        assert_eq!(result, (0..10).collect::<Vec<i32>>());
+        assert_eq!(max_in_flight.load(Ordering::SeqCst), 2);
+    }
+}
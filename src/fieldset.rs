@@ -0,0 +1,89 @@
+use serde::Serialize;
+use serde_json::{Map, Value};
+
+// Requested fields address the serialized json keys. Flattened fields, like the stats of an ApiAthlete,
+// are top level ("g", "a"), nested fields are separated by dots ("info.team"), and a field on a
+// list applies to each of its items. Unknown fields are left out
+#[derive(Debug, Clone, PartialEq)]
+pub struct Fieldset(Vec<Vec<String>>);
+
+impl Fieldset {
+    // "g,a,info.team" => Fieldset. None for an empty list, meaning all fields
+    pub fn parse(fields: &str) -> Option<Fieldset> {
+        let paths: Vec<Vec<String>> = fields.split(',')
+            .map(|e| e.trim())
+            .filter(|e| !e.is_empty())
+            .map(|e| e.split('.').map(|e| e.to_string()).collect())
+            .collect();
+        (!paths.is_empty()).then_some(Fieldset(paths))
+    }
+
+    pub fn select<T: Serialize>(&self, value: &T) -> Value {
+        let value = serde_json::to_value(value).unwrap_or_default();
+        let paths: Vec<&[String]> = self.0.iter().map(|e| e.as_slice()).collect();
+        Fieldset::select_paths(value, &paths)
+    }
+
+    fn select_paths(value: Value, paths: &[&[String]]) -> Value {
+        match value {
+            Value::Array(items) => Value::Array(items.into_iter().map(|e| Fieldset::select_paths(e, paths)).collect()),
+            Value::Object(mut object) => {
+                let mut selected = Map::new();
+                for (key, rest) in paths.iter().filter_map(|e| e.split_first()) {
+                    if selected.contains_key(key) {
+                        continue;
+                    }
+                    let Some(field) = object.remove(key) else { continue };
+                    let nested: Vec<&[String]> = paths.iter()
+                        .filter_map(|e| e.split_first())
+                        .filter(|(k, _)| *k == key)
+                        .map(|(_, rest)| rest)
+                        .collect();
+                    // A bare "info" next to "info.team" selects all of info
+                    let field = match rest.is_empty() || nested.iter().any(|e| e.is_empty()) {
+                        true => field,
+                        false => Fieldset::select_paths(field, &nested),
+                    };
+                    selected.insert(key.clone(), field);
+                }
+                Value::Object(selected)
+            },
+            value => value,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use serde_json::json;
+
+    use crate::{player_service::{ApiAthlete, ApiAthleteStats, ApiPlayerStats}, models::Season};
+
+    use super::Fieldset;
+
+    #[test]
+    fn athlete_goals_and_assists() {
+        let athletes: Vec<ApiAthlete> = [(1, 2, 1), (2, 0, 3)].into_iter().map(|(id, g, a)| ApiAthlete {
+            id,
+            first_name: "olle".to_string(),
+            family_name: "karlsson".to_string(),
+            jersey: 1,
+            team_code: "LHF".to_string(),
+            position: "FW".to_string(),
+            season: Season::Season2022,
+            image_url: None,
+            stats: ApiAthleteStats::Player(ApiPlayerStats { g, a, pim: 4, ..Default::default() }),
+        }).collect();
+        let fields = Fieldset::parse("g, a").unwrap();
+        assert_eq!(fields.select(&athletes), json!([{ "g": 2, "a": 1 }, { "g": 0, "a": 3 }]));
+        assert_eq!(serde_json::to_value(&athletes[0]).unwrap()["pim"], 4);
+    }
+
+    #[test]
+    fn nested_fields() {
+        let event = json!({ "event_id": "1", "info": { "type": "Goal", "team": "LHF", "player": { "jersey": 1 } } });
+        assert_eq!(Fieldset::parse("event_id,info.team,missing").unwrap().select(&event), json!({ "event_id": "1", "info": { "team": "LHF" } }));
+        assert_eq!(Fieldset::parse("info.team,info").unwrap().select(&event), json!({ "info": event["info"] }));
+        assert_eq!(Fieldset::parse(" , "), None);
+    }
+}
@@ -0,0 +1,134 @@
+use std::{sync::{Arc, Mutex}, collections::HashMap, time::{Duration, Instant}};
+
+use serde::Deserialize;
+use tracing::log;
+
+use crate::{api_season_service::ApiGame, game_report_service::GameStatus, event_service::EventService, stats_service::StatsService, player_service::PlayerService, rest_client::ThrottlePolicy, CONFIG};
+
+// Seconds between refreshes of a game in each state. Games not started, or called off, are never refreshed
+#[derive(Deserialize, Debug, Clone)]
+#[serde(default)]
+pub struct RefreshIntervals {
+    pub live_s: u64,
+    pub intermission_s: u64,
+    pub finished_s: u64,
+}
+impl Default for RefreshIntervals {
+    fn default() -> Self {
+        RefreshIntervals {
+            live_s: 30,
+            intermission_s: 60,
+            finished_s: 300,
+        }
+    }
+}
+
+impl RefreshIntervals {
+    pub fn get(&self, status: &GameStatus) -> Option<Duration> {
+        let seconds = match status {
+            GameStatus::Period1 | GameStatus::Period2 | GameStatus::Period3 | GameStatus::Overtime | GameStatus::Shootout => self.live_s,
+            GameStatus::Intermission => self.intermission_s,
+            GameStatus::Finished => self.finished_s,
+            GameStatus::Coming | GameStatus::Postponed | GameStatus::Cancelled => return None,
+        };
+        Some(Duration::from_secs(seconds))
+    }
+}
+
+// Which of the resources were refreshed by a tick
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct RefreshResult {
+    pub events: bool,
+    pub stats: bool,
+    pub players: bool,
+}
+
+// Refreshes events, stats and players of a game together, so they're fetched at the same time
+// and are equally fresh, instead of each on its own throttle
+#[derive(Clone)]
+pub struct GameRefresher {
+    intervals: RefreshIntervals,
+    last_tick: Arc<Mutex<HashMap<String, Instant>>>,
+}
+
+impl GameRefresher {
+    pub fn new(intervals: RefreshIntervals) -> GameRefresher {
+        GameRefresher { intervals, last_tick: Arc::new(Mutex::new(HashMap::new())) }
+    }
+
+    pub fn from_config() -> GameRefresher {
+        GameRefresher::new(CONFIG.refresh_intervals.clone())
+    }
+
+    // None if the game isn't due for a refresh yet
+    pub async fn tick(&self, game: &ApiGame) -> Option<RefreshResult> {
+        let interval = self.intervals.get(&game.status)?;
+        {
+            let mut last_tick = self.last_tick.lock().unwrap_or_else(|e| e.into_inner());
+            if last_tick.get(&game.game_uuid).is_some_and(|e| e.elapsed() < interval) {
+                return None;
+            }
+            last_tick.insert(game.game_uuid.clone(), Instant::now());
+        }
+        let game_uuid = &game.game_uuid;
+        let (events, stats, players) = futures::join!(
+            EventService::update(game_uuid, ThrottlePolicy::Window(interval)),
+            StatsService::update(&game.league, game_uuid, ThrottlePolicy::Window(interval)),
+            PlayerService::update(&game.league, &game.season, game_uuid, ThrottlePolicy::Window(interval)),
+        );
+        let result = RefreshResult { events: events.is_ok(), stats: stats.is_ok(), players: players.is_ok() };
+        log::info!("[REFRESH] {game_uuid} {:?} {:?}", game.status, result);
+        Some(result)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use chrono::Utc;
+    use tempdir::TempDir;
+    use crate::{api_season_service::ApiGame, game_report_service::GameStatus, models::{GameType, League, Season}, rest_client, mock_server::recording_mock_server};
+
+    use super::{GameRefresher, RefreshIntervals};
+
+    #[tokio::test]
+    async fn tick_fetches_events_stats_and_players() {
+        std::env::set_var("DB_PATH", TempDir::new("test").expect("dir to be created").path().to_str().unwrap());
+        // events, stats and players, each an empty list
+        let (base, paths) = recording_mock_server(vec![("200 OK", "", "[]", Duration::ZERO); 3]).await;
+        let game = ApiGame {
+            game_uuid: "tick_fetches_events_stats_and_players".to_string(),
+            home_team_code: "LHF".to_string(),
+            away_team_code: "FHC".to_string(),
+            home_team_result: 0,
+            away_team_result: 0,
+            start_date_time: Utc::now(),
+            start_date_time_local: None,
+            status: GameStatus::Period1,
+            shootout: false,
+            overtime: false,
+            played: false,
+            game_type: GameType::Season,
+            league: League::SHL,
+            season: Season::Season2022,
+            gametime: None,
+        };
+        let refresher = GameRefresher::new(RefreshIntervals { live_s: 60, ..Default::default() });
+
+        let result = rest_client::with_base_url(&base, refresher.tick(&game)).await;
+        assert!(result.is_some_and(|e| e.events));
+        let mut paths = paths.lock().unwrap().clone();
+        paths.sort();
+        assert_eq!(paths, vec![
+            format!("/gameday/boxscore/{}", game.game_uuid),
+            format!("/gameday/periodstats/{}", game.game_uuid),
+            format!("/gameday/play-by-play/initial-events/{}", game.game_uuid),
+        ]);
+
+        // within the interval, and never for games not started
+        assert!(rest_client::with_base_url(&base, refresher.tick(&game)).await.is_none());
+        assert!(refresher.tick(&ApiGame { status: GameStatus::Coming, ..game.clone() }).await.is_none());
+        assert_eq!(RefreshIntervals::default().get(&GameStatus::Intermission), Some(Duration::from_secs(60)));
+    }
+}
@@ -2,7 +2,7 @@ use std::fmt::Display;
 
 use serde::{Serialize, Deserialize};
 
-use crate::{db::Db, models2::external};
+use crate::{db::Db, models2::external, models::{normalize_team_code, GamePeriod}};
 
 #[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
 pub enum GameStatus {
@@ -14,6 +14,25 @@ pub enum GameStatus {
     Overtime,
     Shootout,
     Intermission,
+    Postponed,
+    Cancelled,
+}
+
+impl GameStatus {
+    // Nothing will happen in these games, at least not at the scheduled time
+    pub fn is_called_off(&self) -> bool {
+        matches!(self, GameStatus::Postponed | GameStatus::Cancelled)
+    }
+
+    // The season feed only has a coarse state, details come from the game reports
+    pub fn from_season_state(state: &str) -> GameStatus {
+        match state {
+            "post-game" => GameStatus::Finished,
+            "postponed" => GameStatus::Postponed,
+            "cancelled" | "canceled" => GameStatus::Cancelled,
+            _ => GameStatus::Coming,
+        }
+    }
 }
 
 #[derive(Serialize, Deserialize, Debug, Clone)]
@@ -22,6 +41,9 @@ pub struct ApiGameReport {
 
     pub gametime: String,
     pub status: GameStatus,
+    // the feed's period, telling overtimes apart. Regulation(0) in reports stored before it was kept
+    #[serde(default)]
+    pub period: GamePeriod,
 
     pub home_team_code: String,
     pub away_team_code: String,
@@ -48,8 +70,9 @@ impl From<external::event::GameReport> for ApiGameReport {
             game_uuid: value.gameUuid.clone(),
             gametime: value.gameTime.clone(),
             status: value.get_status(),
-            home_team_code: value.homeTeamId.unwrap_or("TBD".to_string()),
-            away_team_code: value.awayTeamId.unwrap_or("TBD".to_string()),
+            period: value.period.to_num().into(),
+            home_team_code: value.homeTeamId.map(|e| normalize_team_code(&e)).unwrap_or("TBD".to_string()),
+            away_team_code: value.awayTeamId.map(|e| normalize_team_code(&e)).unwrap_or("TBD".to_string()),
             home_team_result: value.homeTeamScore.to_num(),
             away_team_result: value.awayTeamScore.to_num()
         }
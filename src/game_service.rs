@@ -0,0 +1,750 @@
+use std::{time::Instant, collections::HashMap, sync::RwLock, cmp::Ordering};
+
+use lazy_static::lazy_static;
+use serde::{Serialize, Deserialize};
+use tracing::log;
+
+use crate::{event_service::{EventService, ApiGameEvent, ApiEventType, ShotKind, GoalInfo, get_elapsed_seconds}, models2::external::event::PlayByPlayType, game_report_service::{GameReportService, ApiGameReport, GameStatus}, api_season_service::ApiGame, stats_service::{StatsService, ApiGameStats}, player_service::{PlayerService, ApiAthlete}, models::{GamePeriod, normalize_team_code}, rest_client::ThrottlePolicy, service_config::ServiceConfig};
+
+const REGULATION_SECONDS: f32 = 60.0 * 60.0;
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct ApiProjection {
+    pub home: f32,
+    pub away: f32,
+}
+
+// Shots on goal and all attempts (Corsi), goals included in both. Shootouts aren't counted
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Default)]
+pub struct ApiShotCount {
+    pub sog: u16,
+    pub corsi: u16,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct ApiShotCounts {
+    pub home: ApiShotCount,
+    pub away: ApiShotCount,
+}
+
+// Final totals at the current pace. Shots are all attempts, goals included
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct ApiProjections {
+    pub shots: ApiProjection,
+    pub goals: ApiProjection,
+}
+// Skaters on ice, goalkeepers not included
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct ApiStrength {
+    pub home: u8,
+    pub away: u8,
+}
+const SKATERS: u8 = 5;
+const MIN_SKATERS: u8 = 3;
+
+// "5v4 PP, 1:20 remaining", with remaining_s until the next penalty runs out. None at full strength
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct StrengthState {
+    #[serde(flatten)]
+    pub strength: ApiStrength,
+    pub powerplay_team: Option<String>,
+    pub remaining_s: Option<i32>,
+}
+
+// A penalty with when it's served until, goals on the powerplay end minors early
+#[derive(Debug, Clone)]
+struct ServedPenalty {
+    home: bool,
+    start: i32,
+    minutes: i32,
+    end: i32,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct Scoreboard {
+    pub game_uuid: String,
+    pub home_team_code: String,
+    pub away_team_code: String,
+    pub home_team_result: i16,
+    pub away_team_result: i16,
+    // None outside of play, in intermissions and before and after the game
+    pub period: Option<GamePeriod>,
+    pub gametime: String,
+    pub status: GameStatus,
+}
+
+pub const GAME_DOCUMENT_VERSION: u32 = 1;
+
+// Everything known about a game in one document, for archiving and sharing
+#[derive(Serialize, Deserialize, Clone)]
+pub struct GameDocument {
+    pub version: u32,
+    pub game: ApiGame,
+    pub report: Option<ApiGameReport>,
+    pub stats: Option<ApiGameStats>,
+    pub events: Vec<ApiGameEvent>,
+    pub players: Vec<ApiAthlete>,
+}
+
+lazy_static! {
+    static ref SCOREBOARDS: RwLock<HashMap<String, (Instant, Scoreboard)>> = RwLock::new(HashMap::new());
+}
+
+// more than a full slate of games, finished games would otherwise be kept for good
+const MAX_SCOREBOARDS: usize = 500;
+
+// a powerplay is worth about a fifth of a goal
+const POWERPLAY_GOALS: f32 = 0.2;
+
+pub struct GameService;
+impl GameService {
+    // (home, away) chance of winning from score, time left and powerplay state
+    pub fn win_probability(game_uuid: &str) -> (f32, f32) {
+        match GameReportService::read(game_uuid) {
+            Some(report) => {
+                let powerplay = GameService::get_powerplay(&report, &EventService::read(game_uuid));
+                GameService::get_win_probability(&report, powerplay)
+            },
+            None => (0.5, 0.5),
+        }
+    }
+
+    // Running score (home, away) from the latest goal, only goals are mapped
+    pub fn score(game_uuid: &str) -> Option<(i16, i16)> {
+        let db = EventService::get_raw_db();
+        let events = db.read(&game_uuid.to_string())?;
+        let goals = events.into_iter()
+            .filter(|e| matches!(e.class, PlayByPlayType::Goal(_)))
+            .map(|e| e.into_mapped_event(game_uuid))
+            .collect();
+        let home_team_code = GameReportService::read(game_uuid).map(|e| e.home_team_code);
+        Some(get_score(goals, home_team_code.as_deref()))
+    }
+
+    // None without a report
+    pub fn scoreboard(game_uuid: &str, service_config: &ServiceConfig) -> Option<Scoreboard> {
+        if let Some((at, scoreboard)) = SCOREBOARDS.read().unwrap_or_else(|e| e.into_inner()).get(game_uuid) {
+            // Finished games never expire
+            if scoreboard.status == GameStatus::Finished || at.elapsed() < service_config.scoreboard_ttl() {
+                return Some(scoreboard.clone());
+            }
+        }
+        let scoreboard = get_scoreboard(GameReportService::read(game_uuid)?, GameService::score(game_uuid));
+        cache_scoreboard(&mut SCOREBOARDS.write().unwrap_or_else(|e| e.into_inner()), scoreboard.clone(), MAX_SCOREBOARDS);
+        Some(scoreboard)
+    }
+
+    // Games without a report are left out
+    pub fn scoreboard_many(game_uuids: &[String], service_config: &ServiceConfig) -> Vec<Scoreboard> {
+        game_uuids.iter().filter_map(|e| GameService::scoreboard(e, service_config)).collect()
+    }
+
+    // From the caches only, nothing is fetched. Takes the game rather than its uuid, the schedule entry is owned by the season service
+    pub fn export(game: &ApiGame) -> GameDocument {
+        GameDocument {
+            version: GAME_DOCUMENT_VERSION,
+            game: game.clone(),
+            report: GameReportService::read(&game.game_uuid),
+            stats: StatsService::read(&game.league, &game.game_uuid),
+            events: EventService::read(&game.game_uuid),
+            players: PlayerService::read(&game.league, &game.season, &game.game_uuid).unwrap_or_default(),
+        }
+    }
+
+    // None before the game has started
+    pub fn projections(game_uuid: &str) -> Option<ApiProjections> {
+        let report = GameReportService::read(game_uuid)?;
+        let events = EventService::read(game_uuid);
+        let count = |team: &str, shots: bool| match shots {
+            true => get_shot_count(&events, team).corsi as f32,
+            false => events.iter()
+                .filter(|e| e.period != GamePeriod::Shootout)
+                .filter(|e| matches!(&e.info, ApiEventType::Goal(g) if g.team == team))
+                .count() as f32,
+        };
+        let project = |team: &str, shots: bool| get_projection(count(team, shots), &report.status, report.period, &report.gametime);
+        Some(ApiProjections {
+            shots: ApiProjection { home: project(&report.home_team_code, true)?, away: project(&report.away_team_code, true)? },
+            goals: ApiProjection { home: project(&report.home_team_code, false)?, away: project(&report.away_team_code, false)? },
+        })
+    }
+
+    // None without a report, it has the team codes
+    pub fn shots(game_uuid: &str) -> Option<ApiShotCounts> {
+        let report = GameReportService::read(game_uuid)?;
+        let events = EventService::read(game_uuid);
+        Some(ApiShotCounts {
+            home: get_shot_count(&events, &report.home_team_code),
+            away: get_shot_count(&events, &report.away_team_code),
+        })
+    }
+
+    // One last fetch once the game has ended, after that everything is served from the cache
+    pub async fn finalize(game: &ApiGame) {
+        let game_uuid = &game.game_uuid;
+        let (events, stats, players) = futures::join!(
+            EventService::update(game_uuid, ThrottlePolicy::Force),
+            StatsService::update(&game.league, game_uuid, ThrottlePolicy::Force),
+            PlayerService::update(&game.league, &game.season, game_uuid, ThrottlePolicy::Force),
+        );
+        match (events, stats, players) {
+            (Ok(_), Ok(_), Ok(_)) => {
+                GameService::mark_final(game);
+                log::info!("[GAME] Finalized {game_uuid}");
+            },
+            _ => log::error!("[GAME] Final fetch failed {game_uuid}, keeping it refreshable"),
+        }
+    }
+
+    fn mark_final(game: &ApiGame) {
+        EventService::mark_final(&game.game_uuid);
+        StatsService::mark_final(&game.league, &game.game_uuid);
+        PlayerService::mark_final(&game.league, &game.season, &game.game_uuid);
+    }
+
+    fn get_win_probability(report: &ApiGameReport, powerplay: i8) -> (f32, f32) {
+        let diff = (report.home_team_result - report.away_team_result) as f32;
+        let home = match report.status {
+            GameStatus::Coming | GameStatus::Postponed | GameStatus::Cancelled => 0.5,
+            GameStatus::Finished | GameStatus::Shootout if diff == 0.0 => 0.5,
+            GameStatus::Finished | GameStatus::Shootout => if diff > 0.0 { 1.0 } else { 0.0 },
+            _ => {
+                // a lead weighs more the less time there is to catch up
+                let elapsed = get_elapsed_seconds(&report.status, report.period, &report.gametime) as f32;
+                let remaining = ((REGULATION_SECONDS - elapsed) / REGULATION_SECONDS).max(0.02);
+                let x = (diff + POWERPLAY_GOALS * powerplay as f32) / remaining.sqrt();
+                1.0 / (1.0 + (-x).exp())
+            },
+        };
+        let home = f32::clamp(home, 0.0, 1.0);
+        (home, 1.0 - home)
+    }
+
+    // 1 when home is on the powerplay, -1 when away is
+    fn get_powerplay(report: &ApiGameReport, events: &[ApiGameEvent]) -> i8 {
+        let strength = GameService::get_strength(report, events);
+        (strength.home as i8 - strength.away as i8).clamp(-1, 1)
+    }
+
+    // None before the game has started
+    pub fn current_strength(game_uuid: &str) -> Option<StrengthState> {
+        let report = GameReportService::read(game_uuid).filter(|e| e.status != GameStatus::Coming && !e.status.is_called_off())?;
+        Some(GameService::get_strength_state(&report, &EventService::read(game_uuid)))
+    }
+
+    fn get_strength(report: &ApiGameReport, events: &[ApiGameEvent]) -> ApiStrength {
+        let now = get_elapsed_seconds(&report.status, report.period, &report.gametime);
+        get_strength_at(&get_served_penalties(report, events, now), now).0
+    }
+
+    // At the latest known gametime, the report's or the latest event's if the report lags behind
+    fn get_strength_state(report: &ApiGameReport, events: &[ApiGameEvent]) -> StrengthState {
+        let now = events.iter()
+            .map(|e| get_elapsed_seconds(&e.status, e.period, &e.gametime))
+            .chain([get_elapsed_seconds(&report.status, report.period, &report.gametime)])
+            .max()
+            .unwrap_or_default();
+        let (strength, remaining_s) = get_strength_at(&get_served_penalties(report, events, now), now);
+        let powerplay_team = match strength.home.cmp(&strength.away) {
+            Ordering::Greater => Some(report.home_team_code.clone()),
+            Ordering::Less => Some(report.away_team_code.clone()),
+            Ordering::Equal => None,
+        };
+        StrengthState { strength, powerplay_team, remaining_s }
+    }
+}
+
+// Each penalty up to now, ended early when the other team scored on the powerplay. A goal ends the
+// shorthanded team's minor closest to running out, or the first half of a double minor
+fn get_served_penalties(report: &ApiGameReport, events: &[ApiGameEvent], now: i32) -> Vec<ServedPenalty> {
+    let mut events: Vec<&ApiGameEvent> = events.iter().filter(|e| get_elapsed_seconds(&e.status, e.period, &e.gametime) <= now).collect();
+    events.sort_by(|a, b| a.cmp_gametime(b));
+    let mut served: Vec<ServedPenalty> = vec![];
+    for e in events {
+        let at = get_elapsed_seconds(&e.status, e.period, &e.gametime);
+        match &e.info {
+            // misconducts don't change strength
+            ApiEventType::Penalty(p) if p.get_minutes() <= 5 => {
+                let minutes = p.get_minutes();
+                served.push(ServedPenalty { home: p.team == report.home_team_code, start: at, minutes, end: at + minutes * 60 });
+            },
+            ApiEventType::Goal(g) if !g.shootout => {
+                let (strength, _) = get_strength_at(&served, at);
+                let scored_home = g.team == report.home_team_code;
+                let (scorers, shorthanded) = match scored_home {
+                    true => (strength.home, strength.away),
+                    false => (strength.away, strength.home),
+                };
+                if scorers <= shorthanded {
+                    continue;
+                }
+                let is_offset = |p: &ServedPenalty| served.iter().any(|o| o.home != p.home && o.start == p.start);
+                let ended = served.iter()
+                    .enumerate()
+                    .filter(|(_, p)| p.home != scored_home && p.start < at && at < p.end && matches!(p.minutes, 2 | 4) && !is_offset(p))
+                    .min_by_key(|(_, p)| p.end)
+                    .map(|(i, _)| i);
+                if let Some(p) = ended.map(|i| &mut served[i]) {
+                    p.end = match p.minutes == 4 && at < p.start + 120 {
+                        true => at + 120,
+                        false => at,
+                    };
+                }
+            },
+            _ => {},
+        }
+    }
+    served
+}
+
+// Penalties given at the same time to both teams are paired off and only the net difference
+// reduces skaters. A single pair of coincidental minors at full strength is played 4v4
+fn get_strength_at(served: &[ServedPenalty], now: i32) -> (ApiStrength, Option<i32>) {
+    let mut active: HashMap<i32, (Vec<&ServedPenalty>, Vec<&ServedPenalty>)> = HashMap::new();
+    for p in served.iter().filter(|p| p.start <= now && now < p.end) {
+        let (home, away) = active.entry(p.start).or_default();
+        if p.home { home.push(p) } else { away.push(p) }
+    }
+
+    let (mut counted, mut coincidental): (Vec<&ServedPenalty>, Vec<(&ServedPenalty, &ServedPenalty)>) = (vec![], vec![]);
+    for (home, away) in active.values() {
+        let paired = home.len().min(away.len());
+        counted.extend(home[paired..].iter().chain(&away[paired..]));
+        coincidental.extend(home[..paired].iter().copied().zip(away[..paired].iter().copied()));
+    }
+    if counted.is_empty() && coincidental.len() == 1 && coincidental.iter().all(|(h, a)| h.minutes < 5 && a.minutes < 5) {
+        counted.extend(coincidental.iter().flat_map(|(h, a)| [*h, *a]));
+    }
+    let home = counted.iter().filter(|e| e.home).count();
+    let away = counted.len() - home;
+    let skaters = |penalties: usize| SKATERS - penalties.min((SKATERS - MIN_SKATERS) as usize) as u8;
+    let remaining_s = counted.iter().map(|e| e.end - now).min();
+    (ApiStrength { home: skaters(home), away: skaters(away) }, remaining_s)
+}
+
+// Shootout attempts don't count, the shootout winner gets the single decisive goal
+fn get_score(mut goals: Vec<ApiGameEvent>, home_team_code: Option<&str>) -> (i16, i16) {
+    goals.sort_by(ApiGameEvent::cmp_gametime);
+    let goals: Vec<GoalInfo> = goals.into_iter()
+        .filter_map(|e| match e.info {
+            ApiEventType::Goal(g) => Some(g),
+            _ => None,
+        })
+        .collect();
+    let (home, away) = goals.iter()
+        .rfind(|e| !e.shootout)
+        .map(|e| (e.home_team_result, e.away_team_result))
+        .unwrap_or((0, 0));
+    let Some(home_team_code) = home_team_code.map(normalize_team_code) else {
+        return (home, away);
+    };
+    let (home_so, away_so) = goals.iter()
+        .filter(|e| e.shootout)
+        .fold((0, 0), |(h, a), e| if e.team == home_team_code { (h + 1, a) } else { (h, a + 1) });
+    match home_so.cmp(&away_so) {
+        Ordering::Greater => (home + 1, away),
+        Ordering::Less => (home, away + 1),
+        Ordering::Equal => (home, away),
+    }
+}
+
+// When full, the least recently built scoreboard makes room, finished or not
+fn cache_scoreboard(scoreboards: &mut HashMap<String, (Instant, Scoreboard)>, scoreboard: Scoreboard, max: usize) {
+    if scoreboards.len() >= max && !scoreboards.contains_key(&scoreboard.game_uuid) {
+        let oldest = scoreboards.iter().min_by_key(|e| e.1.0).map(|e| e.0.clone());
+        if let Some(oldest) = oldest {
+            scoreboards.remove(&oldest);
+        }
+    }
+    scoreboards.insert(scoreboard.game_uuid.clone(), (Instant::now(), scoreboard));
+}
+
+// Goal events usually arrive before the report catches up, so the score furthest along wins
+fn get_scoreboard(report: ApiGameReport, event_score: Option<(i16, i16)>) -> Scoreboard {
+    let report_score = (report.home_team_result, report.away_team_result);
+    let (home_team_result, away_team_result) = match event_score {
+        Some(score) if score.0 + score.1 > report_score.0 + report_score.1 => score,
+        _ => report_score,
+    };
+    let period = match report.status {
+        GameStatus::Period1 => Some(GamePeriod::Regulation(1)),
+        GameStatus::Period2 => Some(GamePeriod::Regulation(2)),
+        GameStatus::Period3 => Some(GamePeriod::Regulation(3)),
+        GameStatus::Overtime => match report.period {
+            GamePeriod::Overtime(n) => Some(GamePeriod::Overtime(n)),
+            _ => Some(GamePeriod::Overtime(1)),
+        },
+        GameStatus::Shootout => Some(GamePeriod::Shootout),
+        GameStatus::Coming | GameStatus::Intermission | GameStatus::Finished | GameStatus::Postponed | GameStatus::Cancelled => None,
+    };
+    Scoreboard {
+        game_uuid: report.game_uuid,
+        home_team_code: report.home_team_code,
+        away_team_code: report.away_team_code,
+        home_team_result,
+        away_team_result,
+        period,
+        gametime: report.gametime,
+        status: report.status,
+    }
+}
+
+// Upstream sometimes sends the shot of a goal as its own event too, at the same game time.
+// Such a shot is the goal's, so it's skipped rather than counted twice
+fn get_shot_count(events: &[ApiGameEvent], team: &str) -> ApiShotCount {
+    let events: Vec<&ApiGameEvent> = events.iter().filter(|e| e.period != GamePeriod::Shootout).collect();
+    let is_goal_at = |period: GamePeriod, gametime: &str| events.iter()
+        .any(|e| e.period == period && e.gametime == gametime && matches!(&e.info, ApiEventType::Goal(g) if g.team == team));
+    let mut count = ApiShotCount::default();
+    for event in &events {
+        let (sog, corsi) = match &event.info {
+            ApiEventType::Goal(g) if g.team == team => (true, true),
+            ApiEventType::Shot(s) if s.team == team && !is_goal_at(event.period, &event.gametime) => match s.kind {
+                ShotKind::OnGoal => (true, true),
+                ShotKind::Blocked | ShotKind::Wide | ShotKind::Iron => (false, true),
+                ShotKind::Penalty => (false, false),
+            },
+            _ => (false, false),
+        };
+        count.sog += sog as u16;
+        count.corsi += corsi as u16;
+    }
+    count
+}
+
+// Linear over regulation time, the actual total once regulation is over
+fn get_projection(total: f32, status: &GameStatus, period: GamePeriod, gametime: &str) -> Option<f32> {
+    let elapsed = get_elapsed_seconds(status, period, gametime) as f32;
+    match status {
+        GameStatus::Coming | GameStatus::Postponed | GameStatus::Cancelled => None,
+        GameStatus::Finished | GameStatus::Overtime | GameStatus::Shootout => Some(total),
+        _ if elapsed <= 0.0 => None,
+        _ if elapsed >= REGULATION_SECONDS => Some(total),
+        _ => Some(total * REGULATION_SECONDS / elapsed),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{time::Duration, collections::HashMap};
+
+    use chrono::Utc;
+    use tempdir::TempDir;
+
+    use crate::{game_report_service::{ApiGameReport, GameStatus, GameReportService}, event_service::{ApiGameEvent, ApiEventType, PenaltyInfo, EventService, ShotInfo, ShotKind, Location}, models2::external::{event::{Penalty, PlayByPlay, PlayByPlayType}, player::PlayerStatsRsp, game_stats::StatsRsp}, models::{GamePeriod, GameType, League, Season}, api_season_service::ApiGame, db::Db, rest_client::{self, ThrottlePolicy}, player_service::PlayerService, stats_service::StatsService, service_config::ServiceConfig, mock_server::routed_mock_server};
+
+    use super::{get_score, cache_scoreboard, get_scoreboard, GameService, ApiStrength, StrengthState, ApiShotCount, get_projection, get_shot_count};
+
+    #[test]
+    fn projections_are_linear() {
+        assert_eq!(get_projection(10.0, &GameStatus::Period2, GamePeriod::Regulation(2), "00:00"), Some(30.0));
+        assert_eq!(get_projection(10.0, &GameStatus::Period2, GamePeriod::Regulation(2), "10:00"), Some(20.0));
+        assert_eq!(get_projection(0.0, &GameStatus::Coming, GamePeriod::Regulation(0), "00:00"), None);
+        assert_eq!(get_projection(0.0, &GameStatus::Period1, GamePeriod::Regulation(1), "00:00"), None);
+        assert_eq!(get_projection(31.0, &GameStatus::Finished, GamePeriod::Regulation(3), "60:00"), Some(31.0));
+        assert_eq!(get_projection(31.0, &GameStatus::Overtime, GamePeriod::Overtime(1), "02:00"), Some(31.0));
+    }
+
+    #[test]
+    fn goal_and_its_shot_count_once() {
+        let shot = |gametime: &str, team: &str, kind: ShotKind| ApiGameEvent {
+            gametime: gametime.to_string(),
+            info: ApiEventType::Shot(ShotInfo { team: team.to_string(), location: Location { x: 0.0, y: 0.0 }, kind }),
+            ..get_goal(gametime, team)
+        };
+        let events = vec![
+            shot("02:00", "LHF", ShotKind::OnGoal),
+            shot("04:10", "LHF", ShotKind::OnGoal),
+            get_goal("04:10", "LHF"),
+            shot("05:00", "LHF", ShotKind::Wide),
+            shot("06:00", "LHF", ShotKind::Blocked),
+            shot("07:00", "FHC", ShotKind::OnGoal),
+        ];
+        assert_eq!(get_shot_count(&events, "LHF"), ApiShotCount { sog: 2, corsi: 4 });
+        assert_eq!(get_shot_count(&events, "FHC"), ApiShotCount { sog: 1, corsi: 1 });
+    }
+
+    #[tokio::test]
+    async fn finished_game_is_served_from_cache() {
+        std::env::set_var("DB_PATH", TempDir::new("test").expect("dir to be created").path().to_str().unwrap());
+        let game = ApiGame { game_uuid: "finished_game_is_served_from_cache".to_string(), ..get_game() };
+        let players = serde_json::to_string(&PlayerStatsRsp::default()).unwrap();
+        let stats = serde_json::to_string(&StatsRsp::default()).unwrap();
+        // the first final fetch fails on the players, the second one gets everything
+        let base = routed_mock_server(vec![
+            ("/boxscore/", "500 Internal Server Error", String::new()),
+            ("/periodstats/", "200 OK", stats.clone()),
+            ("/play-by-play/", "200 OK", "[]".to_string()),
+            ("/boxscore/", "200 OK", players),
+            ("/periodstats/", "200 OK", stats),
+            ("/play-by-play/", "200 OK", "[]".to_string()),
+        ]).await;
+        rest_client::with_base_url(&base, async {
+            GameService::finalize(&game).await;
+            assert!(PlayerService::is_stale(&game.league, &game.season, &game.game_uuid));
+            GameService::finalize(&game).await;
+
+            // the mock server is done, so these only succeed from the cache
+            assert!(PlayerService::update(&game.league, &game.season, &game.game_uuid, ThrottlePolicy::Force).await.is_ok());
+            assert!(StatsService::update(&game.league, &game.game_uuid, ThrottlePolicy::Force).await.is_ok());
+            assert!(EventService::update(&game.game_uuid, ThrottlePolicy::Force).await.is_ok());
+        }).await;
+    }
+
+    #[test]
+    fn late_game_lead() {
+        let (home, away) = GameService::get_win_probability(&get_report(GameStatus::Period3, "18:00", 2, 1), 0);
+        assert!(home > 0.9, "{home}");
+        assert!((home + away - 1.0).abs() < f32::EPSILON);
+
+        let (early, _) = GameService::get_win_probability(&get_report(GameStatus::Period1, "05:00", 2, 1), 0);
+        assert!(early < home);
+    }
+
+    #[test]
+    fn tied_early() {
+        assert_eq!(GameService::get_win_probability(&get_report(GameStatus::Period1, "02:00", 0, 0), 0), (0.5, 0.5));
+        assert_eq!(GameService::get_win_probability(&get_report(GameStatus::Coming, "00:00", 0, 0), 0), (0.5, 0.5));
+        assert_eq!(GameService::get_win_probability(&get_report(GameStatus::Finished, "60:00", 1, 3), 0), (0.0, 1.0));
+    }
+
+    #[test]
+    fn powerplay_from_active_penalty() {
+        let report = get_report(GameStatus::Period2, "11:00", 1, 1);
+        let penalty = |gametime: &str, team: &str| get_penalty(gametime, team, "2 min");
+        assert_eq!(GameService::get_powerplay(&report, &[penalty("10:00", "FHC")]), 1);
+        assert_eq!(GameService::get_powerplay(&report, &[penalty("10:00", "LHF")]), -1);
+        assert_eq!(GameService::get_powerplay(&report, &[penalty("08:00", "FHC")]), 0);
+        assert_eq!(GameService::get_powerplay(&report, &[penalty("10:00", "FHC"), penalty("10:30", "LHF")]), 0);
+
+        let (home, _) = GameService::get_win_probability(&report, 1);
+        assert!(home > 0.5);
+    }
+
+    #[test]
+    fn coincidental_minors_are_four_on_four() {
+        let report = get_report(GameStatus::Period2, "11:00", 1, 1);
+        let strength = |events: &[ApiGameEvent]| GameService::get_strength(&report, events);
+        let full = ApiStrength { home: 5, away: 5 };
+        assert_eq!(strength(&[]), full);
+        assert_eq!(strength(&[get_penalty("10:00", "LHF", "2 min"), get_penalty("10:00", "FHC", "2 min")]), ApiStrength { home: 4, away: 4 });
+        // Offsetting majors, and coincidental minors on top of a powerplay, don't change the advantage
+        assert_eq!(strength(&[get_penalty("10:00", "LHF", "5 min"), get_penalty("10:00", "FHC", "5 min")]), full);
+        assert_eq!(strength(&[
+            get_penalty("09:30", "LHF", "2 min"),
+            get_penalty("10:00", "LHF", "2 min"),
+            get_penalty("10:00", "FHC", "2 min"),
+        ]), ApiStrength { home: 4, away: 5 });
+        assert_eq!(strength(&[
+            get_penalty("09:30", "LHF", "2 min"),
+            get_penalty("10:00", "FHC", "2 min"),
+            get_penalty("10:30", "FHC", "2 min"),
+            get_penalty("10:45", "FHC", "2 min"),
+        ]), ApiStrength { home: 4, away: 3 });
+        assert_eq!(GameService::get_powerplay(&report, &[get_penalty("10:00", "LHF", "2 min"), get_penalty("10:00", "FHC", "2 min")]), 0);
+    }
+
+    #[test]
+    fn current_five_on_four() {
+        let report = get_report(GameStatus::Period2, "11:30", 1, 1);
+        let state = |events: &[ApiGameEvent]| GameService::get_strength_state(&report, events);
+        let penalty = get_penalty("11:00", "FHC", "2 min");
+
+        // the latest event is further along than the report
+        let current = state(&[penalty.clone(), ApiGameEvent { gametime: "11:40".to_string(), ..get_penalty("11:40", "FHC", "10 min") }]);
+        assert_eq!(current, StrengthState { strength: ApiStrength { home: 5, away: 4 }, powerplay_team: Some("LHF".to_string()), remaining_s: Some(80) });
+        assert_eq!(state(&[get_penalty("08:00", "FHC", "2 min")]), StrengthState { strength: ApiStrength { home: 5, away: 5 }, powerplay_team: None, remaining_s: None });
+
+        // a powerplay goal ends the minor, a shorthanded one doesn't
+        assert_eq!(state(&[penalty.clone(), get_goal("11:20", "LHF")]).strength, ApiStrength { home: 5, away: 5 });
+        assert_eq!(state(&[penalty.clone(), get_goal("11:20", "FHC")]).remaining_s, Some(90));
+        // only the first half of a double minor
+        let double = state(&[get_penalty("10:00", "FHC", "4 min"), get_goal("11:00", "LHF")]);
+        assert_eq!((double.strength, double.remaining_s), (ApiStrength { home: 5, away: 4 }, Some(90)));
+    }
+
+    #[test]
+    fn score_from_last_goal() {
+        std::env::set_var("DB_PATH", TempDir::new("test").expect("dir to be created").path().to_str().unwrap());
+        let game_uuid = "score_from_last_goal";
+        assert_eq!(GameService::score(game_uuid), None);
+
+        let events = vec![
+            serde_json::from_str(r#"{ "eventId": 1, "revision": 1, "hash": "abc", "period": 1, "gametime": "00:01", "description": "", "class": "General" }"#).unwrap(),
+            get_goal_json(2, 1, "05:00", 1, 0),
+            get_goal_json(5, 3, "02:00", 2, 2),
+            get_goal_json(3, 2, "10:00", 1, 1),
+            get_goal_json(4, 2, "15:00", 1, 2),
+        ];
+        EventService::store_all_raw(game_uuid, events);
+        assert_eq!(GameService::score(game_uuid), Some((2, 2)));
+    }
+
+    #[test]
+    fn shootout_counts_decisive_goal_only() {
+        let mut away_attempt = get_goal_json(7, 99, "00:00", 3, 3);
+        if let PlayByPlayType::Goal(g) = &mut away_attempt.class {
+            g.team = "FHC".to_string();
+        }
+        let goals = vec![
+            get_goal_json(4, 3, "19:00", 2, 2),
+            get_goal_json(5, 99, "00:00", 3, 2),
+            away_attempt,
+            get_goal_json(8, 99, "00:00", 4, 3),
+        ].into_iter().map(|e| e.into_mapped_event("game_uuid")).collect::<Vec<_>>();
+        let scorers = goals.iter()
+            .filter(|e| matches!(&e.info, ApiEventType::Goal(g) if !g.shootout))
+            .count();
+        assert_eq!(scorers, 1);
+
+        assert_eq!(get_score(goals.clone(), Some("LHF")), (3, 2));
+        assert_eq!(get_score(goals, None), (2, 2));
+    }
+
+    #[test]
+    fn scoreboard_from_report_and_goals() {
+        std::env::set_var("DB_PATH", TempDir::new("test").expect("dir to be created").path().to_str().unwrap());
+        let game_uuid = "scoreboard_from_report_and_goals";
+        GameReportService::store(game_uuid, &ApiGameReport { game_uuid: game_uuid.to_string(), ..get_report(GameStatus::Period2, "04:00", 0, 0) });
+        let goal: PlayByPlay = serde_json::from_str(r#"{
+            "eventId": 12, "revision": 1, "hash": "abc", "period": 2, "gametime": "03:30", "description": "Mål",
+            "class": "Goal", "team": "LHF", "location": { "x": 1.0, "y": 2.0 },
+            "extra": { "scorerLong": "1 Olle Olsson", "teamAdvantage": "EQ", "homeAgainst": 0, "homeForward": 1, "assist": "" }
+        }"#).unwrap();
+        EventService::store_all_raw(game_uuid, vec![goal]);
+
+        let scoreboard = GameService::scoreboard(game_uuid, &ServiceConfig::default()).unwrap();
+        assert_eq!((scoreboard.home_team_result, scoreboard.away_team_result), (1, 0));
+        assert_eq!(scoreboard.period, Some(GamePeriod::Regulation(2)));
+        assert_eq!(scoreboard.gametime, "04:00");
+        assert_eq!(scoreboard.home_team_code, "LHF");
+
+        let many = GameService::scoreboard_many(&[game_uuid.to_string(), "scoreboard_missing_report".to_string()], &ServiceConfig::default());
+        assert_eq!(many, vec![scoreboard]);
+    }
+
+    #[test]
+    fn scoreboard_follows_configured_ttl() {
+        std::env::set_var("DB_PATH", TempDir::new("test").expect("dir to be created").path().to_str().unwrap());
+        let game_uuid = "scoreboard_follows_configured_ttl";
+        GameReportService::store(game_uuid, &ApiGameReport { game_uuid: game_uuid.to_string(), ..get_report(GameStatus::Period1, "01:00", 0, 0) });
+        assert_eq!(GameService::scoreboard(game_uuid, &ServiceConfig::default()).unwrap().gametime, "01:00");
+
+        GameReportService::store(game_uuid, &ApiGameReport { game_uuid: game_uuid.to_string(), ..get_report(GameStatus::Period1, "02:00", 0, 0) });
+        assert_eq!(GameService::scoreboard(game_uuid, &ServiceConfig::default()).unwrap().gametime, "01:00");
+        let no_cache = ServiceConfig::builder().scoreboard(Duration::ZERO).build();
+        assert_eq!(GameService::scoreboard(game_uuid, &no_cache).unwrap().gametime, "02:00");
+    }
+
+    #[test]
+    fn scoreboard_cache_is_bounded() {
+        let mut scoreboards = HashMap::new();
+        let scoreboard = |game_uuid: &str, status: GameStatus| get_scoreboard(ApiGameReport { game_uuid: game_uuid.to_string(), ..get_report(status, "20:00", 1, 0) }, None);
+        cache_scoreboard(&mut scoreboards, scoreboard("first", GameStatus::Finished), 2);
+        cache_scoreboard(&mut scoreboards, scoreboard("second", GameStatus::Period2), 2);
+        cache_scoreboard(&mut scoreboards, scoreboard("second", GameStatus::Period3), 2);
+        assert_eq!(scoreboards.len(), 2);
+
+        // the finished one is the oldest
+        cache_scoreboard(&mut scoreboards, scoreboard("third", GameStatus::Period1), 2);
+        let mut game_uuids: Vec<&String> = scoreboards.keys().collect();
+        game_uuids.sort();
+        assert_eq!(game_uuids, vec!["second", "third"]);
+    }
+
+    #[test]
+    fn scoreboard_overtime_from_report() {
+        let overtime = |period: GamePeriod| get_scoreboard(ApiGameReport { period, ..get_report(GameStatus::Overtime, "05:00", 1, 1) }, None).period;
+        assert_eq!(overtime(GamePeriod::Overtime(2)), Some(GamePeriod::Overtime(2)));
+        // stored before the period was kept
+        assert_eq!(overtime(GamePeriod::default()), Some(GamePeriod::Overtime(1)));
+    }
+
+    #[test]
+    fn export_round_trips() {
+        std::env::set_var("DB_PATH", TempDir::new("test").expect("dir to be created").path().to_str().unwrap());
+        let game = ApiGame { game_uuid: "export_round_trips".to_string(), ..get_game() };
+        let fixture = |file: &str| std::fs::read_to_string(std::path::PathBuf::from(env!("CARGO_MANIFEST_DIR")).join("fixtures/game1").join(file)).unwrap();
+        EventService::store_all_raw(&game.game_uuid, serde_json::from_str(&fixture("events.json")).unwrap());
+        let stats: StatsRsp = serde_json::from_str(&fixture("stats.json")).unwrap();
+        _ = Db::<String, StatsRsp>::new("rest").write(&rest_client::get_stats_url(&game.league, &game.game_uuid), &stats);
+        let players: PlayerStatsRsp = serde_json::from_str(&fixture("players.json")).unwrap();
+        _ = Db::<String, PlayerStatsRsp>::new("rest").write(&rest_client::get_player_stats_key(&game.league, &game.season, &game.game_uuid), &players);
+        GameReportService::store(&game.game_uuid, &ApiGameReport { game_uuid: game.game_uuid.clone(), ..get_report(GameStatus::Finished, "60:00", 2, 1) });
+
+        let document = GameService::export(&game);
+        assert_eq!(document.version, super::GAME_DOCUMENT_VERSION);
+        assert!(!document.events.is_empty());
+        assert!(!document.players.is_empty());
+        assert!(document.stats.is_some());
+        assert_eq!(document.report.as_ref().map(|e| e.status.clone()), Some(GameStatus::Finished));
+
+        let json = serde_json::to_string(&document).unwrap();
+        let imported: super::GameDocument = serde_json::from_str(&json).unwrap();
+        assert_eq!(serde_json::to_value(&imported).unwrap(), serde_json::to_value(&document).unwrap());
+    }
+
+    fn get_penalty(gametime: &str, team: &str, penalty: &str) -> ApiGameEvent {
+        ApiGameEvent {
+            game_uuid: "game_uuid".to_string(),
+            event_id: "1".to_string(),
+            revision: 1,
+            status: GameStatus::Period2,
+            period: GamePeriod::Regulation(2),
+            gametime: gametime.to_string(),
+            description: "".to_string(),
+            info: ApiEventType::Penalty(PenaltyInfo::new(&format!("1 Olle Olsson utvisas {penalty}, Hooking"), &Penalty { team: team.to_string(), playerId: None })),
+            tags: vec![],
+        }
+    }
+
+    fn get_goal_json(event_id: i32, period: i16, gametime: &str, home: i16, away: i16) -> PlayByPlay {
+        serde_json::from_str(&format!(r#"{{
+            "eventId": {event_id}, "revision": 1, "hash": "abc", "period": {period}, "gametime": "{gametime}", "description": "Mål",
+            "class": "Goal", "team": "LHF", "location": {{ "x": 1.0, "y": 2.0 }},
+            "extra": {{ "scorerLong": "1 Olle Olsson", "teamAdvantage": "EQ", "homeAgainst": {away}, "homeForward": {home}, "assist": "" }}
+        }}"#)).unwrap()
+    }
+
+    fn get_goal(gametime: &str, team: &str) -> ApiGameEvent {
+        let goal: PlayByPlay = serde_json::from_str(&format!(r#"{{
+            "eventId": 12, "revision": 1, "hash": "abc", "period": 2, "gametime": "{gametime}", "description": "Mål",
+            "class": "Goal", "team": "{team}", "location": {{ "x": 1.0, "y": 2.0 }},
+            "extra": {{ "scorerLong": "1 Olle Olsson", "teamAdvantage": "PP1", "homeAgainst": 0, "homeForward": 1, "assist": "" }}
+        }}"#)).unwrap();
+        goal.into_mapped_event("game_uuid")
+    }
+
+    fn get_game() -> ApiGame {
+        ApiGame {
+            game_uuid: "game_uuid".to_string(),
+            home_team_code: "LHF".to_string(),
+            away_team_code: "FHC".to_string(),
+            home_team_result: 2,
+            away_team_result: 1,
+            start_date_time: Utc::now(),
+            start_date_time_local: None,
+            status: GameStatus::Finished,
+            shootout: false,
+            overtime: false,
+            played: true,
+            game_type: GameType::Season,
+            league: League::SHL,
+            season: Season::Season2022,
+            gametime: None,
+        }
+    }
+
+    fn get_report(status: GameStatus, gametime: &str, home: i16, away: i16) -> ApiGameReport {
+        ApiGameReport {
+            game_uuid: "game_uuid".to_string(),
+            gametime: gametime.to_string(),
+            status,
+            period: Default::default(),
+            home_team_code: "LHF".to_string(),
+            away_team_code: "FHC".to_string(),
+            home_team_result: home,
+            away_team_result: away,
+        }
+    }
+}
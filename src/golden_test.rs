@@ -0,0 +1,50 @@
+/**
+ * Golden tests for the upstream -> api mapping.
+ * Each fixtures/<game>/ dir holds recorded upstream responses (events.json, stats.json, players.json)
+ * and the expected mapped outputs (*.golden.json).
+ * Regenerate the goldens with UPDATE_GOLDEN=1 cargo test golden
+ */
+use std::path::PathBuf;
+
+use serde::{Serialize, de::DeserializeOwned};
+
+use crate::{models2::external::{event::PlayByPlay, game_stats::StatsRsp, player::PlayerStatsRsp}, event_service::ApiGameEvent, stats_service::ApiGameStats, player_service::ApiAthlete};
+
+fn get_path(game: &str, file: &str) -> PathBuf {
+    PathBuf::from(env!("CARGO_MANIFEST_DIR")).join("fixtures").join(game).join(file)
+}
+
+fn read_fixture<T: DeserializeOwned>(game: &str, file: &str) -> T {
+    let path = get_path(game, file);
+    let data = std::fs::read_to_string(&path).unwrap_or_else(|e| panic!("Could not read {:?} {e}", path));
+    serde_json::from_str(&data).unwrap_or_else(|e| panic!("Could not parse {:?} {e}", path))
+}
+
+fn assert_golden<T: Serialize>(game: &str, file: &str, mapped: &T) {
+    let path = get_path(game, file);
+    let actual = serde_json::to_value(mapped).unwrap();
+    if std::env::var_os("UPDATE_GOLDEN").is_some() {
+        std::fs::write(&path, serde_json::to_string_pretty(&actual).unwrap() + "\n").unwrap();
+        return;
+    }
+    let expected: serde_json::Value = read_fixture(game, file);
+    assert_eq!(actual, expected, "{:?} doesn't match, run with UPDATE_GOLDEN=1 if the change is intended", path);
+}
+
+fn assert_game(game: &str) {
+    let raw_events: Vec<PlayByPlay> = read_fixture(game, "events.json");
+    let mut events: Vec<ApiGameEvent> = raw_events.into_iter().map(|e| e.into_mapped_event(game)).collect();
+    events.sort_by(ApiGameEvent::cmp_gametime);
+    assert_golden(game, "events.golden.json", &events);
+
+    let stats: ApiGameStats = read_fixture::<StatsRsp>(game, "stats.json").into();
+    assert_golden(game, "stats.golden.json", &stats);
+
+    let players: Vec<ApiAthlete> = read_fixture::<PlayerStatsRsp>(game, "players.json").into();
+    assert_golden(game, "players.golden.json", &players);
+}
+
+#[test]
+fn golden_game1() {
+    assert_game("game1");
+}
@@ -0,0 +1,35 @@
+use serde::Serialize;
+
+// Compact is the wire format clients get, pretty is for people reading responses by hand
+pub fn to_json<T: Serialize>(value: &T, pretty: bool) -> serde_json::Result<String> {
+    match pretty {
+        true => serde_json::to_string_pretty(value),
+        false => serde_json::to_string(value),
+    }
+}
+
+// Pretty prints an already serialized body, None if it isn't json. Object keys come out sorted
+pub fn prettify(body: &[u8]) -> Option<String> {
+    let value: serde_json::Value = serde_json::from_slice(body).ok()?;
+    to_json(&value, true).ok()
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::event_service::ApiScoreChange;
+
+    use super::{to_json, prettify};
+
+    #[test]
+    fn pretty_only_when_asked() {
+        let score = ApiScoreChange { game_uuid: "game_uuid".to_string(), home_team_result: 2, away_team_result: 1 };
+        let compact = to_json(&score, false).unwrap();
+        let pretty = to_json(&score, true).unwrap();
+        assert!(!compact.contains('\n') && !compact.contains("  "));
+        assert!(pretty.contains("\n  \"home_team_result\": 2"), "{pretty}");
+        let prettified = prettify(compact.as_bytes()).unwrap();
+        assert!(prettified.contains("\n  \"away_team_result\": 1"), "{prettified}");
+        assert_eq!(serde_json::from_str::<serde_json::Value>(&prettified).unwrap(), serde_json::from_str::<serde_json::Value>(&compact).unwrap());
+        assert_eq!(prettify(b"Puck puck puck"), None);
+    }
+}
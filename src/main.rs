@@ -22,10 +22,12 @@ use crate::notification_service::{NotificationService};
 use crate::report_state_machine::{ReportStateMachine, ApiSseMsg};
 use crate::event_service::{EventService, ApiEventType, ApiEventTypeLevel};
 use crate::game_report_service::{GameReportService, ApiGameReport, GameStatus};
-use crate::player_service::PlayerService;
+use crate::publish_service::PublishService;
+use crate::game_refresher::GameRefresher;
+use crate::game_service::GameService;
+use crate::shutdown_service::ShutdownService;
 use crate::sse_client::{SseClient};
 use crate::season_service::SeasonService;
-use crate::stats_service::StatsService;
 use crate::user_service::UserService;
 use tracing::{log};
 use lazy_static::lazy_static;
@@ -56,9 +58,27 @@ mod apn_client;
 mod in_mem_games;
 mod api_player_stats_service;
 mod playoff_service;
+mod schedule_service;
+mod publish_service;
+mod single_flight;
+mod service_error;
+mod game_service;
+mod event_delta;
+mod fieldset;
+mod game_refresher;
+mod shutdown_service;
+mod json_format;
+mod team_service;
+mod event_proto;
+mod sse_gzip;
+mod metrics;
+mod service_config;
 
 #[cfg(test)]
-mod mock_test;
+mod golden_test;
+#[cfg(test)]
+mod mock_server;
+
 
 lazy_static! {
     pub static ref CONFIG: Config = config_handler::get_config();
@@ -80,15 +100,18 @@ async fn main() {
         .with_thread_names(false)
         .with_file(false)
         .compact();
+    // Span close events log the busy/idle time of each fetch/map/store phase
     tracing_subscriber::fmt()
         .event_format(format)
+        .with_span_events(tracing_subscriber::fmt::format::FmtSpan::CLOSE)
         .with_max_level(tracing::Level::INFO)
         .init();
 
+    EventService::add_default_processors();
     let api_season_service = ApiSeasonService::new();
     let vote_service = VoteService::new();
     for season in Season::get_all() {
-        let (responses, _) = SeasonService { }.update(&season).await;
+        let (responses, _) = SeasonService { service_config: CONFIG.service_config.clone() }.update(&season).await;
         let api_games = api_season_service.write().await.update(&season, &responses);
         
         StandingService::update(&season, &api_games);
@@ -99,18 +122,29 @@ async fn main() {
 
     let (live_game_sender, live_game_receiver) = mpsc::channel(1000);
     let (sse_msg_sender, sse_msg_receiver) = mpsc::channel(1000);
-    let (broadcast_sender, _) = tokio::sync::broadcast::channel(1000);
+    let (broadcast_sender, _) = api_ws::broadcast_channel();
 
     let loop_api_season_service = api_season_service.clone();
     let event_api_season_service = api_season_service.clone();
     let sse_api_season_service = api_season_service.clone();
     let sse_broadcast_sender = broadcast_sender.clone();
-    let h1 = tokio::spawn(async move { Api::serve(CONFIG.port, api_season_service, vote_service, broadcast_sender).await });
+    // One instance, so the debounce window spans the sse events and the api refreshes
+    let publish_service = PublishService::from_config();
+    let sse_publish_service = publish_service.clone();
+    let h1 = tokio::spawn(async move { Api::serve(CONFIG.port, api_season_service, vote_service, broadcast_sender, CONFIG.service_config.clone(), publish_service).await });
     let h2 = tokio::spawn(async { start_loop(live_game_sender, loop_api_season_service).await });
     let h3 = tokio::spawn(async { game_start_end_listener(sse_api_season_service, live_game_receiver, sse_msg_sender).await });
-    let h4 = tokio::spawn(async { handle_sse_events(event_api_season_service, sse_msg_receiver, sse_broadcast_sender).await });
+    let h4 = tokio::spawn(async { handle_sse_events(event_api_season_service, sse_msg_receiver, sse_broadcast_sender, sse_publish_service).await });
 
-    join_all(vec!(h1, h2, h3, h4)).await;
+    // SIGTERM is what container runtimes and systemd stop with
+    let mut terminate = tokio::signal::unix::signal(tokio::signal::unix::SignalKind::terminate())
+        .expect("SIGTERM handler to be installed");
+    select! {
+        _ = join_all(vec!(h1, h2, h3, h4)) => {},
+        _ = tokio::signal::ctrl_c() => log::info!("[SHUTDOWN] Interrupted"),
+        _ = terminate.recv() => log::info!("[SHUTDOWN] Terminated"),
+    }
+    ShutdownService::shutdown(Duration::from_secs(10)).await;
 
 }
 
@@ -118,7 +152,7 @@ async fn start_loop(
     live_game_sender: Sender<String>, 
     api_season_service: SafeApiSeasonService,
 ) {
-    let season_service = SeasonService { };
+    let season_service = SeasonService { service_config: CONFIG.service_config.clone() };
     let mut sent_live_games = BoundedVecDeque::new(40);
 
     loop {
@@ -131,7 +165,7 @@ async fn start_loop(
             ApiPlayerStatsService::update(&api_games);
         }
 
-        FetchDetailsService::update().await;
+        FetchDetailsService::update(&CONFIG.service_config).await;
 
         let live_games: &Vec<String> = &responses.iter()
             .flat_map(|e| e.1.gameInfo.iter())
@@ -170,7 +204,7 @@ async fn game_start_end_listener(
                         Some((game_uuid, report)) = report_receiver.recv() => {
                             let mapped: ApiGameReport = report.into();
                             if let Some(report_event) = rsm.process(&mapped) {
-                                sse_sender.send((game_uuid.clone(), ApiSseMsg::Event(report_event))).await
+                                sse_sender.send((game_uuid.clone(), ApiSseMsg::Event(Box::new(report_event)))).await
                                     .ok_log("[SSE] Failed to send event");
                             }
                             sse_sender.send((uuid.clone(), ApiSseMsg::Report(mapped))).await
@@ -179,12 +213,12 @@ async fn game_start_end_listener(
                         Some((game_uuid, event)) = event_receiver.recv() => {
                             EventService::store_raw(&uuid, &event);
                             let mapped = event.into_mapped_event(&uuid);
-                            sse_sender.send((game_uuid.clone(), ApiSseMsg::Event(mapped))).await
+                            sse_sender.send((game_uuid.clone(), ApiSseMsg::Event(Box::new(mapped)))).await
                                 .ok_log("[SSE] Failed to send event");
                         }
                         // if 10 minutes has passed without any new events and status is finished => abort
                         _ = tokio::time::sleep(Duration::from_secs(60 * 10)) => {
-                            if let Some(GameStatus::Finished) = ass.read().await.read_current_season_game(&uuid).map(|e| e.status) {
+                            if ass.read().await.read_current_season_game(&uuid).is_some_and(|e| e.status == GameStatus::Finished || e.status.is_called_off()) {
                                 log::info!("[SSE] Abort {}", game_uuid);
                                 break;
                             }
@@ -209,10 +243,12 @@ async fn handle_sse_events(
     api_season_service: SafeApiSeasonService,
     mut sse_msg_receiver: Receiver<(String, ApiSseMsg)>, 
     broadcast_sender: tokio::sync::broadcast::Sender<WsMsg>,
+    publish_service: PublishService,
 ) {
 
     log::info!("[SSE] Start sse handler");
     let mut notification_service = NotificationService::new();
+    let refresher = GameRefresher::from_config();
     loop {
         if let Some((game_uuid, msg)) = sse_msg_receiver.recv().await {
             match msg {
@@ -226,34 +262,36 @@ async fn handle_sse_events(
                     if let Some(g) = updated_api_game {
                         notification_service.process_live_activity(&g).await;
 
-                        StatsService::update(&g.league, &game_uuid, Some(std::time::Duration::from_secs(30))).await;
-                        PlayerService::update(&g.league, &game_uuid, Some(std::time::Duration::from_secs(30))).await;
+                        refresher.tick(&g).await;
                     }
                 },
-                ApiSseMsg::Event(event) => {
+                ApiSseMsg::Event(mut event) => {
                     log::info!("[SSE] EVENT {event}");
-                    let new_event = EventService::store(&game_uuid, &event);
-                    if new_event && event.info.get_level() != ApiEventTypeLevel::Low {
+                    let new_event = EventService::store(&game_uuid, &mut event);
+                    if new_event {
+                        publish_service.publish(&event);
+                    }
+                    let notify = new_event && PublishService::is_publish_enabled(&game_uuid);
+                    if notify && (event.info.get_level() != ApiEventTypeLevel::Low || event.should_publish(&CONFIG.publish_policy)) {
                         if let Some(game) = api_season_service.read().await.read_current_season_game(&game_uuid) {
                             notification_service.process(&game, Some(&event)).await;
                         }
                     }
 
-                    _ = broadcast_sender.send(event.clone().into());
+                    _ = broadcast_sender.send((*event).clone().into());
                         // .ok_log("[SSE] Failed to broadcast event");
 
                     if let Some(g) = api_season_service.read().await.read_current_season_game(&game_uuid) {
-                        StatsService::update(&g.league, &game_uuid, Some(std::time::Duration::from_secs(30))).await;
-                        PlayerService::update(&g.league, &game_uuid, Some(std::time::Duration::from_secs(30))).await;
+                        refresher.tick(&g).await;
                     }
                     if new_event && matches!(event.info, ApiEventType::GameEnd(_)) {
                         let season_service = api_season_service.clone();
                         tokio::spawn(async move {
                             log::info!("[SSE] Game Ended, Updating in 5min");
                             tokio::time::sleep(Duration::from_secs(60 * 5)).await;
+                            EventService::repair(&game_uuid).await;
                             if let Some(g) = season_service.read().await.read_current_season_game(&game_uuid) {
-                                StatsService::update(&g.league, &game_uuid, Some(std::time::Duration::from_secs(30))).await;
-                                PlayerService::update(&g.league, &game_uuid, Some(std::time::Duration::from_secs(30))).await;
+                                GameService::finalize(&g).await;
                                 UserService::remove_references_to(&game_uuid);
                                 log::info!("[SSE] Updated after Game Ended");
                             }
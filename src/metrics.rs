@@ -0,0 +1,63 @@
+use std::{collections::{HashMap, BTreeMap}, sync::Mutex, time::Duration};
+
+use bounded_vec_deque::BoundedVecDeque;
+use lazy_static::lazy_static;
+use serde::Serialize;
+
+// Percentiles are over the latest samples only
+const LATENCY_SAMPLES: usize = 1000;
+
+lazy_static! {
+    static ref LATENCIES: Mutex<HashMap<String, BoundedVecDeque<Duration>>> = Mutex::new(HashMap::new());
+}
+
+#[derive(Serialize, Debug, Clone, PartialEq)]
+pub struct LatencySummary {
+    pub count: usize,
+    pub p50_ms: u64,
+    pub p95_ms: u64,
+}
+
+pub struct Metrics;
+impl Metrics {
+    pub fn record_latency(name: &str, duration: Duration) {
+        let mut latencies = LATENCIES.lock().unwrap_or_else(|e| e.into_inner());
+        latencies.entry(name.to_string())
+            .or_insert_with(|| BoundedVecDeque::new(LATENCY_SAMPLES))
+            .push_back(duration);
+    }
+
+    pub fn latencies() -> BTreeMap<String, LatencySummary> {
+        let latencies = LATENCIES.lock().unwrap_or_else(|e| e.into_inner());
+        latencies.iter()
+            .filter_map(|(name, samples)| Some((name.clone(), get_summary(samples)?)))
+            .collect()
+    }
+}
+
+// Nearest rank, so p95 of a few samples is a sample that was actually seen
+fn get_summary(samples: &BoundedVecDeque<Duration>) -> Option<LatencySummary> {
+    let mut sorted: Vec<Duration> = samples.iter().cloned().collect();
+    sorted.sort();
+    let percentile = |p: f64| {
+        let rank = ((p / 100.0) * sorted.len() as f64).ceil() as usize;
+        sorted.get(rank.saturating_sub(1)).map(|e| e.as_millis() as u64)
+    };
+    Some(LatencySummary { count: sorted.len(), p50_ms: percentile(50.0)?, p95_ms: percentile(95.0)? })
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use super::{Metrics, LatencySummary};
+
+    #[test]
+    fn latency_percentiles() {
+        assert_eq!(Metrics::latencies().get("test_latency_percentiles"), None);
+        for ms in 1..=100 {
+            Metrics::record_latency("test_latency_percentiles", Duration::from_millis(ms));
+        }
+        assert_eq!(Metrics::latencies().get("test_latency_percentiles"), Some(&LatencySummary { count: 100, p50_ms: 50, p95_ms: 95 }));
+    }
+}
@@ -0,0 +1,62 @@
+use std::{sync::{Arc, Mutex}, time::Duration};
+
+use tokio::{net::TcpListener, io::{AsyncReadExt, AsyncWriteExt}};
+
+// Serves a 200 with the given body, once per body
+pub async fn mock_server<B: AsRef<[u8]> + Send + 'static>(bodies: Vec<B>) -> String {
+    mock_server_with_status("200 OK", bodies).await
+}
+
+pub async fn mock_server_with_status<B: AsRef<[u8]> + Send + 'static>(status: &'static str, bodies: Vec<B>) -> String {
+    mock_server_with_responses(bodies.into_iter().map(|e| (status, "", e, Duration::ZERO)).collect()).await
+}
+
+// (status, extra header lines, body, delay before answering), one connection each
+pub async fn mock_server_with_responses<B: AsRef<[u8]> + Send + 'static>(responses: Vec<(&'static str, &'static str, B, Duration)>) -> String {
+    recording_mock_server(responses).await.0
+}
+
+// Like mock_server_with_responses, also recording the requested paths
+pub async fn recording_mock_server<B: AsRef<[u8]> + Send + 'static>(responses: Vec<(&'static str, &'static str, B, Duration)>) -> (String, Arc<Mutex<Vec<String>>>) {
+    let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let url = format!("http://{}", listener.local_addr().unwrap());
+    let paths = Arc::new(Mutex::new(vec![]));
+    let recorded = paths.clone();
+    tokio::spawn(async move {
+        for (status, headers, body, delay) in responses {
+            let (mut socket, _) = listener.accept().await.unwrap();
+            let mut buf = [0; 1024];
+            let n = socket.read(&mut buf).await.unwrap_or_default();
+            let request = String::from_utf8_lossy(&buf[..n]).to_string();
+            recorded.lock().unwrap().push(request.split_whitespace().nth(1).unwrap_or_default().to_string());
+            tokio::time::sleep(delay).await;
+            let body = body.as_ref();
+            let header = format!("HTTP/1.1 {status}\r\n{headers}Content-Type: application/json\r\nContent-Length: {}\r\nConnection: close\r\n\r\n", body.len());
+            _ = socket.write_all(&[header.as_bytes(), body].concat()).await;
+        }
+    });
+    (url, paths)
+}
+
+// Answers each connection with the first unused (path fragment, status, body) its path contains, for calls made concurrently
+pub async fn routed_mock_server<B: AsRef<[u8]> + Send + 'static>(mut routes: Vec<(&'static str, &'static str, B)>) -> String {
+    let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let url = format!("http://{}", listener.local_addr().unwrap());
+    tokio::spawn(async move {
+        while !routes.is_empty() {
+            let (mut socket, _) = listener.accept().await.unwrap();
+            let mut buf = [0; 1024];
+            let n = socket.read(&mut buf).await.unwrap_or_default();
+            let request = String::from_utf8_lossy(&buf[..n]).to_string();
+            let path = request.split_whitespace().nth(1).unwrap_or_default().to_string();
+            let Some(i) = routes.iter().position(|e| path.contains(e.0)) else {
+                continue;
+            };
+            let (_, status, body) = routes.remove(i);
+            let body = body.as_ref();
+            let header = format!("HTTP/1.1 {status}\r\nContent-Type: application/json\r\nContent-Length: {}\r\nConnection: close\r\n\r\n", body.len());
+            _ = socket.write_all(&[header.as_bytes(), body].concat()).await;
+        }
+    });
+    url
+}
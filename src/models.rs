@@ -1,3 +1,5 @@
+use chrono::{DateTime, Utc, FixedOffset, NaiveDate, Datelike};
+use chrono_tz::{Europe::Stockholm, Tz};
 use serde::{Deserialize, Serialize};
 use std::fmt::Display;
 use std::str::FromStr;
@@ -59,6 +61,11 @@ impl Season {
     pub fn get_all() -> Vec<Season> {
         vec![Season::Season2018, Season::Season2019, Season::Season2020, Season::Season2021, Season::Season2022, Season::Season2023]
     }
+    // A season starts in the autumn of its year and ends the next spring
+    pub fn containing(date: &NaiveDate) -> Option<Season> {
+        let year = if date.month() >= 7 { date.year() } else { date.year() - 1 };
+        year.to_string().parse().ok()
+    }
 }
 impl FromStr for Season {
     type Err = ParseStringError;
@@ -104,7 +111,7 @@ impl StringOrNum {
         match self {
             StringOrNum::String(str) => str.parse::<i16>().unwrap_or(0),
             StringOrNum::Number(n) => *n,
-            StringOrNum::Arr(a) => a.get(0).map(|e| e.parse::<i16>().ok()).unwrap_or(None).unwrap_or(0),
+            StringOrNum::Arr(a) => a.first().map(|e| e.parse::<i16>().ok()).unwrap_or(None).unwrap_or(0),
         }
     }
 
@@ -125,3 +132,144 @@ impl std::fmt::Display for SeasonKey {
         write!(f, "{:?}/{:?}/{:?}", self.0, self.1, self.2)
     }
 }
+
+// Upstream times are Swedish local time, everything is stored as UTC
+pub fn to_stockholm_time(date_time: &DateTime<Utc>) -> DateTime<Tz> {
+    date_time.with_timezone(&Stockholm)
+}
+
+pub fn to_stockholm_fixed_offset(date_time: &DateTime<Utc>) -> DateTime<FixedOffset> {
+    to_stockholm_time(date_time).fixed_offset()
+}
+
+pub const SHOOTOUT_PERIOD: i16 = 99;
+
+// Feed period number: 1-3, then 4 and up for each overtime, 99 for the shootout.
+// Regulation(0) is before the game, or an event not from the feed
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+#[serde(from = "i16", into = "i16")]
+pub enum GamePeriod {
+    Regulation(u8),
+    Overtime(u8),
+    Shootout,
+}
+
+impl Default for GamePeriod {
+    fn default() -> Self {
+        GamePeriod::Regulation(0)
+    }
+}
+
+impl From<i16> for GamePeriod {
+    fn from(value: i16) -> Self {
+        match value {
+            SHOOTOUT_PERIOD => GamePeriod::Shootout,
+            1..=3 => GamePeriod::Regulation(value as u8),
+            4..=98 => GamePeriod::Overtime((value - 3) as u8),
+            _ => GamePeriod::Regulation(0),
+        }
+    }
+}
+
+impl From<GamePeriod> for i16 {
+    fn from(value: GamePeriod) -> Self {
+        match value {
+            GamePeriod::Regulation(n) => n as i16,
+            GamePeriod::Overtime(n) => n as i16 + 3,
+            GamePeriod::Shootout => SHOOTOUT_PERIOD,
+        }
+    }
+}
+
+// P1-P3, then OT, 2OT, ... for playoff games going past the first overtime
+pub fn get_period_label(period: GamePeriod) -> String {
+    match period {
+        GamePeriod::Regulation(0) => "".to_string(),
+        GamePeriod::Regulation(n) => format!("P{n}"),
+        GamePeriod::Overtime(1) => "OT".to_string(),
+        GamePeriod::Overtime(n) => format!("{n}OT"),
+        GamePeriod::Shootout => "SO".to_string(),
+    }
+}
+
+// Endpoints don't always agree on case or code for the same team. Only codes known to name the same
+// team go here, FBK (Färjestad) and FHC (Frölunda) are two teams
+const TEAM_CODE_ALIASES: [(&str, &str); 0] = [];
+
+pub fn normalize_team_code(code: &str) -> String {
+    let code = code.trim().to_uppercase();
+    TEAM_CODE_ALIASES.iter()
+        .find(|(alias, _)| *alias == code)
+        .map(|(_, canonical)| canonical.to_string())
+        .unwrap_or(code)
+}
+
+#[cfg(test)]
+mod tests {
+    use chrono::{DateTime, Utc, TimeZone, NaiveDate};
+
+    use super::{to_stockholm_time, to_stockholm_fixed_offset, normalize_team_code, get_period_label, GamePeriod, Season};
+
+    #[test]
+    fn game_period_conversions() {
+        assert_eq!(GamePeriod::from(1), GamePeriod::Regulation(1));
+        assert_eq!(GamePeriod::from(4), GamePeriod::Overtime(1));
+        assert_eq!(GamePeriod::from(99), GamePeriod::Shootout);
+        for period in [0, 1, 3, 4, 5, 99] {
+            assert_eq!(i16::from(GamePeriod::from(period)), period);
+        }
+        assert_eq!(serde_json::to_string(&GamePeriod::Overtime(2)).unwrap(), "5");
+        assert_eq!(serde_json::from_str::<GamePeriod>("99").unwrap(), GamePeriod::Shootout);
+        assert!(GamePeriod::Regulation(3) < GamePeriod::Overtime(1));
+        assert!(GamePeriod::Overtime(2) < GamePeriod::Shootout);
+    }
+
+    #[test]
+    fn period_labels() {
+        assert_eq!(get_period_label(1.into()), "P1");
+        assert_eq!(get_period_label(3.into()), "P3");
+        assert_eq!(get_period_label(4.into()), "OT");
+        assert_eq!(get_period_label(5.into()), "2OT");
+        assert_eq!(get_period_label(99.into()), "SO");
+    }
+
+    #[test]
+    fn season_containing_date() {
+        let date = |s: &str| NaiveDate::parse_from_str(s, "%Y-%m-%d").unwrap();
+        assert_eq!(Season::containing(&date("2022-09-17")), Some(Season::Season2022));
+        assert_eq!(Season::containing(&date("2023-03-01")), Some(Season::Season2022));
+        assert_eq!(Season::containing(&date("2023-08-01")), Some(Season::Season2023));
+        assert_eq!(Season::containing(&date("2018-03-01")), None);
+    }
+
+    #[test]
+    fn normalize_team_codes() {
+        assert_eq!(normalize_team_code("lhf"), "LHF");
+        assert_eq!(normalize_team_code(" Modo "), "MODO");
+        assert_eq!(normalize_team_code("FHC"), "FHC");
+        assert_eq!(normalize_team_code("fbk"), "FBK");
+    }
+
+    #[test]
+    fn stockholm_time_across_autumn_dst() {
+        let before: DateTime<Utc> = "2022-10-29T16:00:00Z".parse().unwrap();
+        let after: DateTime<Utc> = "2022-10-30T16:00:00Z".parse().unwrap();
+        assert_eq!(to_stockholm_time(&before).to_rfc3339(), "2022-10-29T18:00:00+02:00");
+        assert_eq!(to_stockholm_time(&after).to_rfc3339(), "2022-10-30T17:00:00+01:00");
+    }
+
+    #[test]
+    fn stockholm_time_across_spring_dst() {
+        let before = Utc.with_ymd_and_hms(2023, 3, 25, 18, 0, 0).unwrap();
+        let after = Utc.with_ymd_and_hms(2023, 3, 26, 18, 0, 0).unwrap();
+        assert_eq!(to_stockholm_fixed_offset(&before).to_rfc3339(), "2023-03-25T19:00:00+01:00");
+        assert_eq!(to_stockholm_fixed_offset(&after).to_rfc3339(), "2023-03-26T20:00:00+02:00");
+    }
+
+    #[test]
+    fn local_input_is_stored_as_utc() {
+        let parsed: DateTime<Utc> = serde_json::from_str("\"2023-03-26T19:00:00+02:00\"").unwrap();
+        assert_eq!(parsed, Utc.with_ymd_and_hms(2023, 3, 26, 17, 0, 0).unwrap());
+        assert_eq!(to_stockholm_fixed_offset(&parsed).to_rfc3339(), "2023-03-26T19:00:00+02:00");
+    }
+}
@@ -1,6 +1,6 @@
 use serde::{Serialize, Deserialize};
 
-use crate::{models::StringOrNum, game_report_service::GameStatus};
+use crate::{models::{StringOrNum, SHOOTOUT_PERIOD}, game_report_service::GameStatus};
 
 
 
@@ -28,6 +28,8 @@ impl GameReport {
             "PeriodBreak" => GameStatus::Intermission,
             "ShootOut" => GameStatus::Shootout,
             "OverTime" => GameStatus::Overtime,
+            "Postponed" => GameStatus::Postponed,
+            "Cancelled" | "Canceled" => GameStatus::Cancelled,
             "Ongoing" => self.period.to_num().into(),
             _ => GameStatus::Coming,
         }
@@ -41,7 +43,7 @@ impl From<i16> for GameStatus {
             2 => GameStatus::Period2,
             3 => GameStatus::Period3,
             4..=10 => GameStatus::Overtime,
-            99 => GameStatus::Shootout,
+            SHOOTOUT_PERIOD => GameStatus::Shootout,
             _ => GameStatus::Period1,
         }
     }
@@ -73,6 +75,12 @@ pub struct GoalExtra {
     pub homeAgainst: StringOrNum,
     pub homeForward: StringOrNum,
     pub assist: String,
+    #[serde(default, alias = "videoUrl", alias = "clipUrl")]
+    pub mediaUrl: Option<String>,
+    #[serde(default, alias = "playerId", alias = "scorerPlayerId")]
+    pub scorerId: Option<serde_json::Value>,
+    #[serde(default, alias = "assistPlayerIds")]
+    pub assistIds: Vec<serde_json::Value>,
 }
 
 #[derive(Serialize, Deserialize, Debug, Clone)]
@@ -97,6 +105,13 @@ pub struct Period {
 #[derive(Serialize, Deserialize, Debug, Clone)]
 pub struct Penalty {
     pub team: String,
+    #[serde(default)]
+    pub playerId: Option<serde_json::Value>,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct Hit {
+    pub team: String,
 }
 
 
@@ -126,6 +141,7 @@ pub enum PlayByPlayType {
     ShotIron(Shot),
     ShotWide(Shot),
     ShootoutPenaltyShot(Shot),
+    Hit(Hit),
 
     General(General),
     Timeout(General),
@@ -63,6 +63,8 @@ use crate::models::StringOrNum;
 pub struct PlayerName {
     pub firstName: String,
     pub lastName: String,
+    #[serde(default, alias = "image", alias = "portraitUrl", skip_serializing_if = "Option::is_none")]
+    pub imageUrl: Option<String>,
 }
 
  #[derive(Serialize, Deserialize, Debug, Clone)]
@@ -81,14 +81,16 @@ impl ApiEventType {
     fn to_str(&self) -> &str {
         match self {
             ApiEventType::Goal(_) => "Goal",
-            ApiEventType::PeriodStart => "PeriodStart",
+            ApiEventType::PeriodStart | ApiEventType::OvertimeStart => "PeriodStart",
             ApiEventType::PeriodEnd => "PeriodEnd",
             ApiEventType::Penalty(_) => "Penalty",
             ApiEventType::Shot(_) => "Shot",
+            ApiEventType::Hit(_) => "Hit",
             ApiEventType::GameStart => "GameStart",
             ApiEventType::GameEnd(_) => "GameEnd",
             ApiEventType::Timeout => "Timeout",
-            ApiEventType::General => "General",
+            // legacy clients only know the original types
+            ApiEventType::Review | ApiEventType::Challenge | ApiEventType::General => "General",
         }
     }
 }
@@ -119,7 +121,7 @@ impl From<(ApiGameEvent, ApiGameDetails)> for LegacyGameEvent {
                     GameStatus::Period1 => 1,
                     GameStatus::Period2 => 2,
                     GameStatus::Period3 => 3,
-                    GameStatus::Overtime => 4,
+                    GameStatus::Overtime => i16::from(event.period).max(4),
                     GameStatus::Shootout => 99,
                     _ => 1,
                 },
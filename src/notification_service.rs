@@ -4,7 +4,7 @@ use chrono::{Utc, Duration};
 use futures::{future::join_all, FutureExt};
 use tracing::log;
 
-use crate::{event_service::{ApiGameEvent, ApiEventType, ApiEventTypeLevel, EventService}, api_season_service::ApiGame, user_service::{UserService, User}, apn_client::{ApnClient, ApnPush, ApnAlert, ApnBody, ApnHeader, ApnAps, LiveActivityContentState, ApnPushType, ApnError, LiveActivityReport, LiveActivityEvent}, CONFIG, api_teams_service::{TeamsMap}, game_report_service::GameStatus};
+use crate::{event_service::{ApiGameEvent, ApiEventType, ApiEventTypeLevel, EventService, PublishPolicy}, api_season_service::ApiGame, user_service::{UserService, User}, apn_client::{ApnClient, ApnPush, ApnAlert, ApnBody, ApnHeader, ApnAps, LiveActivityContentState, ApnPushType, ApnError, LiveActivityReport, LiveActivityEvent}, CONFIG, api_teams_service::{TeamsMap}, game_report_service::GameStatus, models::GamePeriod};
 
 impl ApiGameEvent {
     fn get_time_info(&self) -> String {
@@ -12,7 +12,10 @@ impl ApiGameEvent {
             GameStatus::Period1 => format!("P1 {}", self.gametime),
             GameStatus::Period2 => format!("P2 {}", self.gametime),
             GameStatus::Period3 => format!("P3 {}", self.gametime),
-            GameStatus::Overtime => format!("Övertid {}", self.gametime),
+            GameStatus::Overtime => match self.period {
+                GamePeriod::Overtime(n) if n > 1 => format!("Övertid {n} {}", self.gametime),
+                _ => format!("Övertid {}", self.gametime),
+            },
             GameStatus::Shootout => "Straffar".to_string(),
             _ => "".to_string(),
         }
@@ -134,6 +137,7 @@ impl User {
 pub struct NotificationService {
     apn_client: ApnClient,
     teams: TeamsMap,
+    publish_policy: PublishPolicy,
 }
 
 impl NotificationService {
@@ -141,6 +145,7 @@ impl NotificationService {
         NotificationService { 
             apn_client: ApnClient::new(), 
             teams: TeamsMap::new(),
+            publish_policy: CONFIG.publish_policy.clone(),
         }
     }
 
@@ -172,8 +177,7 @@ impl NotificationService {
     pub async fn process_live_activity(&mut self, game: &ApiGame) {
         let events = EventService::read(&game.game_uuid.clone());
         let event = events.iter()
-            .filter(|e| e.info.get_level() != ApiEventTypeLevel::Low)
-            .last();
+            .rfind(|e| e.info.get_level() != ApiEventTypeLevel::Low);
         let before = Instant::now();
         self.apn_client.update_token();
         let mut futures = vec!();
@@ -203,8 +207,8 @@ impl NotificationService {
     fn get_apn_push(&self, user: &User, game: &ApiGame, event: Option<&ApiGameEvent>, should_alert: bool) -> Option<(String, ApnPush<Option<LiveActivityContentState>, ApiGame>)> {
         let now = Utc::now().timestamp();
         let expiration = (Utc::now() + Duration::hours(1)).timestamp();
-        let event_is_level_high = event.as_ref().map(|e| e.info.get_level() == ApiEventTypeLevel::High).unwrap_or(false);
-        let alert = match (event_is_level_high, should_alert) {
+        let event_is_published = event.as_ref().map(|e| e.should_publish(&self.publish_policy)).unwrap_or(false);
+        let alert = match (event_is_published, should_alert) {
             (true, true) => Some(ApnAlert::from(game, event.unwrap(), &self.teams, &user.teams)),
             (_, _) => None,
         };
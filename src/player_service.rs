@@ -1,9 +1,15 @@
-use std::time::Duration;
+use std::{time::SystemTime, collections::{HashMap, HashSet}, sync::RwLock};
+
+use lazy_static::lazy_static;
 
 use serde::{Serialize, Deserialize};
+use tracing::{log, Instrument};
+
+use crate::{models::{League, Season, normalize_team_code}, rest_client::{self, ThrottlePolicy}, models2::external::{player::{PlayerStatsRsp, PlayerName, PlayerStats, GoalkeeperStats, EachTeamStats}, self}, db::Db, api_season_service::ApiSeasonService, service_error::ServiceError, event_service::EventService, api_player_stats_service::{ApiPlayerStatsService, TeamSeasonKey}, CONFIG};
 
-use crate::{models::{League, Season}, rest_client, models2::external::{player::{PlayerStatsRsp, PlayerName}, self}, db::Db};
 
+// bump on breaking changes to the stored ApiAthlete shape
+pub const ATHLETE_SCHEMA_VERSION: u32 = 1;
 
 #[derive(Serialize, Deserialize, Clone)]
 pub struct ApiAthlete {
@@ -14,6 +20,8 @@ pub struct ApiAthlete {
     pub team_code: String,
     pub position: String,
     pub season: Season,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub image_url: Option<String>,
     #[serde(flatten)]
     pub stats: ApiAthleteStats,
 }
@@ -39,6 +47,9 @@ pub struct ApiPlayerStats {
     pub sw: i32,
     pub toi_s: i32,
     pub gp: i32,
+    // game winning goals, from the events. The boxscore doesn't have them
+    #[serde(default)]
+    pub gwg: i32,
 }
 
 #[derive(Serialize, Deserialize, Default, Clone)]
@@ -50,6 +61,57 @@ pub struct ApiGoalkeeperStats {
     pub gp: i32,
 }
 
+#[derive(Deserialize, Debug, Clone, Copy, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum StatKey {
+    Goals,
+    Assists,
+    Points,
+    PlusMinus,
+    Pim,
+    Sog,
+    Hits,
+    Gwg,
+    Saves,
+    SvsPct,
+    GoalsAgainst,
+}
+
+impl StatKey {
+    // None when the athlete doesn't have the stat, e.g. goals for a goalkeeper
+    fn get_value(&self, stats: &ApiAthleteStats) -> Option<f32> {
+        match (self, stats) {
+            (StatKey::Goals, ApiAthleteStats::Player(p)) => Some(p.g as f32),
+            (StatKey::Assists, ApiAthleteStats::Player(p)) => Some(p.a as f32),
+            (StatKey::Points, ApiAthleteStats::Player(p)) => Some((p.g + p.a) as f32),
+            (StatKey::PlusMinus, ApiAthleteStats::Player(p)) => Some(p.plus_minus as f32),
+            (StatKey::Pim, ApiAthleteStats::Player(p)) => Some(p.pim as f32),
+            (StatKey::Sog, ApiAthleteStats::Player(p)) => Some(p.sog as f32),
+            (StatKey::Hits, ApiAthleteStats::Player(p)) => Some(p.hits as f32),
+            (StatKey::Gwg, ApiAthleteStats::Player(p)) => Some(p.gwg as f32),
+            (StatKey::Saves, ApiAthleteStats::Goalkeeper(g)) => Some(g.svs as f32),
+            // no shots faced, no percentage
+            (StatKey::SvsPct, ApiAthleteStats::Goalkeeper(g)) if g.svs + g.ga > 0 => Some(g.svs as f32 / (g.svs + g.ga) as f32),
+            // fewest is best, so a goalkeeper who hasn't played would lead
+            (StatKey::GoalsAgainst, ApiAthleteStats::Goalkeeper(g)) if g.gp > 0 => Some(g.ga as f32),
+            _ => None,
+        }
+    }
+
+    // fewer is better
+    fn is_ascending(&self) -> bool {
+        matches!(self, StatKey::GoalsAgainst)
+    }
+}
+
+// The feed's image when there is one, else built from the configured url, if any
+fn get_image_url(id: i32, provided: Option<String>) -> Option<String> {
+    provided
+        .map(|e| e.trim().to_string())
+        .filter(|e| !e.is_empty())
+        .or_else(|| CONFIG.player_image_url.as_ref().map(|e| e.replace("{id}", &id.to_string())))
+}
+
 impl From<(PlayerName, external::player::GoalkeeperStats)> for ApiAthlete {
     fn from(value: (PlayerName, external::player::GoalkeeperStats)) -> Self {
         let name = value.0;
@@ -62,21 +124,31 @@ impl From<(PlayerName, external::player::GoalkeeperStats)> for ApiAthlete {
             gp: match gk.SVS > 0 { true => 1, false => 0 },
         };
         ApiAthlete { id: gk.info.playerId, 
+            image_url: get_image_url(gk.info.playerId, name.imageUrl),
             first_name: name.firstName,
             family_name: name.lastName,
             jersey: gk.NR,
             season: Season::Season2022,
-            team_code: gk.info.teamId,
+            team_code: normalize_team_code(&gk.info.teamId),
             position: "GK".to_string(),
             stats: ApiAthleteStats::Goalkeeper(stats)
         }
     }
 }
-fn parse_toi(s: &str) -> i32 {
-    let (min_str, secs_str) = s.split_once(':').unwrap_or(("0", "0"));
-    let min: i32 = min_str.parse().ok().unwrap_or_default();
-    let secs: i32 = secs_str.parse().ok().unwrap_or_default();
-    min * 60 + secs
+// "MM:SS" or "H:MM:SS". Minutes may go past 59 when there are no hours
+fn parse_toi(s: &str) -> Option<i32> {
+    let s = s.trim();
+    if s.is_empty() {
+        return Some(0);
+    }
+    let parts: Vec<i32> = s.split(':')
+        .map(|e| if !e.is_empty() && e.chars().all(|c| c.is_ascii_digit()) { e.parse().ok() } else { None })
+        .collect::<Option<Vec<i32>>>()?;
+    match parts[..] {
+        [min, secs] if secs < 60 => Some(min * 60 + secs),
+        [hours, min, secs] if min < 60 && secs < 60 => Some(hours * 3600 + min * 60 + secs),
+        _ => None,
+    }
 }
 impl From<(PlayerName, external::player::PlayerStats)> for ApiAthlete {
     fn from(value: (PlayerName, external::player::PlayerStats)) -> Self {
@@ -92,16 +164,21 @@ impl From<(PlayerName, external::player::PlayerStats)> for ApiAthlete {
             pim: p.PIM,
             sog: p.SOG,
             sw: p.SW,
-            toi_s: parse_toi(&p.TOI),
+            toi_s: parse_toi(&p.TOI).unwrap_or_else(|| {
+                log::warn!("[PLAYER] Invalid TOI {} for {}", p.TOI, p.info.playerId);
+                0
+            }),
             gp: 1,
+            gwg: 0,
         };
         ApiAthlete { 
             id: p.info.playerId,
+            image_url: get_image_url(p.info.playerId, name.imageUrl),
             first_name: name.firstName,
             family_name: name.lastName,
             jersey: p.NR,
             season: Season::Season2022,
-            team_code: p.info.teamId,
+            team_code: normalize_team_code(&p.info.teamId),
             position: p.POS.to_str(),
             stats: ApiAthleteStats::Player(stats), 
         }
@@ -109,47 +186,433 @@ impl From<(PlayerName, external::player::PlayerStats)> for ApiAthlete {
 }
 impl From<PlayerStatsRsp> for Vec<ApiAthlete> {
     fn from(v: PlayerStatsRsp) -> Self {
-        let gks = [v.gkStats.homeTeamValue, v.gkStats.awayTeamValue].concat();
-        let mut gk_map = v.goalkeepers.homeTeamValue.clone();
-        gk_map.extend(v.goalkeepers.awayTeamValue);
+        for (kind, names) in [("players", &v.players), ("goalkeepers", &v.goalkeepers)] {
+            let collisions = get_collisions(&names.homeTeamValue, &names.awayTeamValue);
+            if !collisions.is_empty() {
+                log::warn!("[PLAYER] {kind} {:?} named for both teams, using each team's own", collisions);
+            }
+        }
+        let goalkeepers = [
+            (v.gkStats.homeTeamValue, &v.goalkeepers.homeTeamValue, &v.goalkeepers.awayTeamValue),
+            (v.gkStats.awayTeamValue, &v.goalkeepers.awayTeamValue, &v.goalkeepers.homeTeamValue),
+        ].into_iter().flat_map(|(gks, own, other)| gks.into_iter().filter_map(|gk| {
+            let id = gk.info.playerId;
+            get_name(id, own, other).map(|name| (name, gk).into())
+        }).collect::<Vec<ApiAthlete>>());
 
-        let goalkeepers: Vec<ApiAthlete> = gks.into_iter().map(|gk| {
-            let gk_info = gk_map.get(&gk.info.playerId).cloned().unwrap_or_default();
-            (gk_info, gk).into()
-        }).collect();
+        let players = [
+            (v.stats.homeTeamValue, &v.players.homeTeamValue, &v.players.awayTeamValue),
+            (v.stats.awayTeamValue, &v.players.awayTeamValue, &v.players.homeTeamValue),
+        ].into_iter().flat_map(|(ps, own, other)| ps.into_iter().filter_map(|p| {
+            let id = p.info.playerId;
+            get_name(id, own, other).map(|name| (name, p).into())
+        }).collect::<Vec<ApiAthlete>>());
+
+        players.chain(goalkeepers).collect()
+    }
+}
 
-        let ps = [v.stats.homeTeamValue, v.stats.awayTeamValue].concat();
-        let mut player_map = v.players.homeTeamValue;
-        player_map.extend(v.players.awayTeamValue);
+// The boxscore counts shootout goals in G. They are not goals for the scorer, the decisive one only counts for the team
+fn without_shootout_goals(game_uuid: &str, mut athletes: Vec<ApiAthlete>) -> Vec<ApiAthlete> {
+    let shootout_goals = EventService::shootout_goals(game_uuid);
+    if shootout_goals.is_empty() {
+        return athletes;
+    }
+    for e in athletes.iter_mut() {
+        if let ApiAthleteStats::Player(p) = &mut e.stats {
+            let scored = shootout_goals.iter().filter(|g| g.player_id == Some(e.id) && g.team == e.team_code).count() as i32;
+            p.g = (p.g - scored).max(0);
+        }
+    }
+    athletes
+}
 
-        let players: Vec<ApiAthlete> = ps.into_iter().map(|p| {
-            let p_info = player_map.get(&p.info.playerId).cloned().unwrap_or_default();
-            (p_info, p).into()
-        }).collect();
+// Ids named differently for both teams, a feed glitch
+fn get_collisions(home: &HashMap<i32, PlayerName>, away: &HashMap<i32, PlayerName>) -> Vec<i32> {
+    let mut ids: Vec<i32> = home.iter()
+        .filter(|(id, name)| away.get(id).is_some_and(|e| e.firstName != name.firstName || e.lastName != name.lastName))
+        .map(|(id, _)| *id)
+        .collect();
+    ids.sort();
+    ids
+}
 
-        [players, goalkeepers].concat()
+// Own team's names first, so an id present for both teams isn't mixed up. Unnamed athletes are dropped
+fn get_name(id: i32, own: &HashMap<i32, PlayerName>, other: &HashMap<i32, PlayerName>) -> Option<PlayerName> {
+    let name = own.get(&id).or_else(|| other.get(&id)).cloned();
+    if name.is_none() {
+        log::warn!("[PLAYER] No name for {id}, dropping");
     }
+    name
+}
+
+lazy_static! {
+    // league and season of each game a roster was looked up for
+    static ref GAME_SEASONS: RwLock<HashMap<String, (League, Season)>> = RwLock::new(HashMap::new());
 }
 
 pub struct PlayerService;
 impl PlayerService {
+    // The cached players of a game, found without knowing its league and season. Empty for unknown games
+    pub fn roster(game_uuid: &str) -> Vec<ApiAthlete> {
+        let cached = GAME_SEASONS.read().unwrap_or_else(|e| e.into_inner()).get(game_uuid).cloned();
+        let found = cached.or_else(|| {
+            let game = ApiSeasonService::read(&Season::get_current()).into_iter().find(|e| e.game_uuid == game_uuid)
+                .or_else(|| ApiSeasonService::read_all().into_iter().find(|e| e.game_uuid == game_uuid))?;
+            let found = (game.league, game.season);
+            GAME_SEASONS.write().unwrap_or_else(|e| e.into_inner()).insert(game_uuid.to_string(), found.clone());
+            Some(found)
+        });
+        found.and_then(|(league, season)| PlayerService::read(&league, &season, game_uuid)).unwrap_or_default()
+    }
 
-    pub async fn update(league: &League, game_uuid: &str, throttle_s: Option<Duration>) -> Vec<ApiAthlete> {
+    pub async fn update(league: &League, season: &Season, game_uuid: &str, throttle: ThrottlePolicy) -> Result<Vec<ApiAthlete>, ServiceError> {
         let url = rest_client::get_player_stats_url(league, game_uuid);
-        let rsp: Option<PlayerStatsRsp> = rest_client::throttle_call(&url, throttle_s).await;
-        rsp.map(|e| e.into()).unwrap_or_default()
+        let key = rest_client::get_player_stats_key(league, season, game_uuid);
+        let merge = |rsp: PlayerStatsRsp| match Db::<String, PlayerStatsRsp>::new("rest").read(&key) {
+            Some(cached) => merge_player_stats(cached, rsp),
+            None => rsp,
+        };
+        let rsp: Result<PlayerStatsRsp, ServiceError> = rest_client::throttle_call_merged(&url, &key, throttle, merge)
+            .instrument(tracing::info_span!("fetch", service = "players", game_uuid, league = ?league))
+            .await;
+        tracing::info_span!("map", service = "players", game_uuid, league = ?league)
+            .in_scope(|| rsp.map(|e| without_shootout_goals(game_uuid, e.into())))
     }
 
-    pub fn read(league: &League, game_uuid: &str) -> Option<Vec<ApiAthlete>> {
+    pub fn read(league: &League, season: &Season, game_uuid: &str) -> Option<Vec<ApiAthlete>> {
         let db = Db::<String, PlayerStatsRsp>::new("rest");
-        let url = rest_client::get_player_stats_url(league, game_uuid);
-        let rsp: Option<PlayerStatsRsp> = db.read(&url);
-        rsp.map(|e| e.into())
+        let key = rest_client::get_player_stats_key(league, season, game_uuid);
+        let rsp: Option<PlayerStatsRsp> = db.read(&key);
+        rsp.map(|e| without_shootout_goals(game_uuid, e.into()))
     }
 
-    pub fn is_stale(league: &League, game_uuid: &str) -> bool {
-        let url = rest_client::get_player_stats_url(league, game_uuid);
+    pub fn is_stale(league: &League, season: &Season, game_uuid: &str) -> bool {
+        let key = rest_client::get_player_stats_key(league, season, game_uuid);
         let db = Db::<String, PlayerStatsRsp>::new("rest");
-        db.is_stale(&url, None)
+        db.is_stale(&key, None)
+    }
+
+    pub fn last_updated(league: &League, season: &Season, game_uuid: &str) -> Option<SystemTime> {
+        let key = rest_client::get_player_stats_key(league, season, game_uuid);
+        Db::<String, PlayerStatsRsp>::new("rest").last_updated(&key).max(rest_client::last_fetched(&key))
+    }
+
+    pub fn mark_final(league: &League, season: &Season, game_uuid: &str) {
+        let key = rest_client::get_player_stats_key(league, season, game_uuid);
+        _ = Db::<String, PlayerStatsRsp>::new("rest").mark_final(&key);
+    }
+
+    pub fn leaderboard(league: &League, season: &Season, stat: StatKey, limit: usize) -> Vec<ApiAthlete> {
+        let teams: HashSet<String> = ApiSeasonService::read(season).into_iter()
+            .filter(|e| &e.league == league)
+            .flat_map(|e| [e.home_team_code, e.away_team_code])
+            .collect();
+        let db = ApiPlayerStatsService::get_team_player_db();
+        let athletes = teams.into_iter()
+            .flat_map(|team| db.read(&TeamSeasonKey(season.clone(), team)).unwrap_or_default())
+            .collect();
+        get_leaderboard(athletes, stat, limit)
+    }
+
+    pub fn game_log(player_id: i32, season: &Season) -> Vec<(String, ApiAthlete)> {
+        let mut games = ApiSeasonService::read(season);
+        games.sort_by_key(|e| e.start_date_time);
+        games.iter()
+            .filter_map(|g| PlayerService::read(&g.league, &g.season, &g.game_uuid)
+                .and_then(|players| players.into_iter().find(|p| p.id == player_id))
+                .map(|p| (g.game_uuid.clone(), p)))
+            .collect()
+    }
+}
+
+// A partial fetch only overwrites the players in it, players missing from it keep their cached stats
+fn merge_player_stats(cached: PlayerStatsRsp, rsp: PlayerStatsRsp) -> PlayerStatsRsp {
+    let stats_key = |e: &PlayerStats| (e.info.playerId, e.info.period);
+    let gk_stats_key = |e: &GoalkeeperStats| (e.info.playerId, e.info.period);
+    PlayerStatsRsp {
+        dataColumns: if rsp.dataColumns.is_empty() { cached.dataColumns } else { rsp.dataColumns },
+        gkDataColumns: if rsp.gkDataColumns.is_empty() { cached.gkDataColumns } else { rsp.gkDataColumns },
+        gkStats: EachTeamStats {
+            homeTeamValue: merge_rows(cached.gkStats.homeTeamValue, rsp.gkStats.homeTeamValue, gk_stats_key),
+            awayTeamValue: merge_rows(cached.gkStats.awayTeamValue, rsp.gkStats.awayTeamValue, gk_stats_key),
+        },
+        stats: EachTeamStats {
+            homeTeamValue: merge_rows(cached.stats.homeTeamValue, rsp.stats.homeTeamValue, stats_key),
+            awayTeamValue: merge_rows(cached.stats.awayTeamValue, rsp.stats.awayTeamValue, stats_key),
+        },
+        goalkeepers: EachTeamStats {
+            homeTeamValue: cached.goalkeepers.homeTeamValue.into_iter().chain(rsp.goalkeepers.homeTeamValue).collect(),
+            awayTeamValue: cached.goalkeepers.awayTeamValue.into_iter().chain(rsp.goalkeepers.awayTeamValue).collect(),
+        },
+        players: EachTeamStats {
+            homeTeamValue: cached.players.homeTeamValue.into_iter().chain(rsp.players.homeTeamValue).collect(),
+            awayTeamValue: cached.players.awayTeamValue.into_iter().chain(rsp.players.awayTeamValue).collect(),
+        },
+    }
+}
+
+// Rows are per player and period
+fn merge_rows<T>(cached: Vec<T>, mut rows: Vec<T>, key: impl Fn(&T) -> (i32, i32)) -> Vec<T> {
+    let fetched: HashSet<(i32, i32)> = rows.iter().map(&key).collect();
+    rows.extend(cached.into_iter().filter(|e| !fetched.contains(&key(e))));
+    rows
+}
+
+// Ties keep a stable order by id
+fn get_leaderboard(athletes: Vec<ApiAthlete>, stat: StatKey, limit: usize) -> Vec<ApiAthlete> {
+    let mut ranked: Vec<(f32, ApiAthlete)> = athletes.into_iter()
+        .filter_map(|e| stat.get_value(&e.stats).map(|value| (value, e)))
+        .collect();
+    ranked.sort_by(|a, b| {
+        let order = b.0.total_cmp(&a.0);
+        let order = if stat.is_ascending() { order.reverse() } else { order };
+        order.then_with(|| a.1.id.cmp(&b.1.id))
+    });
+    ranked.into_iter().take(limit).map(|e| e.1).collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashMap;
+
+    use chrono::{Utc, Duration};
+    use tempdir::TempDir;
+
+    use crate::{api_season_service::ApiGame, db::Db, models::{Season, League, GameType, StringOrNum}, models2::external::player::{PlayerStatsRsp, EachTeamStats, PlayerStats, PlayerInfo, PlayerName, GoalkeeperStats}, rest_client::{self, ThrottlePolicy}, game_report_service::GameStatus, player_service::{ApiAthleteStats, ApiAthlete}, mock_server::mock_server, event_service::EventService, models2::external::event::PlayByPlay};
+
+    use super::{PlayerService, parse_toi, get_collisions, get_leaderboard, StatKey, ApiPlayerStats, ApiGoalkeeperStats};
+
+    #[test]
+    fn leaderboard_by_points() {
+        let player = |id: i32, g: i32, a: i32| get_athlete(id, ApiAthleteStats::Player(ApiPlayerStats { g, a, ..Default::default() }));
+        let goalkeeper = get_athlete(9, ApiAthleteStats::Goalkeeper(ApiGoalkeeperStats { svs: 100, ..Default::default() }));
+        let athletes = vec![player(1, 1, 1), player(2, 5, 0), player(3, 0, 4), player(4, 2, 2), goalkeeper];
+
+        let ids = |stat: StatKey, limit: usize| get_leaderboard(athletes.clone(), stat, limit).into_iter().map(|e| e.id).collect::<Vec<i32>>();
+        assert_eq!(ids(StatKey::Points, 10), vec![2, 3, 4, 1]);
+        assert_eq!(ids(StatKey::Points, 2), vec![2, 3]);
+        assert_eq!(ids(StatKey::Assists, 1), vec![3]);
+    }
+
+    #[test]
+    fn leaderboard_by_save_percentage() {
+        let goalkeeper = |id: i32, svs: i32, ga: i32| get_athlete(id, ApiAthleteStats::Goalkeeper(ApiGoalkeeperStats { svs, ga, gp: (svs > 0) as i32, ..Default::default() }));
+        let athletes = vec![
+            goalkeeper(1, 90, 10),
+            goalkeeper(2, 95, 5),
+            goalkeeper(3, 0, 0),
+            goalkeeper(4, 180, 30),
+            get_athlete(5, ApiAthleteStats::Player(ApiPlayerStats { g: 10, ..Default::default() })),
+        ];
+        let ids = |stat: StatKey| get_leaderboard(athletes.clone(), stat, 10).into_iter().map(|e| e.id).collect::<Vec<i32>>();
+        assert_eq!(ids(StatKey::SvsPct), vec![2, 1, 4]);
+        assert_eq!(ids(StatKey::GoalsAgainst), vec![2, 1, 4]);
+    }
+
+    fn get_athlete(id: i32, stats: ApiAthleteStats) -> ApiAthlete {
+        ApiAthlete {
+            id,
+            first_name: "olle".to_string(),
+            family_name: "karlsson".to_string(),
+            jersey: 1,
+            team_code: "LHF".to_string(),
+            position: "FW".to_string(),
+            season: Season::Season2022,
+            image_url: None,
+            stats,
+        }
+    }
+
+    #[test]
+    fn unnamed_goalkeeper_is_dropped() {
+        let mut rsp = get_rsp(1, 1);
+        let gk = GoalkeeperStats {
+            info: PlayerInfo { playerId: 2, teamId: "LHF".to_string(), period: 0 },
+            GA: 1, NR: 30, SOGA: 20, SPGA: 0, SVS: 19, SVS_perc: 95.0,
+        };
+        rsp.gkStats = EachTeamStats { homeTeamValue: vec![gk], awayTeamValue: vec![] };
+
+        let athletes: Vec<ApiAthlete> = rsp.into();
+        assert_eq!(athletes.iter().map(|e| e.id).collect::<Vec<i32>>(), vec![1]);
+        assert!(athletes.iter().all(|e| !e.first_name.is_empty()));
+    }
+
+    #[test]
+    fn colliding_id_uses_own_team_name() {
+        let mut rsp = get_rsp(1, 1);
+        let mut away = rsp.stats.homeTeamValue[0].clone();
+        away.info.teamId = "FHC".to_string();
+        rsp.stats.awayTeamValue.push(away);
+        rsp.players.awayTeamValue.insert(1, PlayerName { firstName: "kalle".to_string(), lastName: "svensson".to_string(), imageUrl: None });
+        assert_eq!(get_collisions(&rsp.players.homeTeamValue, &rsp.players.awayTeamValue), vec![1]);
+
+        let athletes: Vec<ApiAthlete> = rsp.into();
+        let names: Vec<(String, String)> = athletes.iter().map(|e| (e.team_code.clone(), e.first_name.clone())).collect();
+        assert_eq!(names, vec![("LHF".to_string(), "olle".to_string()), ("FHC".to_string(), "kalle".to_string())]);
+    }
+
+    #[test]
+    fn image_from_feed() {
+        let mut rsp = get_rsp(1, 1);
+        rsp.players.homeTeamValue.insert(1, serde_json::from_str(r#"{ "firstName": "olle", "lastName": "karlsson", "image": "https://img/1.jpg" }"#).unwrap());
+        let athletes: Vec<ApiAthlete> = rsp.into();
+        assert_eq!(athletes[0].image_url, Some("https://img/1.jpg".to_string()));
+
+        // no image and no configured url in tests
+        let athletes: Vec<ApiAthlete> = get_rsp(1, 1).into();
+        assert_eq!(athletes[0].image_url, None);
+        assert!(serde_json::to_value(&athletes[0]).unwrap().get("image_url").is_none());
+    }
+
+    #[test]
+    fn shootout_goals_not_counted() {
+        std::env::set_var("DB_PATH", TempDir::new("test").expect("dir to be created").path().to_str().unwrap());
+        let game_uuid = "shootout_goals_not_counted";
+        // one goal in regulation and the decisive one in the shootout, both in the boxscore's G
+        _ = Db::<String, PlayerStatsRsp>::new("rest").write(&rest_client::get_player_stats_key(&League::SHL, &Season::Season2022, game_uuid), &get_rsp(1, 2));
+        let goal = |event_id: i32, period: i16| serde_json::from_str::<PlayByPlay>(&format!(r#"{{
+            "eventId": {event_id}, "revision": 1, "hash": "abc", "period": {period}, "gametime": "{event_id:02}:00", "description": "Mål",
+            "class": "Goal", "team": "LHF", "location": {{ "x": 1.0, "y": 2.0 }},
+            "extra": {{ "scorerId": 1, "scorerLong": "10 Olle Karlsson", "teamAdvantage": "EQ", "homeAgainst": 0, "homeForward": 1, "assist": "" }}
+        }}"#)).unwrap();
+        EventService::store_all_raw(game_uuid, vec![goal(1, 1), goal(2, 99)]);
+
+        let athletes = PlayerService::read(&League::SHL, &Season::Season2022, game_uuid).unwrap();
+        assert_eq!(athletes.len(), 1);
+        match &athletes[0].stats {
+            ApiAthleteStats::Player(p) => assert_eq!(p.g, 1),
+            _ => panic!("not a player"),
+        }
+    }
+
+    #[test]
+    fn parse_toi_minutes_seconds() {
+        assert_eq!(parse_toi("12:34"), Some(12 * 60 + 34));
+        assert_eq!(parse_toi("65:00"), Some(65 * 60));
+        assert_eq!(parse_toi(""), Some(0));
+    }
+
+    #[test]
+    fn parse_toi_hours() {
+        assert_eq!(parse_toi("1:05:30"), Some(3600 + 5 * 60 + 30));
+        assert_eq!(parse_toi("1:65:30"), None);
+        assert_eq!(parse_toi("12:75"), None);
+        assert_eq!(parse_toi("abc"), None);
+        assert_eq!(parse_toi("12:-4"), None);
+        assert_eq!(parse_toi("1:2:3:4"), None);
+    }
+
+    #[test]
+    fn game_log() {
+        std::env::set_var("DB_PATH", TempDir::new("test").expect("dir to be created").path().to_str().unwrap());
+        let player_id = 4242;
+        let season = Season::Season2021;
+        let first = get_game("game_log_1", Utc::now() - Duration::days(2));
+        let second = get_game("game_log_2", Utc::now() - Duration::days(1));
+        let not_played = get_game("game_log_3", Utc::now());
+        _ = Db::<Season, Vec<ApiGame>>::new("v2_season_decorated").write(&season, &vec![second.clone(), not_played.clone(), first.clone()]);
+
+        let rest_db = Db::<String, PlayerStatsRsp>::new("rest");
+        _ = rest_db.write(&rest_client::get_player_stats_key(&League::SHL, &first.season, &first.game_uuid), &get_rsp(player_id, 1));
+        _ = rest_db.write(&rest_client::get_player_stats_key(&League::SHL, &second.season, &second.game_uuid), &get_rsp(player_id, 2));
+        _ = rest_db.write(&rest_client::get_player_stats_key(&League::SHL, &not_played.season, &not_played.game_uuid), &get_rsp(player_id + 1, 3));
+
+        let log = PlayerService::game_log(player_id, &season);
+        assert_eq!(log.len(), 2);
+        assert_eq!(log[0].0, first.game_uuid);
+        assert_eq!(log[1].0, second.game_uuid);
+        let goals: Vec<i32> = log.iter().map(|e| match &e.1.stats { ApiAthleteStats::Player(p) => p.g, _ => panic!("not good") }).collect();
+        assert_eq!(goals, vec![1, 2]);
+    }
+
+    #[test]
+    fn read_is_season_scoped() {
+        std::env::set_var("DB_PATH", TempDir::new("test").expect("dir to be created").path().to_str().unwrap());
+        let game_uuid = "read_is_season_scoped";
+        let rest_db = Db::<String, PlayerStatsRsp>::new("rest");
+        _ = rest_db.write(&rest_client::get_player_stats_key(&League::SHL, &Season::Season2021, game_uuid), &get_rsp(1, 1));
+        _ = rest_db.write(&rest_client::get_player_stats_key(&League::SHL, &Season::Season2022, game_uuid), &get_rsp(2, 2));
+
+        let ids = |season: &Season| PlayerService::read(&League::SHL, season, game_uuid)
+            .unwrap()
+            .into_iter()
+            .map(|e| e.id)
+            .collect::<Vec<i32>>();
+        assert_eq!(ids(&Season::Season2021), vec![1]);
+        assert_eq!(ids(&Season::Season2022), vec![2]);
+        assert!(PlayerService::read(&League::SHL, &Season::Season2020, game_uuid).is_none());
+    }
+
+    #[tokio::test]
+    async fn partial_fetch_keeps_missing_players() {
+        std::env::set_var("DB_PATH", TempDir::new("test").expect("dir to be created").path().to_str().unwrap());
+        let game_uuid = "partial_fetch_keeps_missing_players";
+        // the feed only has player 2, who scored since
+        let base = mock_server(vec![serde_json::to_string(&get_rsp(2, 1)).unwrap()]).await;
+        // the key has the url
+        let key = rest_client::with_base_url(&base, async { rest_client::get_player_stats_key(&League::SHL, &Season::Season2022, game_uuid) }).await;
+        let mut cached = get_rsp(1, 1);
+        cached.stats.homeTeamValue.extend(get_rsp(2, 0).stats.homeTeamValue);
+        cached.players.homeTeamValue.extend(get_rsp(2, 0).players.homeTeamValue);
+        _ = Db::<String, PlayerStatsRsp>::new("rest").write(&key, &cached);
+
+        let goals = |athletes: Vec<ApiAthlete>| {
+            let mut goals: Vec<(i32, i32)> = athletes.into_iter()
+                .map(|e| match e.stats { ApiAthleteStats::Player(p) => (e.id, p.g), _ => panic!("not a player") })
+                .collect();
+            goals.sort();
+            goals
+        };
+        let fetched = rest_client::with_base_url(&base, PlayerService::update(&League::SHL, &Season::Season2022, game_uuid, ThrottlePolicy::Force)).await;
+        assert_eq!(goals(fetched.unwrap()), vec![(1, 1), (2, 1)]);
+        let read = rest_client::with_base_url(&base, async { PlayerService::read(&League::SHL, &Season::Season2022, game_uuid) }).await;
+        assert_eq!(goals(read.unwrap()), vec![(1, 1), (2, 1)]);
+    }
+
+    fn get_game(game_uuid: &str, start_date_time: chrono::DateTime<Utc>) -> ApiGame {
+        ApiGame {
+            game_uuid: game_uuid.to_string(),
+            home_team_code: "LHF".to_string(),
+            away_team_code: "FHC".to_string(),
+            home_team_result: 3,
+            away_team_result: 0,
+            start_date_time,
+            start_date_time_local: None,
+            status: GameStatus::Finished,
+            shootout: false,
+            overtime: false,
+            played: true,
+            game_type: GameType::Season,
+            league: League::SHL,
+            season: Season::Season2021,
+            gametime: None,
+        }
+    }
+
+    fn get_rsp(player_id: i32, goals: i32) -> PlayerStatsRsp {
+        let stats = PlayerStats {
+            info: PlayerInfo { playerId: player_id, teamId: "LHF".to_string(), period: 0 },
+            plus_minus: 0,
+            A: 0,
+            FOL: 0,
+            FOPerc: 0.0,
+            FOW: 0,
+            G: goals,
+            Hits: 0,
+            NR: 10,
+            PIM: 0,
+            POS: StringOrNum::String("FW".to_string()),
+            PPG: 0,
+            PPSOG: 0,
+            SOG: 0,
+            SW: 0,
+            TOI: "10:00".to_string(),
+        };
+        PlayerStatsRsp {
+            stats: EachTeamStats { homeTeamValue: vec![stats], awayTeamValue: vec![] },
+            players: EachTeamStats { homeTeamValue: HashMap::from([(player_id, PlayerName { firstName: "olle".to_string(), lastName: "karlsson".to_string(), imageUrl: None })]), awayTeamValue: HashMap::new() },
+            ..Default::default()
+        }
     }
 }
\ No newline at end of file
@@ -0,0 +1,296 @@
+use std::{sync::{Arc, Mutex}, collections::HashMap, time::Duration};
+
+use tracing::log;
+
+use crate::{event_service::{ApiGameEvent, PublishPolicy, EventKey}, CONFIG, db::Db, shutdown_service::ShutdownService};
+
+pub trait Publisher: Send + Sync {
+    fn publish(&self, event: &ApiGameEvent);
+}
+
+pub struct LogPublisher;
+impl Publisher for LogPublisher {
+    fn publish(&self, event: &ApiGameEvent) {
+        log::info!("[PUBLISH] {} {event}", event.game_uuid);
+    }
+}
+
+pub struct WebhookPublisher {
+    url: String,
+    client: reqwest::Client,
+}
+impl WebhookPublisher {
+    pub fn new(url: &str) -> WebhookPublisher {
+        WebhookPublisher { url: url.to_string(), client: reqwest::Client::new() }
+    }
+}
+impl Publisher for WebhookPublisher {
+    fn publish(&self, event: &ApiGameEvent) {
+        let request = self.client.post(&self.url).json(event);
+        let url = self.url.clone();
+        tokio::spawn(async move {
+            match request.send().await.and_then(|e| e.error_for_status()) {
+                Ok(_) => log::info!("[PUBLISH] Webhook {url}"),
+                Err(e) => log::error!("[PUBLISH] Webhook failed {url} {e}"),
+            }
+        });
+    }
+}
+
+#[derive(Clone)]
+pub struct PublishService {
+    policy: PublishPolicy,
+    publishers: Vec<Arc<dyn Publisher>>,
+    debounce: Duration,
+    // latest revision per (game_uuid, event key) waiting for the debounce window to pass
+    pending: Arc<Mutex<HashMap<(String, EventKey), ApiGameEvent>>>,
+}
+
+impl PublishService {
+    pub fn new(policy: PublishPolicy, publishers: Vec<Arc<dyn Publisher>>) -> PublishService {
+        PublishService { policy, publishers, debounce: Duration::ZERO, pending: Arc::new(Mutex::new(HashMap::new())) }
+    }
+
+    pub fn with_debounce(mut self, debounce: Duration) -> PublishService {
+        self.debounce = debounce;
+        self
+    }
+
+    // Made once at startup and shared, the debounce window only holds within one instance and its clones
+    pub fn from_config() -> PublishService {
+        let mut publishers: Vec<Arc<dyn Publisher>> = vec![Arc::new(LogPublisher)];
+        for url in &CONFIG.webhook_urls {
+            publishers.push(Arc::new(WebhookPublisher::new(url)));
+        }
+        PublishService::new(CONFIG.publish_policy.clone(), publishers)
+            .with_debounce(Duration::from_millis(CONFIG.publish_debounce_ms))
+    }
+
+    // Events of a disabled game are still stored, only not published. Kept over restarts
+    pub fn set_publish_enabled(game_uuid: &str, enabled: bool) {
+        log::info!("[PUBLISH] {} {game_uuid}", if enabled { "Enabled" } else { "Disabled" });
+        _ = Db::<String, bool>::new("v2_publish_enabled").write(&game_uuid.to_string(), &enabled);
+    }
+
+    pub fn is_publish_enabled(game_uuid: &str) -> bool {
+        Db::<String, bool>::new("v2_publish_enabled").read(&game_uuid.to_string()).unwrap_or(true)
+    }
+
+    // With a debounce window, true is only that the event is queued. Outside a runtime there is nothing
+    // to wait on, so it's published right away
+    pub fn publish(&self, event: &ApiGameEvent) -> bool {
+        if !event.should_publish(&self.policy) || !PublishService::is_publish_enabled(&event.game_uuid) {
+            return false;
+        }
+        if self.debounce.is_zero() || tokio::runtime::Handle::try_current().is_err() {
+            return self.publish_now(event);
+        }
+        let key = (event.game_uuid.clone(), event.key());
+        let is_first = self.pending.lock().unwrap_or_else(|e| e.into_inner())
+            .insert(key.clone(), event.clone())
+            .is_none();
+        if is_first {
+            let service = self.clone();
+            ShutdownService::spawn(async move {
+                tokio::time::sleep(service.debounce).await;
+                let event = service.pending.lock().unwrap_or_else(|e| e.into_inner()).remove(&key);
+                if let Some(event) = event {
+                    service.publish_now(&event);
+                }
+            });
+        } else {
+            log::info!("[PUBLISH] Debounced {} {}", event.game_uuid, event.event_id);
+        }
+        true
+    }
+
+    fn publish_now(&self, event: &ApiGameEvent) -> bool {
+        // may have been disabled during the debounce window
+        if !PublishService::is_publish_enabled(&event.game_uuid) || !PublishService::advance_cursor(event) {
+            return false;
+        }
+        for p in &self.publishers {
+            p.publish(event);
+        }
+        true
+    }
+
+    // Last published revision of each event per game is persisted, so a restart doesn't publish seen events again
+    fn advance_cursor(event: &ApiGameEvent) -> bool {
+        let db = Db::<String, Vec<(EventKey, u16)>>::new("v2_publish_revisions");
+        let key = event.key();
+        db.update(&event.game_uuid, |published| {
+            match published.iter_mut().find(|(e, _)| *e == key) {
+                Some((_, revision)) if *revision >= event.revision => false,
+                Some((_, revision)) => {
+                    *revision = event.revision;
+                    true
+                },
+                None => {
+                    published.push((key, event.revision));
+                    true
+                },
+            }
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{sync::{Arc, Mutex}, time::Duration};
+
+    use tempdir::TempDir;
+
+    use crate::{event_service::{ApiGameEvent, ApiEventType, PublishPolicy, EventService}, models2::external::event::PlayByPlay};
+
+    use super::{Publisher, PublishService};
+
+    struct RecordingPublisher {
+        published: Mutex<Vec<String>>,
+        events: Mutex<Vec<ApiGameEvent>>,
+    }
+    impl RecordingPublisher {
+        fn new() -> Arc<RecordingPublisher> {
+            Arc::new(RecordingPublisher { published: Mutex::new(vec![]), events: Mutex::new(vec![]) })
+        }
+    }
+    impl Publisher for RecordingPublisher {
+        fn publish(&self, event: &ApiGameEvent) {
+            self.published.lock().unwrap().push(event.event_id.clone());
+            self.events.lock().unwrap().push(event.clone());
+        }
+    }
+
+    #[test]
+    fn publishes_goals_not_shots() {
+        std::env::set_var("DB_PATH", TempDir::new("test").expect("dir to be created").path().to_str().unwrap());
+        let recorder = RecordingPublisher::new();
+        let service = PublishService::new(PublishPolicy::default(), vec![recorder.clone()]);
+
+        let goal = get_event(GOAL_JSON);
+        let shot = get_event(r#"{
+            "eventId": 2, "revision": 1, "hash": "b", "period": 1, "gametime": "02:00", "description": "",
+            "class": "Shot", "team": "LHF", "location": { "x": 1.0, "y": 2.0 }
+        }"#);
+        assert!(service.publish(&goal));
+        assert!(!service.publish(&shot));
+
+        assert_eq!(*recorder.published.lock().unwrap(), vec!["1".to_string()]);
+    }
+
+    #[test]
+    fn no_duplicate_publishes_after_restart() {
+        std::env::set_var("DB_PATH", TempDir::new("test").expect("dir to be created").path().to_str().unwrap());
+        let recorder = RecordingPublisher::new();
+        let goal = |event_id: i32, revision: u16| {
+            let mut event = get_event(GOAL_JSON);
+            event.game_uuid = "no_duplicate_publishes".to_string();
+            event.event_id = event_id.to_string();
+            event.revision = revision;
+            event
+        };
+
+        let service = PublishService::new(PublishPolicy::default(), vec![recorder.clone()]);
+        assert!(service.publish(&goal(1, 1)));
+        assert!(!service.publish(&goal(1, 1)));
+
+        let restarted = PublishService::new(PublishPolicy::default(), vec![recorder.clone()]);
+        assert!(!restarted.publish(&goal(1, 1)));
+        assert!(restarted.publish(&goal(1, 2)));
+        assert!(restarted.publish(&goal(3, 1)));
+
+        assert_eq!(*recorder.published.lock().unwrap(), vec!["1", "1", "3"]);
+    }
+
+    #[test]
+    fn cursor_is_per_event() {
+        std::env::set_var("DB_PATH", TempDir::new("test").expect("dir to be created").path().to_str().unwrap());
+        let recorder = RecordingPublisher::new();
+        let goal = |event_id: &str, revision: u16| {
+            let mut event = get_event(GOAL_JSON);
+            event.game_uuid = "cursor_is_per_event".to_string();
+            event.event_id = event_id.to_string();
+            event.revision = revision;
+            event
+        };
+
+        let service = PublishService::new(PublishPolicy::default(), vec![recorder.clone()]);
+        assert!(service.publish(&goal("5", 3)));
+        // a lower id or revision than the last published is still a new event
+        assert!(service.publish(&goal("2", 1)));
+        assert!(service.publish(&goal("GameEnd", 1)));
+        assert!(!service.publish(&goal("2", 1)));
+        assert!(!service.publish(&goal("5", 2)));
+        assert!(service.publish(&goal("5", 4)));
+
+        assert_eq!(*recorder.published.lock().unwrap(), vec!["5", "2", "GameEnd", "5"]);
+    }
+
+    #[tokio::test]
+    async fn debounces_rapid_revisions() {
+        std::env::set_var("DB_PATH", TempDir::new("test").expect("dir to be created").path().to_str().unwrap());
+        let recorder = RecordingPublisher::new();
+        let service = PublishService::new(PublishPolicy::default(), vec![recorder.clone()])
+            .with_debounce(Duration::from_millis(50));
+        for (revision, assist) in [(1, None), (2, None), (3, Some("2 Nils Nilsson".to_string()))] {
+            let mut event = get_event(GOAL_JSON);
+            event.game_uuid = "debounces_rapid_revisions".to_string();
+            event.revision = revision;
+            if let ApiEventType::Goal(g) = &mut event.info {
+                g.assist = assist;
+            }
+            assert!(service.publish(&event));
+        }
+        assert!(recorder.events.lock().unwrap().is_empty());
+
+        tokio::time::sleep(Duration::from_millis(200)).await;
+        let events = recorder.events.lock().unwrap();
+        assert_eq!(events.len(), 1);
+        assert_eq!(events[0].revision, 3);
+        match &events[0].info {
+            ApiEventType::Goal(g) => assert_eq!(g.assist.as_deref(), Some("2 Nils Nilsson")),
+            _ => panic!("not a goal"),
+        }
+    }
+
+    #[test]
+    fn debounce_outside_runtime_publishes_now() {
+        std::env::set_var("DB_PATH", TempDir::new("test").expect("dir to be created").path().to_str().unwrap());
+        let recorder = RecordingPublisher::new();
+        let service = PublishService::new(PublishPolicy::default(), vec![recorder.clone()])
+            .with_debounce(Duration::from_millis(50));
+        let event = ApiGameEvent { game_uuid: "debounce_outside_runtime_publishes_now".to_string(), ..get_event(GOAL_JSON) };
+        assert!(service.publish(&event));
+        assert_eq!(*recorder.published.lock().unwrap(), vec!["1"]);
+    }
+
+    #[test]
+    fn disabled_game_stores_without_publishing() {
+        std::env::set_var("DB_PATH", TempDir::new("test").expect("dir to be created").path().to_str().unwrap());
+        let game_uuid = "disabled_game_stores_without_publishing";
+        let recorder = RecordingPublisher::new();
+        let service = PublishService::new(PublishPolicy::default(), vec![recorder.clone()]);
+        let mut goal = ApiGameEvent { game_uuid: game_uuid.to_string(), ..get_event(GOAL_JSON) };
+
+        PublishService::set_publish_enabled(game_uuid, false);
+        assert!(EventService::store(game_uuid, &mut goal));
+        assert!(!service.publish(&goal));
+        // already stored
+        assert!(!EventService::store(game_uuid, &mut goal.clone()));
+        assert!(recorder.published.lock().unwrap().is_empty());
+
+        PublishService::set_publish_enabled(game_uuid, true);
+        assert!(service.publish(&goal));
+        assert_eq!(*recorder.published.lock().unwrap(), vec!["1"]);
+    }
+
+    const GOAL_JSON: &str = r#"{
+        "eventId": 1, "revision": 1, "hash": "a", "period": 1, "gametime": "01:00", "description": "",
+        "class": "Goal", "team": "LHF", "location": { "x": 1.0, "y": 2.0 },
+        "extra": { "scorerLong": "1 Olle Olsson", "teamAdvantage": "EQ", "homeAgainst": 0, "homeForward": 1, "assist": "" }
+    }"#;
+
+    fn get_event(json: &str) -> ApiGameEvent {
+        serde_json::from_str::<PlayByPlay>(json).unwrap().into_mapped_event("game_uuid")
+    }
+}
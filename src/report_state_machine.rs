@@ -2,11 +2,11 @@ use std::{time::Instant, cmp::Ordering};
 
 use tracing::log;
 
-use crate::{event_service::{ApiGameEvent, ApiEventType, GameEndInfo}, game_report_service::{GameReportService, ApiGameReport, GameStatus}};
+use crate::{event_service::{ApiGameEvent, ApiEventType, GameEndInfo}, game_report_service::{GameReportService, ApiGameReport, GameStatus}, models::GamePeriod};
 
 pub enum ApiSseMsg {
     Report(ApiGameReport),
-    Event(ApiGameEvent),
+    Event(Box<ApiGameEvent>),
 }
 
 impl ApiGameReport {
@@ -16,7 +16,12 @@ impl ApiGameReport {
             Ordering::Less => Some(self.away_team_code.clone()),
             Ordering::Equal => None,
         }
-    }    
+    }
+
+    fn get_game_end_info(&self) -> GameEndInfo {
+        let is_final = self.status == GameStatus::Finished;
+        GameEndInfo { winner: self.get_winner().filter(|_| is_final), is_final }
+    }
 }
 
 pub struct ReportStateMachine {
@@ -41,9 +46,11 @@ impl ReportStateMachine {
                 event_id: "GameStarted".to_string(),
                 revision: 1,
                 status: GameStatus::Period1,
+                period: GamePeriod::Regulation(1),
                 gametime: "00:00".to_string(),
                 description: "Nedsläpp".to_string(),
                 info: ApiEventType::GameStart,
+                tags: vec![],
             })
         } else if last_status != GameStatus::Finished && report.status == GameStatus::Finished {
             Some(ApiGameEvent { 
@@ -51,9 +58,11 @@ impl ReportStateMachine {
                 event_id: "GameEnded".to_string(), 
                 revision: 1,
                 status: GameStatus::Finished,
+                period: GamePeriod::default(),
                 gametime: report.gametime.clone(),
                 description: "Matchen slutade".to_string(),
-                info: ApiEventType::GameEnd(GameEndInfo { winner: report.get_winner() }),
+                info: ApiEventType::GameEnd(report.get_game_end_info()),
+                tags: vec![],
             })
         } else {
             None
@@ -69,4 +78,47 @@ impl ReportStateMachine {
         log::info!("[RSM] Get initial status {:?} {game_uuid} {:.2?}", res, before.elapsed());
         res
     } 
-}
\ No newline at end of file
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{game_report_service::{ApiGameReport, GameStatus}, event_service::{ApiEventType, GameEndInfo}};
+
+    use super::ReportStateMachine;
+
+    #[test]
+    fn game_end_final() {
+        let info = get_game_end_info(3, 2);
+        assert_eq!(info, GameEndInfo { winner: Some("LHF".to_string()), is_final: true });
+    }
+
+    #[test]
+    fn game_end_forfeit() {
+        let info = get_game_end_info(0, 5);
+        assert_eq!(info, GameEndInfo { winner: Some("FHC".to_string()), is_final: true });
+    }
+
+    #[test]
+    fn game_end_abandoned() {
+        let info = get_game_end_info(1, 1);
+        assert_eq!(info, GameEndInfo { winner: None, is_final: true });
+    }
+
+    fn get_game_end_info(home_team_result: i16, away_team_result: i16) -> GameEndInfo {
+        let mut rsm = ReportStateMachine { last: Some(GameStatus::Period3) };
+        let report = ApiGameReport {
+            game_uuid: "game_uuid".to_string(),
+            gametime: "60:00".to_string(),
+            status: GameStatus::Finished,
+            period: Default::default(),
+            home_team_code: "LHF".to_string(),
+            away_team_code: "FHC".to_string(),
+            home_team_result,
+            away_team_result,
+        };
+        match rsm.process(&report).map(|e| e.info) {
+            Some(ApiEventType::GameEnd(info)) => info,
+            _ => panic!("not good"),
+        }
+    }
+}
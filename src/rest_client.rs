@@ -1,10 +1,17 @@
-use std::time::{Duration, Instant};
+use std::collections::HashMap;
+use std::fmt::Display;
+use std::sync::Mutex;
+use std::time::{Duration, Instant, SystemTime};
 
+use lazy_static::lazy_static;
 use serde::{Serialize};
 use serde::de::DeserializeOwned;
 use tracing::log;
-use crate::{LogResult, CONFIG};
+use bounded_vec_deque::BoundedVecDeque;
+use crate::CONFIG;
 use crate::db::{Db};
+use crate::metrics::Metrics;
+use crate::service_error::{ServiceError, HttpError};
 use crate::models::{League, GameType, Season, SeasonKey};
 
 pub trait IdentifiableEnum {
@@ -43,50 +50,451 @@ impl IdentifiableEnum for Season {
     }
 }
 
+tokio::task_local! {
+    static BASE_URL: String;
+    static SLOW_CALL_THRESHOLD: Duration;
+}
+
+// Runs f with all endpoints on base_url instead of the configured hosts, e.g. a mock server
+#[cfg(test)]
+pub async fn with_base_url<F: std::future::Future>(base_url: &str, f: F) -> F::Output {
+    BASE_URL.scope(base_url.trim_end_matches('/').to_string(), f).await
+}
+
+// Runs f with calls slower than threshold recorded as slow, instead of the configured slow_call_ms
+#[cfg(test)]
+pub async fn with_slow_call_threshold<F: std::future::Future>(threshold: Duration, f: F) -> F::Output {
+    SLOW_CALL_THRESHOLD.scope(threshold, f).await
+}
+
+// None when slow calls aren't recorded
+fn get_slow_call_threshold() -> Option<Duration> {
+    let threshold = SLOW_CALL_THRESHOLD.try_with(|e| *e).unwrap_or_else(|_| Duration::from_millis(CONFIG.slow_call_ms));
+    (!threshold.is_zero()).then_some(threshold)
+}
+
+fn get_base_url(league: &League) -> String {
+    BASE_URL.try_with(|e| e.clone()).unwrap_or_else(|_| CONFIG.get_url(league).to_string())
+}
+
 pub fn get_season_url(key: &SeasonKey) -> String {
     let season_param = format!("seasonUuid={}", key.0.get_uuid());
     let league_param = format!("seriesUuid={}", key.1.get_uuid());
     let game_type_param = format!("gameTypeUuid={}", key.2.get_uuid());
-    format!("{}/sports/game-info?gamePlace=all&played=all&{season_param}&{league_param}&{game_type_param}", CONFIG.get_url(&key.1))
+    format!("{}/sports/game-info?gamePlace=all&played=all&{season_param}&{league_param}&{game_type_param}", get_base_url(&key.1))
 }
 
-pub async fn get_events(game_uuid: &str) -> Option<Vec<crate::models2::external::event::PlayByPlay>> {
-    let url = format!("{}/gameday/play-by-play/initial-events/{game_uuid}", CONFIG.get_url(&League::SHL));
+pub async fn get_events(game_uuid: &str) -> Result<Vec<crate::models2::external::event::PlayByPlay>, ServiceError> {
+    let url = format!("{}/gameday/play-by-play/initial-events/{game_uuid}", get_base_url(&League::SHL));
     get_call(&url).await
 }
 
 pub fn get_stats_url(league: &League, game_uuid: &str) -> String {
-    format!("{}/gameday/periodstats/{game_uuid}", CONFIG.get_url(league))
+    format!("{}/gameday/periodstats/{game_uuid}", get_base_url(league))
 }
 
 pub fn get_player_stats_url(league: &League, game_uuid: &str) -> String {
-    format!("{}/gameday/boxscore/{game_uuid}", CONFIG.get_url(league))
+    format!("{}/gameday/boxscore/{game_uuid}", get_base_url(league))
+}
+
+// The url has no season, game uuids can't be trusted to be unique across seasons
+pub fn get_player_stats_key(league: &League, season: &Season, game_uuid: &str) -> String {
+    format!("{season}/{}", get_player_stats_url(league, game_uuid))
+}
+
+lazy_static! {
+    // unchanged responses aren't rewritten, so the file mtime can't be used alone for throttling
+    static ref LAST_FETCHED: Mutex<HashMap<String, Instant>> = Mutex::new(HashMap::new());
+    // successful fetches only, an unchanged response doesn't rewrite the cached file
+    static ref LAST_FETCHED_OK: Mutex<HashMap<String, Instant>> = Mutex::new(HashMap::new());
+    static ref LAST_ERRORS: Mutex<HashMap<String, ServiceError>> = Mutex::new(HashMap::new());
+    // per host, no calls before the time a 429 asked us to wait for
+    static ref RETRY_AFTER: Mutex<HashMap<String, Instant>> = Mutex::new(HashMap::new());
+    static ref SLOW_CALLS: Mutex<BoundedVecDeque<SlowCall>> = Mutex::new(BoundedVecDeque::new(MAX_SLOW_CALLS));
+}
+
+const MAX_SLOW_CALLS: usize = 100;
+
+// A key whose last fetch failed isn't fetched again before this, unless forced. Failures aren't cached,
+// so without it a key that keeps failing would be fetched on every call
+const FAILED_FETCH_BACKOFF: Duration = Duration::from_secs(10 * 60);
+
+// status is None when there was no response
+#[derive(Serialize, Debug, Clone, PartialEq)]
+pub struct SlowCall {
+    pub url: String,
+    pub duration_ms: u64,
+    pub status: Option<u16>,
+}
+
+// When a cached response is refetched. Final values are never refetched, whatever the policy
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum ThrottlePolicy {
+    // Fetched once, then always served from the cache
+    Default,
+    // Never fetched, only served from the cache
+    Never,
+    // Fetched when the cache is older than the window
+    Window(Duration),
+    // Fetched on every call
+    Force,
+}
+
+impl ThrottlePolicy {
+    pub fn is_stale<K: Display + Clone, V: DeserializeOwned + Serialize + Clone>(&self, db: &Db<K, V>, key: &K) -> bool {
+        match self {
+            ThrottlePolicy::Default => db.is_stale(key, None),
+            ThrottlePolicy::Never => false,
+            ThrottlePolicy::Window(window) => db.is_stale(key, Some(*window)),
+            ThrottlePolicy::Force => db.is_stale(key, Some(Duration::ZERO)),
+        }
+    }
+
+    fn is_recently_fetched(&self, url: &str) -> bool {
+        match self {
+            ThrottlePolicy::Window(window) => {
+                let last_fetched = LAST_FETCHED.lock().unwrap_or_else(|e| e.into_inner()).get(url).cloned();
+                last_fetched.is_some_and(|last| last.elapsed() <= *window) || is_backing_off(url)
+            },
+            ThrottlePolicy::Default => is_backing_off(url),
+            ThrottlePolicy::Never | ThrottlePolicy::Force => false,
+        }
+    }
+}
+
+// The last fetch of key failed, within FAILED_FETCH_BACKOFF
+pub fn is_backing_off(key: &str) -> bool {
+    let last = LAST_FETCHED.lock().unwrap_or_else(|e| e.into_inner()).get(key).cloned();
+    let last_ok = LAST_FETCHED_OK.lock().unwrap_or_else(|e| e.into_inner()).get(key).cloned();
+    last.is_some_and(|last| last_ok.is_none_or(|ok| ok < last) && last.elapsed() <= FAILED_FETCH_BACKOFF)
+}
+
+pub async fn throttle_call<T: DeserializeOwned + Serialize + Clone + Default>(url: &str, throttle: ThrottlePolicy) -> Result<T, ServiceError> {
+    throttle_call_keyed(url, url, throttle).await
 }
 
-pub async fn throttle_call<T: DeserializeOwned + Serialize + Clone + Default>(url: &str, throttle_s: Option<Duration>) -> Option<T> {
+// Same as throttle_call, cached under key instead of the url
+pub async fn throttle_call_keyed<T: DeserializeOwned + Serialize + Clone + Default>(url: &str, key: &str, throttle: ThrottlePolicy) -> Result<T, ServiceError> {
+    throttle_call_merged(url, key, throttle, |rsp| rsp).await
+}
+
+// Same as throttle_call_keyed, with merge applied to a fetched value before it's cached
+pub async fn throttle_call_merged<T, F>(url: &str, key: &str, throttle: ThrottlePolicy, merge: F) -> Result<T, ServiceError>
+where
+    T: DeserializeOwned + Serialize + Clone + Default,
+    F: FnOnce(T) -> T,
+{
     let db = Db::<String, T>::new("rest");
 
-    if db.is_stale(&url.to_string(), throttle_s) {
-        let rsp: Option<T> = get_call(url).await;
-        if let Some(rsp) = rsp {
-            _ = db.write(&url.to_string(), &rsp);
-            Some(rsp)
-        } else {
-            _ = db.write(&url.to_string(), &rsp.unwrap_or_default());
-            None
+    if throttle.is_stale(&db, &key.to_string()) && !throttle.is_recently_fetched(key) {
+        let rsp: Result<T, ServiceError> = get_call(url).await.map(merge);
+        let now = Instant::now();
+        LAST_FETCHED.lock().unwrap_or_else(|e| e.into_inner()).insert(key.to_string(), now);
+        // a failed fetch must not overwrite a good cached value, it's only recorded for is_backing_off
+        if let Ok(rsp) = &rsp {
+            LAST_FETCHED_OK.lock().unwrap_or_else(|e| e.into_inner()).insert(key.to_string(), now);
+            _ = db.write_if_changed(&key.to_string(), rsp);
         }
+        rsp
     } else {
-        db.read(&url.to_string())
+        db.read(&key.to_string()).ok_or_else(|| ServiceError::Cache(format!("{key} not cached")))
+    }
+}
+
+// When key was last fetched successfully by this process, also when the response was unchanged
+pub fn last_fetched(key: &str) -> Option<SystemTime> {
+    let last = *LAST_FETCHED_OK.lock().unwrap_or_else(|e| e.into_inner()).get(key)?;
+    SystemTime::now().checked_sub(last.elapsed())
+}
+
+// The latest slow calls, oldest first
+pub fn slow_calls() -> Vec<SlowCall> {
+    SLOW_CALLS.lock().unwrap_or_else(|e| e.into_inner()).iter().cloned().collect()
+}
+
+fn record_call(url: &str, status: Option<u16>, duration: Duration) {
+    Metrics::record_latency("rest", duration);
+    if get_slow_call_threshold().is_some_and(|e| duration > e) {
+        log::warn!("[REST] Slow call {url} {:.2?} {:?}", duration, status);
+        let slow_call = SlowCall { url: url.to_string(), duration_ms: duration.as_millis() as u64, status };
+        SLOW_CALLS.lock().unwrap_or_else(|e| e.into_inner()).push_back(slow_call);
     }
 }
 
-async fn get_call<T: DeserializeOwned>(url: &str) -> Option<T> {
+// The latest failed fetch of the url, kept after later successful ones
+pub fn last_error(url: &str) -> Option<ServiceError> {
+    LAST_ERRORS.lock().unwrap_or_else(|e| e.into_inner()).get(url).cloned()
+}
+
+async fn get_call<T: DeserializeOwned>(url: &str) -> Result<T, ServiceError> {
+    let host = get_host(url);
+    wait_retry_after(&host).await;
     let before = Instant::now();
-    if let Some(rsp) = reqwest::get(url).await.ok_log(format!("[REST] {} Call failed", url).as_str()) {
-        let res = rsp.json().await.ok_log(format!("[REST] {} Parse failed", url).as_str());
-        log::info!("[REST] Call {url} {:.2?}", before.elapsed());
-        res
-    } else {
-        None
+    let mut response_status = None;
+    let result = match reqwest::get(url).await {
+        Ok(rsp) => {
+            let status = rsp.status();
+            response_status = Some(status.as_u16());
+            if status == reqwest::StatusCode::TOO_MANY_REQUESTS {
+                let retry_after = rsp.headers().get(reqwest::header::RETRY_AFTER)
+                    .and_then(|e| e.to_str().ok())
+                    .and_then(parse_retry_after);
+                if let Some(retry_after) = retry_after {
+                    log::warn!("[REST] {host} asked to retry after {:?}", retry_after);
+                    RETRY_AFTER.lock().unwrap_or_else(|e| e.into_inner()).insert(host.clone(), Instant::now() + retry_after);
+                }
+            }
+            match rsp.bytes().await {
+                Ok(body) if !status.is_success() => Err(ServiceError::Http(HttpError {
+                    url: url.to_string(),
+                    status: status.as_u16(),
+                    snippet: get_snippet(&body),
+                })),
+                Ok(body) => decode_body(&body).and_then(parse_body),
+                Err(e) => Err(ServiceError::Network(e.to_string())),
+            }
+        },
+        Err(e) => Err(ServiceError::Network(e.to_string())),
+    };
+    record_call(url, response_status, before.elapsed());
+    match &result {
+        Ok(_) => log::info!("[REST] Call {url} {:.2?}", before.elapsed()),
+        Err(e) => {
+            log::error!("[REST] {url} Call failed {e}");
+            LAST_ERRORS.lock().unwrap_or_else(|e| e.into_inner()).insert(url.to_string(), e.clone());
+        },
+    }
+    result
+}
+
+async fn wait_retry_after(host: &str) {
+    let not_before = RETRY_AFTER.lock().unwrap_or_else(|e| e.into_inner()).get(host).cloned();
+    if let Some(wait) = not_before.and_then(|e| e.checked_duration_since(Instant::now())) {
+        log::info!("[REST] Waiting {:?} for {host}", wait);
+        tokio::time::sleep(wait).await;
+    }
+}
+
+fn get_host(url: &str) -> String {
+    reqwest::Url::parse(url).ok()
+        .and_then(|e| e.host_str().map(|host| format!("{host}:{}", e.port_or_known_default().unwrap_or_default())))
+        .unwrap_or_default()
+}
+
+// A misbehaving upstream mustn't stall us for longer than this
+const MAX_RETRY_AFTER: Duration = Duration::from_secs(5 * 60);
+
+// Seconds, or an http date
+fn parse_retry_after(value: &str) -> Option<Duration> {
+    let value = value.trim();
+    let retry_after = match value.parse::<u64>() {
+        Ok(seconds) => Duration::from_secs(seconds),
+        Err(_) => {
+            let date = chrono::DateTime::parse_from_rfc2822(value).ok()?;
+            (date.with_timezone(&chrono::Utc) - chrono::Utc::now()).to_std().unwrap_or_default()
+        },
+    };
+    Some(retry_after.min(MAX_RETRY_AFTER))
+}
+
+fn get_snippet(body: &[u8]) -> String {
+    String::from_utf8_lossy(body).chars().take(SNIPPET_LEN).collect()
+}
+
+const SNIPPET_LEN: usize = 64;
+
+// Not using the lossy text(), a broken encoding must not turn into a parse error on replacement chars
+fn decode_body(body: &[u8]) -> Result<&str, ServiceError> {
+    std::str::from_utf8(body).map_err(|e| {
+        ServiceError::Decode(format!("{e}, {} bytes: {}", body.len(), get_snippet(body)))
+    })
+}
+
+// An empty or null body is an upstream hiccup, not an empty response
+fn parse_body<T: DeserializeOwned>(body: &str) -> Result<T, ServiceError> {
+    let body = body.trim();
+    if body.is_empty() || body == "null" {
+        return Err(ServiceError::Parse("Empty body".to_string()));
+    }
+    serde_json::from_str(body).map_err(|e| ServiceError::Parse(e.to_string()))
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::{Duration, Instant};
+
+    use serde::{Serialize, Deserialize};
+    use tempdir::TempDir;
+    use tokio::net::TcpListener;
+
+    use crate::{db::Db, service_error::{ServiceError, HttpError}, models::League, mock_server::{mock_server, mock_server_with_status, mock_server_with_responses, recording_mock_server}};
+
+    use crate::metrics::Metrics;
+
+    use super::{throttle_call, last_error, parse_retry_after, ThrottlePolicy, with_base_url, with_slow_call_threshold, slow_calls, get_stats_url, get_events};
+
+    #[derive(Serialize, Deserialize, Debug, Clone, Default, PartialEq)]
+    struct Rsp {
+        items: Vec<i32>,
+    }
+
+    #[tokio::test]
+    async fn base_url_override_hits_mock() {
+        std::env::set_var("DB_PATH", TempDir::new("test").expect("dir to be created").path().to_str().unwrap());
+        let base = mock_server(vec!["[]"]).await;
+        let (url, events) = with_base_url(&format!("{base}/"), async {
+            (get_stats_url(&League::HA, "game_uuid"), get_events("base_url_override_hits_mock").await)
+        }).await;
+        assert_eq!(url, format!("{base}/gameday/periodstats/game_uuid"));
+        assert_eq!(events.map(|e| e.len()), Ok(0));
+        // outside of the scope the configured host is used again, none in tests
+        assert_eq!(get_stats_url(&League::HA, "game_uuid"), "/gameday/periodstats/game_uuid");
+    }
+
+    #[tokio::test]
+    async fn error_status_is_captured() {
+        std::env::set_var("DB_PATH", TempDir::new("test").expect("dir to be created").path().to_str().unwrap());
+        let body = format!("Internal error: {}", "x".repeat(200));
+        let base = mock_server_with_status("500 Internal Server Error", vec![body]).await;
+        let url = format!("{base}/error_status");
+
+        let rsp: Result<Rsp, ServiceError> = throttle_call(&url, ThrottlePolicy::Default).await;
+        let expected = ServiceError::Http(HttpError { url: url.clone(), status: 500, snippet: format!("Internal error: {}", "x".repeat(48)) });
+        assert_eq!(rsp, Err(expected.clone()));
+        assert_eq!(last_error(&url), Some(expected));
+        assert_eq!(last_error(&format!("{base}/never_fetched")), None);
+    }
+
+    #[tokio::test]
+    async fn slow_call_is_recorded() {
+        std::env::set_var("DB_PATH", TempDir::new("test").expect("dir to be created").path().to_str().unwrap());
+        let base = mock_server_with_responses(vec![
+            ("200 OK", "", r#"{"items": []}"#, Duration::from_millis(800)),
+            ("200 OK", "", r#"{"items": []}"#, Duration::ZERO),
+        ]).await;
+        let slow = format!("{base}/slow_call");
+        let fast = format!("{base}/fast_call");
+        with_slow_call_threshold(Duration::from_millis(400), async {
+            let _: Result<Rsp, ServiceError> = throttle_call(&slow, ThrottlePolicy::Force).await;
+            let _: Result<Rsp, ServiceError> = throttle_call(&fast, ThrottlePolicy::Force).await;
+        }).await;
+
+        let recorded = slow_calls();
+        let call = recorded.iter().find(|e| e.url == slow).expect("slow call to be recorded");
+        assert_eq!(call.status, Some(200));
+        assert!(call.duration_ms >= 800, "{:?}", call);
+        assert!(!recorded.iter().any(|e| e.url == fast), "{:?}", recorded);
+        assert!(Metrics::latencies().get("rest").is_some_and(|e| e.count >= 2));
+    }
+
+    #[tokio::test]
+    async fn empty_body_keeps_cached_value() {
+        std::env::set_var("DB_PATH", TempDir::new("test").expect("dir to be created").path().to_str().unwrap());
+        let base = mock_server(vec![r#"{"items": [1, 2]}"#, "", "null"]).await;
+        let url = format!("{base}/empty_body");
+
+        let first: Result<Rsp, ServiceError> = throttle_call(&url, ThrottlePolicy::Default).await;
+        assert_eq!(first, Ok(Rsp { items: vec![1, 2] }));
+
+        let empty: Result<Rsp, ServiceError> = throttle_call(&url, ThrottlePolicy::Force).await;
+        assert!(matches!(empty, Err(ServiceError::Parse(_))));
+        let null: Result<Rsp, ServiceError> = throttle_call(&url, ThrottlePolicy::Force).await;
+        assert!(matches!(null, Err(ServiceError::Parse(_))));
+
+        let db = Db::<String, Rsp>::new("rest");
+        assert_eq!(db.read(&url), Some(Rsp { items: vec![1, 2] }));
+    }
+
+    #[tokio::test]
+    async fn failed_fetch_backs_off() {
+        std::env::set_var("DB_PATH", TempDir::new("test").expect("dir to be created").path().to_str().unwrap());
+        let (base, paths) = recording_mock_server(vec![("500 Internal Server Error", "", "", Duration::ZERO), ("200 OK", "", r#"{"items": [1]}"#, Duration::ZERO)]).await;
+        let url = format!("{base}/failed_fetch_backs_off");
+
+        let failed: Result<Rsp, ServiceError> = throttle_call(&url, ThrottlePolicy::Default).await;
+        assert!(matches!(failed, Err(ServiceError::Http(_))));
+        assert!(super::is_backing_off(&url));
+        let again: Result<Rsp, ServiceError> = throttle_call(&url, ThrottlePolicy::Default).await;
+        assert!(matches!(again, Err(ServiceError::Cache(_))));
+        assert_eq!(paths.lock().unwrap().len(), 1);
+
+        // forcing still fetches
+        let forced: Result<Rsp, ServiceError> = throttle_call(&url, ThrottlePolicy::Force).await;
+        assert_eq!(forced, Ok(Rsp { items: vec![1] }));
+        assert!(!super::is_backing_off(&url));
+    }
+
+    #[tokio::test]
+    async fn invalid_utf8_keeps_cached_value() {
+        std::env::set_var("DB_PATH", TempDir::new("test").expect("dir to be created").path().to_str().unwrap());
+        let base = mock_server(vec![br#"{"items": [1]}"#.to_vec(), b"{\"items\": [\xff\xfe]}".to_vec()]).await;
+        let url = format!("{base}/invalid_utf8");
+
+        let first: Result<Rsp, ServiceError> = throttle_call(&url, ThrottlePolicy::Default).await;
+        assert_eq!(first, Ok(Rsp { items: vec![1] }));
+
+        let invalid: Result<Rsp, ServiceError> = throttle_call(&url, ThrottlePolicy::Force).await;
+        match invalid {
+            Err(ServiceError::Decode(e)) => assert!(e.contains("15 bytes"), "{e}"),
+            e => panic!("expected a decode error, got {e:?}"),
+        }
+        assert_eq!(Db::<String, Rsp>::new("rest").read(&url), Some(Rsp { items: vec![1] }));
+    }
+
+    #[tokio::test]
+    async fn error_variants() {
+        std::env::set_var("DB_PATH", TempDir::new("test").expect("dir to be created").path().to_str().unwrap());
+        let base = mock_server(vec!["not json"]).await;
+        let parse: Result<Rsp, ServiceError> = throttle_call(&format!("{base}/error_parse"), ThrottlePolicy::Default).await;
+        assert!(matches!(parse, Err(ServiceError::Parse(_))));
+
+        let closed = TcpListener::bind("127.0.0.1:0").await.unwrap().local_addr().unwrap();
+        let url = format!("http://{closed}/error_network");
+        let network: Result<Rsp, ServiceError> = throttle_call(&url, ThrottlePolicy::Window(Duration::from_secs(3600))).await;
+        assert!(matches!(network, Err(ServiceError::Network(_))));
+
+        // already tried within the throttle window, and nothing got cached
+        let cache: Result<Rsp, ServiceError> = throttle_call(&url, ThrottlePolicy::Window(Duration::from_secs(3600))).await;
+        assert!(matches!(cache, Err(ServiceError::Cache(_))));
+    }
+
+    #[tokio::test]
+    async fn policies_decide_fetching() {
+        std::env::set_var("DB_PATH", TempDir::new("test").expect("dir to be created").path().to_str().unwrap());
+        let base = mock_server(vec![r#"{"items": [1]}"#, r#"{"items": [2]}"#, r#"{"items": [3]}"#]).await;
+        let url = format!("{base}/policies_decide_fetching");
+        let call = |throttle| throttle_call::<Rsp>(&url, throttle);
+
+        assert!(matches!(call(ThrottlePolicy::Never).await, Err(ServiceError::Cache(_))));
+        assert_eq!(call(ThrottlePolicy::Default).await, Ok(Rsp { items: vec![1] }));
+        assert_eq!(call(ThrottlePolicy::Default).await, Ok(Rsp { items: vec![1] }));
+        assert_eq!(call(ThrottlePolicy::Never).await, Ok(Rsp { items: vec![1] }));
+        assert_eq!(call(ThrottlePolicy::Window(Duration::from_secs(3600))).await, Ok(Rsp { items: vec![1] }));
+        assert_eq!(call(ThrottlePolicy::Force).await, Ok(Rsp { items: vec![2] }));
+        assert_eq!(call(ThrottlePolicy::Force).await, Ok(Rsp { items: vec![3] }));
+
+        // not even forced once final
+        Db::<String, Rsp>::new("rest").mark_final(&url).unwrap();
+        assert_eq!(call(ThrottlePolicy::Force).await, Ok(Rsp { items: vec![3] }));
+    }
+
+    #[tokio::test]
+    async fn retry_after_delays_next_call() {
+        std::env::set_var("DB_PATH", TempDir::new("test").expect("dir to be created").path().to_str().unwrap());
+        let base = mock_server_with_responses(vec![
+            ("429 Too Many Requests", "Retry-After: 2\r\n", "", Duration::ZERO),
+            ("200 OK", "", r#"{"items": [1]}"#, Duration::ZERO),
+        ]).await;
+        let url = format!("{base}/retry_after");
+
+        let limited: Result<Rsp, ServiceError> = throttle_call(&url, ThrottlePolicy::Force).await;
+        assert!(matches!(limited, Err(ServiceError::Http(HttpError { status: 429, .. }))));
+
+        let before = Instant::now();
+        let rsp: Result<Rsp, ServiceError> = throttle_call(&url, ThrottlePolicy::Force).await;
+        assert_eq!(rsp, Ok(Rsp { items: vec![1] }));
+        assert!(before.elapsed() >= Duration::from_millis(1900), "{:?}", before.elapsed());
+        assert_eq!(parse_retry_after("100000"), Some(Duration::from_secs(300)));
+        assert_eq!(parse_retry_after("Wed, 21 Oct 2015 07:28:00 GMT"), Some(Duration::ZERO));
     }
 }
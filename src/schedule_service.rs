@@ -0,0 +1,194 @@
+use std::{collections::{BTreeMap, HashMap}, sync::{Arc, Mutex}, time::Instant};
+
+use chrono::{DateTime, Utc, NaiveDate};
+use lazy_static::lazy_static;
+use serde::{Serialize, Deserialize};
+
+use crate::{models::{League, Season, GameType, SeasonKey, normalize_team_code, to_stockholm_time}, rest_client::{self, ThrottlePolicy}, models2::external::season::{SeasonRsp, SeasonGame}, game_report_service::GameStatus, service_config::ServiceConfig};
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct GameRef {
+    pub game_uuid: String,
+    pub team: String,
+    pub opponent: String,
+    pub start_date_time: DateTime<Utc>,
+    pub is_home: bool,
+    pub status: GameStatus,
+    pub game_type: GameType,
+}
+
+impl GameRef {
+    fn new(game_type: &GameType, e: &SeasonGame, is_home: bool) -> GameRef {
+        let (team, opponent) = match is_home {
+            true => (&e.homeTeamInfo.code, &e.awayTeamInfo.code),
+            false => (&e.awayTeamInfo.code, &e.homeTeamInfo.code),
+        };
+        GameRef {
+            game_uuid: e.uuid.clone(),
+            team: normalize_team_code(team),
+            opponent: normalize_team_code(opponent),
+            start_date_time: e.startDateTime,
+            is_home,
+            status: GameStatus::from_season_state(&e.state),
+            game_type: game_type.clone(),
+        }
+    }
+}
+
+// Games by Stockholm local date, so a game starting 00:30 local belongs to that day and not the UTC one before
+#[derive(Default)]
+struct ScheduleIndex {
+    by_date: BTreeMap<NaiveDate, Vec<GameRef>>,
+}
+
+impl ScheduleIndex {
+    fn new(rsps: &[(GameType, SeasonRsp)]) -> ScheduleIndex {
+        let mut index = ScheduleIndex::default();
+        for (game_type, rsp) in rsps {
+            for e in &rsp.gameInfo {
+                let date = to_stockholm_time(&e.startDateTime).date_naive();
+                index.by_date.entry(date).or_default().push(GameRef::new(game_type, e, true));
+            }
+        }
+        for games in index.by_date.values_mut() {
+            games.sort_by_key(|e| e.start_date_time);
+        }
+        index
+    }
+
+    fn get(&self, date: &NaiveDate) -> Vec<GameRef> {
+        self.by_date.get(date).cloned().unwrap_or_default()
+    }
+}
+
+lazy_static! {
+    static ref DATE_INDEX: Mutex<HashMap<Season, (Instant, Arc<ScheduleIndex>)>> = Mutex::new(HashMap::new());
+}
+
+pub struct ScheduleService;
+impl ScheduleService {
+    pub async fn team_games(league: &League, season: &Season, team_code: &str, service_config: &ServiceConfig) -> Vec<GameRef> {
+        let rsps = ScheduleService::fetch(std::slice::from_ref(league), season, service_config).await;
+        ScheduleService::get_team_games(&rsps, team_code)
+    }
+
+    // Games on a Stockholm local date, from home team perspective
+    pub async fn games_on(date: &NaiveDate, service_config: &ServiceConfig) -> Vec<GameRef> {
+        match Season::containing(date) {
+            Some(season) => ScheduleService::get_index(&season, service_config).await.get(date),
+            None => vec![],
+        }
+    }
+
+    async fn get_index(season: &Season, service_config: &ServiceConfig) -> Arc<ScheduleIndex> {
+        let cached = DATE_INDEX.lock().unwrap_or_else(|e| e.into_inner()).get(season).cloned();
+        match cached {
+            Some((built, index)) if !matches!(service_config.season_throttle(season), ThrottlePolicy::Window(t) if built.elapsed() > t) => index,
+            _ => {
+                let rsps = ScheduleService::fetch(&League::get_all(), season, service_config).await;
+                let index = Arc::new(ScheduleIndex::new(&rsps));
+                DATE_INDEX.lock().unwrap_or_else(|e| e.into_inner()).insert(season.clone(), (Instant::now(), index.clone()));
+                index
+            }
+        }
+    }
+
+    async fn fetch(leagues: &[League], season: &Season, service_config: &ServiceConfig) -> Vec<(GameType, SeasonRsp)> {
+        let mut rsps = vec![];
+        for league in leagues {
+            for game_type in GameType::get_all() {
+                let key = SeasonKey(season.clone(), league.clone(), game_type.clone());
+                let url = rest_client::get_season_url(&key);
+                if let Ok(rsp) = rest_client::throttle_call::<SeasonRsp>(&url, service_config.season_throttle(season)).await {
+                    rsps.push((game_type, rsp));
+                }
+            }
+        }
+        rsps
+    }
+
+    fn get_team_games(rsps: &[(GameType, SeasonRsp)], team_code: &str) -> Vec<GameRef> {
+        let team_code = normalize_team_code(team_code);
+        let mut games: Vec<GameRef> = rsps.iter()
+            .flat_map(|(game_type, rsp)| rsp.gameInfo.iter().map(move |e| (game_type, e)))
+            .filter(|(_, e)| normalize_team_code(&e.homeTeamInfo.code) == team_code || normalize_team_code(&e.awayTeamInfo.code) == team_code)
+            .map(|(game_type, e)| GameRef::new(game_type, e, normalize_team_code(&e.homeTeamInfo.code) == team_code))
+            .collect();
+        games.sort_by_key(|e| e.start_date_time);
+        games
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{models::GameType, models2::external::season::SeasonRsp, game_report_service::GameStatus};
+
+    use super::{ScheduleService, ScheduleIndex};
+
+    const SCHEDULE: &str = r#"{
+        "gameInfo": [
+            {
+                "uuid": "game2", "startDateTime": "2022-09-20T17:00:00Z", "state": "pre-game", "shootout": false, "overtime": false,
+                "homeTeamInfo": { "code": "FHC", "score": 0 }, "awayTeamInfo": { "code": "LHF", "score": 0 }, "seriesInfo": { "code": "SHL" }
+            },
+            {
+                "uuid": "game1", "startDateTime": "2022-09-17T15:15:00Z", "state": "post-game", "shootout": false, "overtime": true,
+                "homeTeamInfo": { "code": "LHF", "score": 3 }, "awayTeamInfo": { "code": "TIK", "score": 2 }, "seriesInfo": { "code": "SHL" }
+            },
+            {
+                "uuid": "game3", "startDateTime": "2022-09-18T15:15:00Z", "state": "post-game", "shootout": false, "overtime": false,
+                "homeTeamInfo": { "code": "MODO", "score": 1 }, "awayTeamInfo": { "code": "TIK", "score": 2 }, "seriesInfo": { "code": "SHL" }
+            }
+        ],
+        "teamList": []
+    }"#;
+
+    #[test]
+    fn parse_team_schedule() {
+        let rsp: SeasonRsp = serde_json::from_str(SCHEDULE).unwrap();
+        let games = ScheduleService::get_team_games(&[(GameType::Season, rsp)], "LHF");
+        assert_eq!(games.len(), 2);
+
+        assert_eq!(games[0].game_uuid, "game1");
+        assert_eq!(games[0].opponent, "TIK");
+        assert!(games[0].is_home);
+        assert_eq!(games[0].status, GameStatus::Finished);
+
+        assert_eq!(games[1].game_uuid, "game2");
+        assert_eq!(games[1].opponent, "FHC");
+        assert!(!games[1].is_home);
+        assert_eq!(games[1].status, GameStatus::Coming);
+        assert_eq!(games[1].game_type, GameType::Season);
+    }
+
+    #[test]
+    fn games_on_local_date() {
+        let rsp: SeasonRsp = serde_json::from_str(r#"{
+            "gameInfo": [
+                {
+                    "uuid": "late", "startDateTime": "2022-09-17T21:30:00Z", "state": "pre-game", "shootout": false, "overtime": false,
+                    "homeTeamInfo": { "code": "FHC", "score": 0 }, "awayTeamInfo": { "code": "LHF", "score": 0 }, "seriesInfo": { "code": "SHL" }
+                },
+                {
+                    "uuid": "after_midnight", "startDateTime": "2022-09-17T22:30:00Z", "state": "pre-game", "shootout": false, "overtime": false,
+                    "homeTeamInfo": { "code": "MODO", "score": 0 }, "awayTeamInfo": { "code": "TIK", "score": 0 }, "seriesInfo": { "code": "SHL" }
+                },
+                {
+                    "uuid": "early", "startDateTime": "2022-09-17T13:00:00Z", "state": "post-game", "shootout": false, "overtime": false,
+                    "homeTeamInfo": { "code": "LHF", "score": 1 }, "awayTeamInfo": { "code": "TIK", "score": 2 }, "seriesInfo": { "code": "SHL" }
+                }
+            ],
+            "teamList": []
+        }"#).unwrap();
+        let index = ScheduleIndex::new(&[(GameType::Season, rsp)]);
+
+        let games = index.get(&"2022-09-17".parse().unwrap());
+        assert_eq!(games.iter().map(|e| e.game_uuid.as_str()).collect::<Vec<&str>>(), vec!["early", "late"]);
+        assert_eq!(games[1].team, "FHC");
+        assert_eq!(games[1].opponent, "LHF");
+
+        let games = index.get(&"2022-09-18".parse().unwrap());
+        assert_eq!(games.iter().map(|e| e.game_uuid.as_str()).collect::<Vec<&str>>(), vec!["after_midnight"]);
+        assert!(index.get(&"2022-09-19".parse().unwrap()).is_empty());
+    }
+}
@@ -1,22 +1,13 @@
-use std::time::Duration;
-
-use crate::rest_client::{self};
+use crate::rest_client;
 use crate::db::Db;
 use crate::models::{GameType, League, SeasonKey, Season};
 use crate::models2::external::season::{SeasonRsp};
+use crate::service_config::ServiceConfig;
 
 pub struct SeasonService {
+    pub service_config: ServiceConfig,
 }
 
-impl Season {
-    fn get_throttle(&self) -> Option<Duration> {
-        if self == &Season::get_current() {
-            Some(Duration::from_secs(60 * 60 * 10))
-        } else {
-            None
-        }
-    }
-}
 impl SeasonService {
 
     pub async fn update(&self, season: &Season) -> (Vec<(SeasonKey, SeasonRsp)>, bool) {
@@ -28,8 +19,9 @@ impl SeasonService {
             for game_type in GameType::get_all() {
                 let key = SeasonKey(season.clone(), league.clone(), game_type.clone());
                 let url = rest_client::get_season_url(&key);
-                if db.is_stale(&url, season.get_throttle()) {
-                    if let Some(obj) = rest_client::throttle_call(&url, season.get_throttle()).await {
+                let throttle = self.service_config.season_throttle(season);
+                if throttle.is_stale(&db, &url) {
+                    if let Ok(obj) = rest_client::throttle_call(&url, throttle).await {
                         result.push((key.clone(), obj));
                         updated = true;
                     }
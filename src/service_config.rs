@@ -0,0 +1,157 @@
+use std::time::Duration;
+
+use serde::Deserialize;
+
+use crate::{models::Season, rest_client::ThrottlePolicy};
+
+// Seconds the cached resources are served before they're fetched again. Games refreshed by
+// the GameRefresher follow RefreshIntervals instead
+#[derive(Deserialize, Debug, Clone, PartialEq)]
+#[serde(default)]
+pub struct ServiceConfig {
+    // the events endpoint, refreshed in the background
+    pub events_s: u64,
+    // game details. None fetches once and then serves the cache, until the game is final
+    pub details_events_s: Option<u64>,
+    pub stats_s: Option<u64>,
+    pub players_s: Option<u64>,
+    // only the current season, older seasons are fetched once
+    pub season_s: u64,
+    pub fetch_details_s: u64,
+    pub scoreboard_s: u64,
+}
+impl Default for ServiceConfig {
+    fn default() -> Self {
+        ServiceConfig {
+            events_s: 30,
+            details_events_s: None,
+            stats_s: None,
+            players_s: None,
+            season_s: 60 * 60 * 10,
+            fetch_details_s: 60 * 60,
+            scoreboard_s: 5,
+        }
+    }
+}
+
+impl ServiceConfig {
+    pub fn builder() -> ServiceConfigBuilder {
+        ServiceConfigBuilder(ServiceConfig::default())
+    }
+
+    pub fn events_throttle(&self) -> ThrottlePolicy {
+        ThrottlePolicy::Window(Duration::from_secs(self.events_s))
+    }
+
+    pub fn details_events_throttle(&self) -> ThrottlePolicy {
+        get_throttle(self.details_events_s)
+    }
+
+    pub fn stats_throttle(&self) -> ThrottlePolicy {
+        get_throttle(self.stats_s)
+    }
+
+    pub fn players_throttle(&self) -> ThrottlePolicy {
+        get_throttle(self.players_s)
+    }
+
+    pub fn season_throttle(&self, season: &Season) -> ThrottlePolicy {
+        if season == &Season::get_current() {
+            ThrottlePolicy::Window(Duration::from_secs(self.season_s))
+        } else {
+            ThrottlePolicy::Default
+        }
+    }
+
+    pub fn fetch_details_window(&self) -> Duration {
+        Duration::from_secs(self.fetch_details_s)
+    }
+
+    pub fn scoreboard_ttl(&self) -> Duration {
+        Duration::from_secs(self.scoreboard_s)
+    }
+}
+
+fn get_throttle(window_s: Option<u64>) -> ThrottlePolicy {
+    match window_s {
+        Some(s) => ThrottlePolicy::Window(Duration::from_secs(s)),
+        None => ThrottlePolicy::Default,
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct ServiceConfigBuilder(ServiceConfig);
+impl ServiceConfigBuilder {
+    pub fn events(mut self, window: Duration) -> Self {
+        self.0.events_s = window.as_secs();
+        self
+    }
+
+    pub fn details_events(mut self, window: Duration) -> Self {
+        self.0.details_events_s = Some(window.as_secs());
+        self
+    }
+
+    pub fn stats(mut self, window: Duration) -> Self {
+        self.0.stats_s = Some(window.as_secs());
+        self
+    }
+
+    pub fn players(mut self, window: Duration) -> Self {
+        self.0.players_s = Some(window.as_secs());
+        self
+    }
+
+    pub fn season(mut self, window: Duration) -> Self {
+        self.0.season_s = window.as_secs();
+        self
+    }
+
+    pub fn fetch_details(mut self, window: Duration) -> Self {
+        self.0.fetch_details_s = window.as_secs();
+        self
+    }
+
+    pub fn scoreboard(mut self, ttl: Duration) -> Self {
+        self.0.scoreboard_s = ttl.as_secs();
+        self
+    }
+
+    pub fn build(self) -> ServiceConfig {
+        self.0
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use tempdir::TempDir;
+
+    use crate::{db::Db, models::Season, rest_client::ThrottlePolicy};
+
+    use super::ServiceConfig;
+
+    #[test]
+    fn custom_windows_are_honored() {
+        std::env::set_var("DB_PATH", TempDir::new("test").expect("dir to be created").path().to_str().unwrap());
+        let config = ServiceConfig::builder()
+            .events(Duration::ZERO)
+            .stats(Duration::from_secs(90))
+            .season(Duration::from_secs(120))
+            .build();
+        assert_eq!(config.events_throttle(), ThrottlePolicy::Window(Duration::ZERO));
+        assert_eq!(config.stats_throttle(), ThrottlePolicy::Window(Duration::from_secs(90)));
+        assert_eq!(config.players_throttle(), ThrottlePolicy::Default);
+        assert_eq!(config.season_throttle(&Season::get_current()), ThrottlePolicy::Window(Duration::from_secs(120)));
+        assert_eq!(config.season_throttle(&Season::Season2018), ThrottlePolicy::Default);
+
+        let db = Db::<String, String>::new("test_service_config");
+        let key = "events".to_string();
+        db.write(&key, &"value".to_string()).unwrap();
+        std::thread::sleep(Duration::from_millis(10));
+        assert!(config.events_throttle().is_stale(&db, &key));
+        assert!(!ServiceConfig::default().events_throttle().is_stale(&db, &key));
+        assert!(!config.stats_throttle().is_stale(&db, &key));
+    }
+}
@@ -0,0 +1,37 @@
+use std::fmt::Display;
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct HttpError {
+    pub url: String,
+    pub status: u16,
+    // start of the body, upstream error pages can be large
+    pub snippet: String,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum ServiceError {
+    // upstream couldn't be reached or didn't answer
+    Network(String),
+    // upstream answered with an error status
+    Http(HttpError),
+    // upstream answered with something that isn't the expected JSON
+    Parse(String),
+    // upstream answered with bytes that aren't UTF-8, so not even text
+    Decode(String),
+    // no fetch was made and nothing usable is cached
+    Cache(String),
+}
+
+impl Display for ServiceError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::Network(e) => write!(f, "Network: {e}"),
+            Self::Http(e) => write!(f, "Http: {} {} {}", e.status, e.url, e.snippet),
+            Self::Parse(e) => write!(f, "Parse: {e}"),
+            Self::Decode(e) => write!(f, "Decode: {e}"),
+            Self::Cache(e) => write!(f, "Cache: {e}"),
+        }
+    }
+}
+
+impl std::error::Error for ServiceError {}
@@ -0,0 +1,77 @@
+use std::{future::Future, sync::Mutex, time::Duration};
+
+use lazy_static::lazy_static;
+use tokio::task::JoinHandle;
+use tracing::log;
+
+lazy_static! {
+    static ref IN_FLIGHT: Mutex<Vec<JoinHandle<()>>> = Mutex::new(vec![]);
+}
+
+// Background work that writes to the Db, like refreshes and debounced publishes, is spawned here
+// so a shutdown can wait for it instead of dropping it half way
+pub struct ShutdownService;
+impl ShutdownService {
+    pub fn spawn<F: Future<Output = ()> + Send + 'static>(f: F) {
+        let handle = tokio::spawn(f);
+        let mut in_flight = IN_FLIGHT.lock().unwrap_or_else(|e| e.into_inner());
+        in_flight.retain(|e| !e.is_finished());
+        in_flight.push(handle);
+    }
+
+    // Waits at most timeout for the spawned work, including work spawned meanwhile. False if some didn't finish
+    pub async fn shutdown(timeout: Duration) -> bool {
+        let drain = async {
+            loop {
+                let handles = std::mem::take(&mut *IN_FLIGHT.lock().unwrap_or_else(|e| e.into_inner()));
+                if handles.is_empty() {
+                    break;
+                }
+                log::info!("[SHUTDOWN] Waiting for {} in flight", handles.len());
+                futures::future::join_all(handles).await;
+            }
+        };
+        let drained = tokio::time::timeout(timeout, drain).await.is_ok();
+        if !drained {
+            log::error!("[SHUTDOWN] Timed out after {:?}, pending writes are lost", timeout);
+        }
+        drained
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use tempdir::TempDir;
+
+    use crate::db::Db;
+
+    use super::ShutdownService;
+
+    #[tokio::test]
+    async fn shutdown_persists_pending_writes() {
+        std::env::set_var("DB_PATH", TempDir::new("test").expect("dir to be created").path().to_str().unwrap());
+        for i in 0..5u64 {
+            ShutdownService::spawn(async move {
+                tokio::time::sleep(Duration::from_millis(20 * i)).await;
+                let db: Db<String, u64> = Db::new("test_shutdown");
+                _ = db.write(&format!("key_{i}"), &i);
+                // work spawned while draining is waited for too
+                if i == 4 {
+                    ShutdownService::spawn(async { _ = Db::<String, u64>::new("test_shutdown").write(&"key_late".to_string(), &5); });
+                }
+            });
+        }
+        assert!(ShutdownService::shutdown(Duration::from_secs(5)).await);
+
+        let db: Db<String, u64> = Db::new("test_shutdown");
+        for i in 0..5u64 {
+            assert_eq!(db.read(&format!("key_{i}")), Some(i));
+        }
+        assert_eq!(db.read(&"key_late".to_string()), Some(5));
+
+        ShutdownService::spawn(tokio::time::sleep(Duration::from_secs(60)));
+        assert!(!ShutdownService::shutdown(Duration::from_millis(10)).await);
+    }
+}
@@ -0,0 +1,67 @@
+use std::{collections::HashMap, sync::Mutex, future::Future};
+
+use futures::{future::{BoxFuture, Shared}, FutureExt};
+
+// Concurrent calls for the same key share one in-flight future and all get its result
+pub struct SingleFlight<T: Clone> {
+    in_flight: Mutex<HashMap<String, Shared<BoxFuture<'static, T>>>>,
+}
+
+impl<T: Clone + Send + Sync + 'static> SingleFlight<T> {
+    pub fn new() -> SingleFlight<T> {
+        SingleFlight { in_flight: Mutex::new(HashMap::new()) }
+    }
+
+    pub async fn run<F: Future<Output = T> + Send + 'static>(&self, key: &str, f: impl FnOnce() -> F) -> T {
+        let future = {
+            let mut in_flight = self.in_flight.lock().unwrap_or_else(|e| e.into_inner());
+            match in_flight.get(key) {
+                Some(e) => e.clone(),
+                None => {
+                    let shared = f().boxed().shared();
+                    in_flight.insert(key.to_string(), shared.clone());
+                    shared
+                }
+            }
+        };
+        let result = future.clone().await;
+
+        let mut in_flight = self.in_flight.lock().unwrap_or_else(|e| e.into_inner());
+        if in_flight.get(key).map(|e| e.ptr_eq(&future)).unwrap_or(false) {
+            in_flight.remove(key);
+        }
+        result
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{sync::{Arc, atomic::{AtomicUsize, Ordering}}, time::Duration};
+
+    use super::SingleFlight;
+
+    #[tokio::test]
+    async fn concurrent_calls_share_one_fetch() {
+        let single_flight = SingleFlight::<i32>::new();
+        let nr_fetches = Arc::new(AtomicUsize::new(0));
+        let fetch = || {
+            let nr_fetches = nr_fetches.clone();
+            async move {
+                nr_fetches.fetch_add(1, Ordering::SeqCst);
+                tokio::time::sleep(Duration::from_millis(50)).await;
+                42
+            }
+        };
+
+        let (a, b, c) = futures::join!(
+            single_flight.run("game", fetch),
+            single_flight.run("game", fetch),
+            single_flight.run("game", fetch),
+        );
+        assert_eq!((a, b, c), (42, 42, 42));
+        assert_eq!(nr_fetches.load(Ordering::SeqCst), 1);
+
+        single_flight.run("game", fetch).await;
+        assert_eq!(nr_fetches.load(Ordering::SeqCst), 2);
+    }
+}
@@ -0,0 +1,132 @@
+use std::{io::Write, time::Duration};
+
+use axum::{body::{Bytes, StreamBody}, http::{header, HeaderMap}, response::{IntoResponse, Response}};
+use flate2::{Compression, write::GzEncoder};
+use futures::{Stream, StreamExt};
+use serde::Serialize;
+
+// Same interval as axum's KeepAlive
+const KEEP_ALIVE: Duration = Duration::from_secs(15);
+
+pub fn accepts_gzip(headers: &HeaderMap) -> bool {
+    headers.get(header::ACCEPT_ENCODING)
+        .and_then(|e| e.to_str().ok())
+        .is_some_and(|e| e.split(',').any(|e| e.split(';').next().unwrap_or_default().trim() == "gzip"))
+}
+
+// One gzip stream for the whole connection. The compression layer would hold events back until
+// its buffer fills, here each frame is sync flushed, so it decodes as soon as it's received
+struct FrameEncoder(GzEncoder<Vec<u8>>);
+impl FrameEncoder {
+    fn new() -> FrameEncoder {
+        FrameEncoder(GzEncoder::new(vec![], Compression::default()))
+    }
+
+    fn encode(&mut self, frame: &str) -> std::io::Result<Bytes> {
+        self.0.write_all(frame.as_bytes())?;
+        self.0.flush()?;
+        Ok(Bytes::from(std::mem::take(self.0.get_mut())))
+    }
+
+    fn finish(mut self) -> std::io::Result<Bytes> {
+        self.0.try_finish()?;
+        Ok(Bytes::from(std::mem::take(self.0.get_mut())))
+    }
+}
+
+fn get_frame<T: Serialize>(item: &T) -> String {
+    // same framing as axum Event::json_data
+    format!("data:{}\n\n", serde_json::to_string(item).unwrap_or_default())
+}
+
+pub fn gzip_frames<T, S>(items: S, keep_alive: Duration) -> impl Stream<Item = std::io::Result<Bytes>>
+where
+    T: Serialize,
+    S: Stream<Item = T> + Send + 'static,
+{
+    async_stream::stream! {
+        let mut encoder = FrameEncoder::new();
+        let mut items = Box::pin(items);
+        loop {
+            let frame = tokio::select! {
+                item = items.next() => match item {
+                    Some(item) => get_frame(&item),
+                    None => break,
+                },
+                _ = tokio::time::sleep(keep_alive) => ":\n\n".to_string(),
+            };
+            yield encoder.encode(&frame);
+        }
+        yield encoder.finish();
+    }
+}
+
+pub fn gzip_response<T, S>(items: S) -> Response
+where
+    T: Serialize + Send + 'static,
+    S: Stream<Item = T> + Send + 'static,
+{
+    let headers = [
+        (header::CONTENT_TYPE, "text/event-stream"),
+        (header::CONTENT_ENCODING, "gzip"),
+        (header::CACHE_CONTROL, "no-cache"),
+    ];
+    (headers, StreamBody::new(gzip_frames(items, KEEP_ALIVE))).into_response()
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{convert::Infallible, io::Write, time::Duration};
+
+    use axum::{body::HttpBody, http::HeaderMap, response::{IntoResponse, sse::{Event, Sse}}};
+    use flate2::write::GzDecoder;
+    use futures::StreamExt;
+
+    use crate::event_service::ApiScoreChange;
+
+    use super::{accepts_gzip, gzip_frames};
+
+    fn get_changes() -> Vec<ApiScoreChange> {
+        (0..3).map(|i| ApiScoreChange { game_uuid: format!("game_{i}"), home_team_result: i, away_team_result: 1 }).collect()
+    }
+
+    #[tokio::test]
+    async fn compressed_stream_decodes_to_same_events() {
+        let mut body = Sse::new(futures::stream::iter(get_changes())
+            .map(|e| Ok::<Event, Infallible>(Event::default().json_data(e).unwrap())))
+            .into_response()
+            .into_body();
+        let mut uncompressed = vec![];
+        while let Some(chunk) = body.data().await {
+            uncompressed.extend_from_slice(&chunk.unwrap());
+        }
+
+        let mut decoder = GzDecoder::new(vec![]);
+        let mut chunks = Box::pin(gzip_frames(futures::stream::iter(get_changes()), Duration::from_secs(60)));
+        let mut framed = 0;
+        while let Some(chunk) = chunks.next().await {
+            decoder.write_all(&chunk.unwrap()).unwrap();
+            decoder.flush().unwrap();
+            // every chunk ends on a whole frame
+            let decoded = String::from_utf8(decoder.get_ref().clone()).unwrap();
+            assert!(decoded.is_empty() || decoded.ends_with("\n\n"));
+            framed = decoded.matches("\n\n").count();
+        }
+        assert_eq!(framed, 3);
+        assert_eq!(String::from_utf8(decoder.finish().unwrap()).unwrap(), String::from_utf8(uncompressed).unwrap());
+    }
+
+    #[tokio::test]
+    async fn keep_alive_while_idle() {
+        let mut chunks = Box::pin(gzip_frames(futures::stream::pending::<ApiScoreChange>(), Duration::from_millis(10)));
+        let mut decoder = GzDecoder::new(vec![]);
+        decoder.write_all(&chunks.next().await.unwrap().unwrap()).unwrap();
+        decoder.flush().unwrap();
+        assert_eq!(decoder.get_ref().as_slice(), b":\n\n");
+
+        let mut headers = HeaderMap::new();
+        headers.insert("accept-encoding", "deflate, gzip;q=0.8".parse().unwrap());
+        assert!(accepts_gzip(&headers));
+        assert!(!accepts_gzip(&HeaderMap::new()));
+    }
+}
@@ -1,4 +1,4 @@
-use std::{fmt::Display, collections::HashMap, time::Instant};
+use std::{fmt::Display, collections::BTreeMap, time::Instant};
 
 use serde::{Deserialize, Serialize};
 use tracing::log;
@@ -98,7 +98,8 @@ impl StandingService {
     }
 
     fn get_standings(games: Vec<&ApiGame>) -> Vec<Standing> {
-        let mut team_map = HashMap::<TeamCode, Standing>::new();
+        // ordered, so teams tied on points and diff always come out the same
+        let mut team_map = BTreeMap::<TeamCode, Standing>::new();
         for g in games {
             {
                 team_map
@@ -203,6 +204,7 @@ mod tests {
             home_team_result: 3,
             away_team_result: 0,
             start_date_time: Utc::now(),
+            start_date_time_local: None,
             status: crate::game_report_service::GameStatus::Finished,
             shootout: false,
             overtime: false,
@@ -221,6 +223,7 @@ mod tests {
             home_team_result: 3,
             away_team_result: 0,
             start_date_time: Utc::now(),
+            start_date_time_local: None,
             status: crate::game_report_service::GameStatus::Coming,
             shootout: false,
             overtime: false,
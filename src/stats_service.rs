@@ -1,8 +1,9 @@
-use std::time::Duration;
+use std::{time::SystemTime, collections::BTreeMap};
 
 use serde::{Deserialize, Serialize};
+use tracing::Instrument;
 
-use crate::{models::League, rest_client::{self}, models2::external::game_stats::StatsRsp, db::Db};
+use crate::{models::{League, Season, get_period_label, GamePeriod}, api_season_service::ApiSeasonService, game_report_service::GameStatus, event_service::{EventService, ApiEventType}, rest_client::{self, ThrottlePolicy}, models2::external::game_stats::{StatsRsp, Statistics}, db::Db, service_error::ServiceError};
 
 #[derive(Serialize, Deserialize, Debug, Clone)]
 pub struct ApiGameTeamStats {
@@ -12,47 +13,152 @@ pub struct ApiGameTeamStats {
     pub fow: i32,
 }
 
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct ApiStatValue {
+    pub home: i32,
+    pub away: i32,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct ApiPeriodStats {
+    pub period: GamePeriod,
+    pub label: String,
+    pub home: ApiGameTeamStats,
+    pub away: ApiGameTeamStats,
+}
+
 #[derive(Serialize, Deserialize, Debug, Clone)]
 pub struct ApiGameStats {
     pub home: ApiGameTeamStats,
     pub away: ApiGameTeamStats,
+    #[serde(default)]
+    pub periods: Vec<ApiPeriodStats>,
 }
 
+// Summed over the team's finished games with cached stats
+#[derive(Serialize, Deserialize, Debug, Clone, Default, PartialEq)]
+pub struct TeamSeasonStats {
+    pub team_code: String,
+    pub gp: i32,
+    pub goals_for: i32,
+    pub goals_against: i32,
+    pub shots_for: i32,
+    pub shots_against: i32,
+    pub pim: i32,
+    // powerplay goals, over the opponents' penalties of 5 minutes or less
+    pub pp_goals: i32,
+    pub pp_opportunities: i32,
+    pub pp_pct: f32,
+}
 
 impl From<StatsRsp> for ApiGameStats {
     fn from(v: StatsRsp) -> Self {
         let stats = v.period_stats_breakdown.iter()
             .find(|e| e.period.value.to_str() == "Total")
-            .map(|e| e.statistics.clone());
-        
-        let goals = &stats.as_ref().and_then(|e| e.iter().find(|e| e.caption == "G"));
-        let sog = &stats.as_ref().and_then(|e| e.iter().find(|e| e.caption == "SOG"));
-        let fow = &stats.as_ref().and_then(|e| e.iter().find(|e| e.caption == "FOWon"));
-        let pim = &stats.as_ref().and_then(|e| e.iter().find(|e| e.caption == "PIM"));
-
-        let home = ApiGameTeamStats { 
-            g: goals.map(|e| e.homeTeamValue).unwrap_or_default(), 
-            sog: sog.map(|e| e.homeTeamValue).unwrap_or_default(),
-            pim: pim.map(|e| e.homeTeamValue).unwrap_or_default(),
-            fow: fow.map(|e| e.homeTeamValue).unwrap_or_default(),
-        };
+            .map(|e| e.statistics.clone())
+            .unwrap_or_default();
+        let (home, away) = get_team_stats(&stats);
 
-        let away = ApiGameTeamStats { 
-            g: goals.map(|e| e.awayTeamValue).unwrap_or_default(), 
-            sog: sog.map(|e| e.awayTeamValue).unwrap_or_default(),
-            pim: pim.map(|e| e.awayTeamValue).unwrap_or_default(),
-            fow: fow.map(|e| e.awayTeamValue).unwrap_or_default(),
-        };
-        ApiGameStats { home, away }
+        // every period, including overtimes past the third period
+        let periods = v.period_stats_breakdown.iter()
+            .filter(|e| e.period.value.to_str() != "Total")
+            .map(|e| {
+                let period = GamePeriod::from(e.period.value.to_num());
+                let (home, away) = get_team_stats(&e.statistics);
+                ApiPeriodStats { period, label: get_period_label(period), home, away }
+            })
+            .collect();
+        ApiGameStats { home, away, periods }
     }
 }
+
+fn get_team_stats(stats: &[Statistics]) -> (ApiGameTeamStats, ApiGameTeamStats) {
+    let goals = stats.iter().find(|e| e.caption == "G");
+    let sog = stats.iter().find(|e| e.caption == "SOG");
+    let fow = stats.iter().find(|e| e.caption == "FOWon");
+    let pim = stats.iter().find(|e| e.caption == "PIM");
+
+    let home = ApiGameTeamStats { 
+        g: goals.map(|e| e.homeTeamValue).unwrap_or_default(), 
+        sog: sog.map(|e| e.homeTeamValue).unwrap_or_default(),
+        pim: pim.map(|e| e.homeTeamValue).unwrap_or_default(),
+        fow: fow.map(|e| e.homeTeamValue).unwrap_or_default(),
+    };
+
+    let away = ApiGameTeamStats { 
+        g: goals.map(|e| e.awayTeamValue).unwrap_or_default(), 
+        sog: sog.map(|e| e.awayTeamValue).unwrap_or_default(),
+        pim: pim.map(|e| e.awayTeamValue).unwrap_or_default(),
+        fow: fow.map(|e| e.awayTeamValue).unwrap_or_default(),
+    };
+    (home, away)
+}
+
 pub struct StatsService;
 
 impl StatsService {
-    pub async fn update(league: &League, game_uuid: &str, throttle_s: Option<Duration>) -> Option<ApiGameStats> {
+    pub async fn update(league: &League, game_uuid: &str, throttle: ThrottlePolicy) -> Result<ApiGameStats, ServiceError> {
         let url = rest_client::get_stats_url(league, game_uuid);
-        let rsp: Option<StatsRsp> = rest_client::throttle_call(&url, throttle_s).await;
-        rsp.map(|e| e.into())
+        let rsp: Result<StatsRsp, ServiceError> = rest_client::throttle_call(&url, throttle)
+            .instrument(tracing::info_span!("fetch", service = "stats", game_uuid, league = ?league))
+            .await;
+        tracing::info_span!("map", service = "stats", game_uuid, league = ?league)
+            .in_scope(|| rsp.map(|e| e.into()))
+    }
+
+    pub fn read(league: &League, game_uuid: &str) -> Option<ApiGameStats> {
+        let url = rest_client::get_stats_url(league, game_uuid);
+        let db = Db::<String, StatsRsp>::new("rest");
+        db.read(&url).map(|e| e.into())
+    }
+
+    pub fn team_season(league: &League, season: &Season, team_code: &str) -> TeamSeasonStats {
+        let mut result = TeamSeasonStats { team_code: team_code.to_string(), ..Default::default() };
+        let games = ApiSeasonService::read(season).into_iter()
+            .filter(|e| e.league == *league && e.status == GameStatus::Finished)
+            .filter(|e| e.home_team_code == team_code || e.away_team_code == team_code);
+        for game in games {
+            let Some(stats) = StatsService::read(league, &game.game_uuid) else { continue };
+            let (own, other) = match game.home_team_code == team_code {
+                true => (stats.home, stats.away),
+                false => (stats.away, stats.home),
+            };
+            result.gp += 1;
+            result.goals_for += own.g;
+            result.goals_against += other.g;
+            result.shots_for += own.sog;
+            result.shots_against += other.sog;
+            result.pim += own.pim;
+            for e in EventService::read(&game.game_uuid) {
+                match e.info {
+                    ApiEventType::Goal(g) if g.team == team_code && g.team_advantage.starts_with("PP") => result.pp_goals += 1,
+                    ApiEventType::Penalty(p) if p.team != team_code && (1..=5).contains(&p.get_minutes()) => result.pp_opportunities += 1,
+                    _ => {},
+                }
+            }
+        }
+        if result.pp_opportunities > 0 {
+            result.pp_pct = 100.0 * result.pp_goals as f32 / result.pp_opportunities as f32;
+        }
+        result
+    }
+
+    // Every caption in the Total breakdown, not only the ones in ApiGameStats
+    pub fn all_captions(league: &League, game_uuid: &str) -> BTreeMap<String, ApiStatValue> {
+        let url = rest_client::get_stats_url(league, game_uuid);
+        let db = Db::<String, StatsRsp>::new("rest");
+        db.read(&url).map(|e| StatsService::get_all_captions(&e)).unwrap_or_default()
+    }
+
+    fn get_all_captions(rsp: &StatsRsp) -> BTreeMap<String, ApiStatValue> {
+        rsp.period_stats_breakdown.iter()
+            .find(|e| e.period.value.to_str() == "Total")
+            .map(|e| e.statistics.iter().fold(BTreeMap::new(), |mut acc, s| {
+                // first one wins on duplicate captions, same as ApiGameStats
+                acc.entry(s.caption.clone()).or_insert(ApiStatValue { home: s.homeTeamValue, away: s.awayTeamValue });
+                acc
+            }))
+            .unwrap_or_default()
     }
 
     pub fn is_stale(league: &League, game_uuid: &str) -> bool {
@@ -60,4 +166,204 @@ impl StatsService {
         let db = Db::<String, StatsRsp>::new("rest");
         db.is_stale(&url, None)
     }
+
+    pub fn last_updated(league: &League, game_uuid: &str) -> Option<SystemTime> {
+        let url = rest_client::get_stats_url(league, game_uuid);
+        Db::<String, StatsRsp>::new("rest").last_updated(&url).max(rest_client::last_fetched(&url))
+    }
+
+    pub fn mark_final(league: &League, game_uuid: &str) {
+        let url = rest_client::get_stats_url(league, game_uuid);
+        _ = Db::<String, StatsRsp>::new("rest").mark_final(&url);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use proptest::prelude::*;
+
+    use chrono::Utc;
+    use tempdir::TempDir;
+
+    use crate::{models2::external::{game_stats::{StatsRsp, PeriodStatsBreakdown, Period, Statistics}, event::PlayByPlay}, models::{StringOrNum, GamePeriod, Season, League, GameType}, api_season_service::ApiGame, game_report_service::GameStatus, db::Db, rest_client::{self, ThrottlePolicy}, event_service::EventService, mock_server::mock_server};
+
+    use super::{StatsService, ApiStatValue, ApiGameStats, TeamSeasonStats};
+
+    fn statistics() -> impl Strategy<Value = Statistics> {
+        (prop::sample::select(vec!["G", "SOG", "PIM", "FOWon", "Saves", ""]), any::<i32>(), any::<i32>())
+            .prop_map(|(caption, home, away)| Statistics { caption: caption.to_string(), homeTeamValue: home, awayTeamValue: away })
+    }
+
+    fn breakdown() -> impl Strategy<Value = PeriodStatsBreakdown> {
+        let value = prop_oneof![
+            Just(StringOrNum::String("Total".to_string())),
+            any::<i16>().prop_map(StringOrNum::Number),
+            prop::collection::vec("[a-zA-Z0-9]{0,6}", 0..3).prop_map(StringOrNum::Arr),
+        ];
+        (value, prop::collection::vec(statistics(), 0..8))
+            .prop_map(|(value, statistics)| PeriodStatsBreakdown { period: Period { label: "".to_string(), value }, statistics })
+    }
+
+    proptest! {
+        #[test]
+        fn stats_mapping_never_panics(breakdowns in prop::collection::vec(breakdown(), 0..6)) {
+            let rsp = StatsRsp { period_stats_breakdown: breakdowns };
+            let total = rsp.period_stats_breakdown.iter().find(|e| e.period.value.to_str() == "Total");
+            let first_goals = total.and_then(|e| e.statistics.iter().find(|e| e.caption == "G"));
+
+            let stats: ApiGameStats = rsp.clone().into();
+            prop_assert_eq!(stats.home.g, first_goals.map(|e| e.homeTeamValue).unwrap_or_default());
+            prop_assert_eq!(stats.away.g, first_goals.map(|e| e.awayTeamValue).unwrap_or_default());
+
+            let captions = StatsService::get_all_captions(&rsp);
+            prop_assert!(captions.len() <= total.map(|e| e.statistics.len()).unwrap_or_default());
+            prop_assert_eq!(captions.get("G").map(|e| e.home), first_goals.map(|e| e.homeTeamValue));
+        }
+    }
+
+    #[tokio::test]
+    async fn unchanged_refetch_is_as_of_now() {
+        std::env::set_var("DB_PATH", TempDir::new("test").expect("dir to be created").path().to_str().unwrap());
+        let game_uuid = "unchanged_refetch_is_as_of_now";
+        let body = std::fs::read_to_string(std::path::PathBuf::from(env!("CARGO_MANIFEST_DIR")).join("fixtures/game1/stats.json")).unwrap();
+        let base = mock_server(vec![body.clone(), body]).await;
+        rest_client::with_base_url(&base, async {
+            assert!(StatsService::update(&League::SHL, game_uuid, ThrottlePolicy::Force).await.is_ok());
+            let url = rest_client::get_stats_url(&League::SHL, game_uuid);
+            let written = Db::<String, StatsRsp>::new("rest").last_updated(&url).unwrap();
+            std::thread::sleep(std::time::Duration::from_millis(20));
+
+            assert!(StatsService::update(&League::SHL, game_uuid, ThrottlePolicy::Force).await.is_ok());
+            assert_eq!(Db::<String, StatsRsp>::new("rest").last_updated(&url), Some(written));
+            assert!(StatsService::last_updated(&League::SHL, game_uuid).unwrap() > written);
+        }).await;
+    }
+
+    #[test]
+    fn overtime_period_breakdown() {
+        let rsp: StatsRsp = serde_json::from_str(r#"{
+            "period_stats_breakdown": [
+                { "period": { "label": "Period 3", "value": 3 }, "statistics": [
+                    { "caption": "G", "homeTeamValue": 1, "awayTeamValue": 1 }
+                ] },
+                { "period": { "label": "Overtime", "value": 4 }, "statistics": [
+                    { "caption": "G", "homeTeamValue": 0, "awayTeamValue": 1 },
+                    { "caption": "SOG", "homeTeamValue": 3, "awayTeamValue": 4 }
+                ] },
+                { "period": { "label": "Total", "value": "Total" }, "statistics": [
+                    { "caption": "G", "homeTeamValue": 1, "awayTeamValue": 2 }
+                ] }
+            ]
+        }"#).unwrap();
+        let stats: ApiGameStats = rsp.into();
+        assert_eq!(stats.periods.len(), 2);
+        assert_eq!(stats.periods[1].period, GamePeriod::Overtime(1));
+        assert_eq!(stats.periods[1].label, "OT");
+        assert_eq!(stats.periods[1].away.g, 1);
+        assert_eq!(stats.periods[1].away.sog, 4);
+        assert_eq!(stats.away.g, 2);
+    }
+
+    #[test]
+    fn all_captions_from_total() {
+        let rsp: StatsRsp = serde_json::from_str(r#"{
+            "period_stats_breakdown": [
+                { "period": { "label": "Period 1", "value": 1 }, "statistics": [
+                    { "caption": "Saves", "homeTeamValue": 5, "awayTeamValue": 7 }
+                ] },
+                { "period": { "label": "Total", "value": "Total" }, "statistics": [
+                    { "caption": "G", "homeTeamValue": 2, "awayTeamValue": 1 },
+                    { "caption": "Saves", "homeTeamValue": 21, "awayTeamValue": 26 }
+                ] }
+            ]
+        }"#).unwrap();
+        let captions = StatsService::get_all_captions(&rsp);
+        assert_eq!(captions.len(), 2);
+        assert_eq!(captions.get("Saves"), Some(&ApiStatValue { home: 21, away: 26 }));
+    }
+
+    #[test]
+    fn captions_serialize_the_same() {
+        let rsp = |captions: &[&str]| StatsRsp {
+            period_stats_breakdown: serde_json::from_value(serde_json::json!([{
+                "period": { "label": "Total", "value": "Total" },
+                "statistics": captions.iter().map(|e| serde_json::json!({ "caption": e, "homeTeamValue": 1, "awayTeamValue": 2 })).collect::<Vec<_>>(),
+            }])).unwrap(),
+        };
+        let captions = ["G", "SOG", "Saves", "PIM", "FOW", "Hits"];
+        let mut reversed = captions;
+        reversed.reverse();
+        let first = serde_json::to_string(&StatsService::get_all_captions(&rsp(&captions))).unwrap();
+        let second = serde_json::to_string(&StatsService::get_all_captions(&rsp(&reversed))).unwrap();
+        assert_eq!(first.as_bytes(), second.as_bytes());
+        assert!(first.starts_with(r#"{"FOW":"#), "{first}");
+    }
+
+    #[test]
+    fn team_season_from_two_games() {
+        std::env::set_var("DB_PATH", TempDir::new("test").expect("dir to be created").path().to_str().unwrap());
+        let season = Season::Season2019;
+        let game = |game_uuid: &str, home: &str, away: &str, status: GameStatus| ApiGame {
+            game_uuid: game_uuid.to_string(),
+            home_team_code: home.to_string(),
+            away_team_code: away.to_string(),
+            home_team_result: 0,
+            away_team_result: 0,
+            start_date_time: Utc::now(),
+            start_date_time_local: None,
+            status,
+            shootout: false,
+            overtime: false,
+            played: true,
+            game_type: GameType::Season,
+            league: League::SHL,
+            season: season.clone(),
+            gametime: None,
+        };
+        let games = vec![
+            game("team_season_1", "TS1", "TS2", GameStatus::Finished),
+            game("team_season_2", "TS3", "TS1", GameStatus::Finished),
+            game("team_season_3", "TS1", "TS3", GameStatus::Coming),
+            game("team_season_4", "TS2", "TS3", GameStatus::Finished),
+        ];
+        _ = Db::<Season, Vec<ApiGame>>::new("v2_season_decorated").write(&season, &games);
+        let stats = |g: (i32, i32), sog: (i32, i32), pim: (i32, i32)| -> StatsRsp {
+            let stat = |caption: &str, (home, away): (i32, i32)| serde_json::json!({ "caption": caption, "homeTeamValue": home, "awayTeamValue": away });
+            serde_json::from_value(serde_json::json!({ "period_stats_breakdown": [{
+                "period": { "label": "Total", "value": "Total" },
+                "statistics": [stat("G", g), stat("SOG", sog), stat("PIM", pim)],
+            }] })).unwrap()
+        };
+        let rest_db = Db::<String, StatsRsp>::new("rest");
+        _ = rest_db.write(&rest_client::get_stats_url(&League::SHL, "team_season_1"), &stats((3, 1), (30, 20), (4, 6)));
+        _ = rest_db.write(&rest_client::get_stats_url(&League::SHL, "team_season_2"), &stats((2, 2), (25, 28), (8, 2)));
+        _ = rest_db.write(&rest_client::get_stats_url(&League::SHL, "team_season_3"), &stats((9, 9), (9, 9), (9, 9)));
+        let penalty: PlayByPlay = serde_json::from_value(serde_json::json!({
+            "eventId": 1, "revision": 1, "hash": "abc", "period": 1, "gametime": "10:00", "description": "1 Olle Olsson utvisas 2 min, Hooking",
+            "class": "Penalty", "team": "TS2"
+        })).unwrap();
+        let goal: PlayByPlay = serde_json::from_value(serde_json::json!({
+            "eventId": 2, "revision": 1, "hash": "abc", "period": 1, "gametime": "11:00", "description": "Mål",
+            "class": "Goal", "team": "TS1", "location": { "x": 1.0, "y": 2.0 },
+            "extra": { "scorerLong": "1 Olle Olsson", "teamAdvantage": "PP1", "homeAgainst": 0, "homeForward": 1, "assist": "" }
+        })).unwrap();
+        EventService::store_all_raw("team_season_1", vec![penalty.clone(), goal]);
+        // TS1's own penalty is no powerplay for them
+        let own_penalty = serde_json::to_string(&penalty).unwrap().replace("TS2", "TS1");
+        EventService::store_all_raw("team_season_2", vec![serde_json::from_str(&own_penalty).unwrap()]);
+
+        let result = StatsService::team_season(&League::SHL, &season, "TS1");
+        assert_eq!(result, TeamSeasonStats {
+            team_code: "TS1".to_string(),
+            gp: 2,
+            goals_for: 5,
+            goals_against: 3,
+            shots_for: 58,
+            shots_against: 45,
+            pim: 6,
+            pp_goals: 1,
+            pp_opportunities: 1,
+            pp_pct: 100.0,
+        });
+    }
 }
@@ -0,0 +1,73 @@
+use serde::{Serialize, Deserialize};
+
+use crate::{api_teams_service::ApiTeamsService, models::normalize_team_code, CONFIG};
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct TeamMeta {
+    pub code: String,
+    pub name: String,
+    pub shortname: String,
+    // primary first, as hex
+    pub colors: Vec<String>,
+    pub logo_url: Option<String>,
+}
+
+// (code, name, shortname, colors) of the SHL teams, for what the stored teams don't cover
+const BUNDLED_TEAMS: [(&str, &str, &str, [&str; 2]); 14] = [
+    ("BIF", "Brynäs IF", "Brynäs", ["#E2001A", "#FFCC00"]),
+    ("FBK", "Färjestad BK", "Färjestad", ["#006A4E", "#FFFFFF"]),
+    ("FHC", "Frölunda HC", "Frölunda", ["#00843D", "#FFFFFF"]),
+    ("HV71", "HV71", "HV71", ["#003E7E", "#FFD100"]),
+    ("IKO", "IK Oskarshamn", "Oskarshamn", ["#0033A0", "#FFFFFF"]),
+    ("LHC", "Linköping HC", "Linköping", ["#003A70", "#E4002B"]),
+    ("LHF", "Luleå Hockey", "Luleå", ["#E30613", "#000000"]),
+    ("LIF", "Leksands IF", "Leksand", ["#003DA5", "#FFFFFF"]),
+    ("MIK", "Malmö Redhawks", "Malmö", ["#C8102E", "#FFFFFF"]),
+    ("OHK", "Örebro HK", "Örebro", ["#D50032", "#000000"]),
+    ("RBK", "Rögle BK", "Rögle", ["#00843D", "#000000"]),
+    ("SAIK", "Skellefteå AIK", "Skellefteå", ["#FFD100", "#000000"]),
+    ("TIK", "Timrå IK", "Timrå", ["#D50032", "#FFFFFF"]),
+    ("VLH", "Växjö Lakers", "Växjö", ["#FF6A13", "#002855"]),
+];
+
+pub struct TeamService;
+impl TeamService {
+    // Upstream has no colors or logos, so these are bundled. Names come from the stored teams when there
+    pub fn get(code: &str) -> Option<TeamMeta> {
+        let code = normalize_team_code(code);
+        let team = ApiTeamsService::read().into_iter().find(|e| e.code == code);
+        let bundled = BUNDLED_TEAMS.iter().find(|e| e.0 == code);
+        let (name, shortname) = match (team, bundled) {
+            (Some(team), _) => (team.name, team.shortname),
+            (None, Some((_, name, shortname, _))) => (name.to_string(), shortname.to_string()),
+            (None, None) => return None,
+        };
+        Some(TeamMeta {
+            logo_url: CONFIG.team_logo_url.as_ref().map(|e| e.replace("{code}", &code)),
+            code,
+            name,
+            shortname,
+            colors: bundled.map(|e| e.3.iter().map(|e| e.to_string()).collect()).unwrap_or_default(),
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use tempdir::TempDir;
+
+    use super::TeamService;
+
+    #[test]
+    fn known_team_code() {
+        std::env::set_var("DB_PATH", TempDir::new("test").expect("dir to be created").path().to_str().unwrap());
+        let lhf = TeamService::get(" lhf ").unwrap();
+        assert_eq!((lhf.code.as_str(), lhf.shortname.as_str()), ("LHF", "Luleå"));
+        assert_eq!(lhf.colors, vec!["#E30613", "#000000"]);
+        assert_eq!(lhf.logo_url, None);
+        assert_eq!(TeamService::get("XYZ"), None);
+        assert_eq!(TeamService::get("vlh").map(|e| e.name), Some("Växjö Lakers".to_string()));
+        assert_eq!(TeamService::get("FBK").map(|e| e.name), Some("Färjestad BK".to_string()));
+        assert_eq!(TeamService::get("FHC").map(|e| e.name), Some("Frölunda HC".to_string()));
+    }
+}